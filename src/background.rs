@@ -0,0 +1,320 @@
+use bytemuck::Zeroable;
+use cgmath::{Matrix, Matrix4, SquareMatrix, Vector4};
+use wgpu::util::DeviceExt;
+
+use crate::environment::{Environment, PREFILTERED_MIP_LEVELS};
+use crate::orbit_camera::OrbitCamera;
+use crate::texture;
+
+/// The solid colors that can be cycled through at runtime, in linear RGB.
+pub const SOLID_COLORS: [[f32; 3]; 5] = [
+    [0.1, 0.2, 0.3],
+    [0.0, 0.0, 0.0],
+    [0.18, 0.18, 0.18],
+    [1.0, 1.0, 1.0],
+    [0.0, 0.35, 0.05],
+];
+
+/// What is drawn behind the model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackgroundMode {
+    /// The render pass is cleared to [BackgroundSettings::color].
+    Solid = 0,
+    /// A vertical gradient from [BackgroundSettings::gradient_bottom] to
+    /// [BackgroundSettings::gradient_top].
+    Gradient = 1,
+    /// The environment cubemap that lights the scene.
+    Skybox = 2,
+}
+
+impl BackgroundMode {
+    /// The next mode, wrapping around to [BackgroundMode::Solid].
+    pub fn next(self) -> Self {
+        match self {
+            Self::Solid => Self::Gradient,
+            Self::Gradient => Self::Skybox,
+            Self::Skybox => Self::Solid,
+        }
+    }
+}
+
+impl std::str::FromStr for BackgroundMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "solid" => Ok(Self::Solid),
+            "gradient" => Ok(Self::Gradient),
+            "skybox" => Ok(Self::Skybox),
+            _ => anyhow::bail!(
+                "unknown background {:?}, expected solid, gradient or skybox",
+                s
+            ),
+        }
+    }
+}
+
+/// Controls what the [Background] looks like. All colors are linear RGB.
+#[derive(Debug, Clone, Copy)]
+pub struct BackgroundSettings {
+    pub mode: BackgroundMode,
+    pub color: [f32; 3],
+    pub gradient_top: [f32; 3],
+    pub gradient_bottom: [f32; 3],
+    /// How blurry the skybox is, from 0 (sharp) to 1. Only the background is affected, the
+    /// lighting always uses the unblurred environment.
+    pub blur: f32,
+    /// Screenshots leave the background out and store the coverage in the alpha channel.
+    pub transparent_screenshots: bool,
+}
+
+impl Default for BackgroundSettings {
+    fn default() -> Self {
+        Self {
+            mode: BackgroundMode::Solid,
+            color: SOLID_COLORS[0],
+            gradient_top: [0.3, 0.45, 0.65],
+            gradient_bottom: [0.03, 0.03, 0.04],
+            blur: 0.0,
+            transparent_screenshots: false,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct BackgroundUniform {
+    inv_projection: [[f32; 4]; 4],
+    // Only the rotation of the view, the background is infinitely far away
+    inv_view: [[f32; 4]; 4],
+    top: [f32; 4],
+    bottom: [f32; 4],
+    mode: u32,
+    // The mip of the prefiltered environment to sample, 0 samples the sharp cubemap
+    lod: f32,
+    tan_half_fovy: f32,
+    intensity: f32,
+}
+
+/// Draws a gradient or the environment behind the model.
+///
+/// The background is a fullscreen triangle at the far plane that is drawn after the model, so
+/// the depth test skips every pixel the model already covers. The solid color is just the clear
+/// color of the render pass.
+pub struct Background {
+    pub settings: BackgroundSettings,
+    buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipeline: wgpu::RenderPipeline,
+    format: wgpu::TextureFormat,
+}
+
+impl Background {
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        environment: &Environment,
+    ) -> Self {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Background Buffer"),
+            contents: bytemuck::cast_slice(&[BackgroundUniform::zeroed()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let cube_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::Cube,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                cube_entry(1),
+                cube_entry(2),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("background_bind_group_layout"),
+        });
+        let bind_group = create_bind_group(device, &bind_group_layout, &buffer, environment);
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Background Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = create_pipeline(device, &pipeline_layout, format, sample_count);
+
+        Self {
+            settings: BackgroundSettings::default(),
+            buffer,
+            bind_group_layout,
+            bind_group,
+            pipeline_layout,
+            pipeline,
+            format,
+        }
+    }
+
+    /// Points the skybox at a new environment.
+    pub fn set_environment(&mut self, device: &wgpu::Device, environment: &Environment) {
+        self.bind_group =
+            create_bind_group(device, &self.bind_group_layout, &self.buffer, environment);
+    }
+
+    /// Recreates the pipeline for the MSAA sample count of the render pass.
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        self.pipeline = create_pipeline(device, &self.pipeline_layout, self.format, sample_count);
+    }
+
+    /// Uploads the settings and the camera orientation.
+    ///
+    /// Arguments:
+    ///
+    /// * `environment_intensity`: The skybox is scaled like the lighting it comes from.
+    pub fn update(&self, queue: &wgpu::Queue, camera: &OrbitCamera, environment_intensity: f32) {
+        let mut view = camera.build_view_matrix();
+        view.w = Vector4::unit_w();
+        let inv_projection = camera
+            .build_projection_matrix()
+            .invert()
+            .unwrap_or(Matrix4::identity());
+        let blur = self.settings.blur.clamp(0.0, 1.0);
+        let [top, bottom] = [self.settings.gradient_top, self.settings.gradient_bottom]
+            .map(|[r, g, b]| [r, g, b, 1.0]);
+
+        let uniform = BackgroundUniform {
+            inv_projection: inv_projection.into(),
+            // the inverse of a rotation is its transpose
+            inv_view: view.transpose().into(),
+            top,
+            bottom,
+            mode: self.settings.mode as u32,
+            lod: blur * (PREFILTERED_MIP_LEVELS - 1) as f32,
+            tan_half_fovy: (camera.fovy.0 / 2.0).tan(),
+            intensity: environment_intensity,
+        };
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    /// The color the render pass is cleared to, fully transparent for `transparent` captures.
+    pub fn clear_color(&self, transparent: bool) -> wgpu::Color {
+        if transparent {
+            return wgpu::Color::TRANSPARENT;
+        }
+        let [r, g, b] = self.settings.color;
+        wgpu::Color {
+            r: r as f64,
+            g: g as f64,
+            b: b as f64,
+            a: 1.0,
+        }
+    }
+
+    /// Fills the pixels the model left empty. Must come after the opaque geometry in the pass.
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if self.settings.mode == BackgroundMode::Solid {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    buffer: &wgpu::Buffer,
+    environment: &Environment,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(&environment.cubemap_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(&environment.prefiltered_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::Sampler(&environment.sampler),
+            },
+        ],
+        label: Some("background_bind_group"),
+    })
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+) -> wgpu::RenderPipeline {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Background Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("background.wgsl").into()),
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Background Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        // Drawn at the far plane, so only where the depth buffer still holds the cleared 1.0
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_STENCIL_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::LessEqual,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
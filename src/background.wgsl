@@ -0,0 +1,60 @@
+// Background behind the model, see background.rs
+
+const MODE_GRADIENT: u32 = 1u;
+const MODE_SKYBOX: u32 = 2u;
+
+struct Background {
+    inv_projection: mat4x4<f32>,
+    inv_view: mat4x4<f32>,
+    top: vec4<f32>,
+    bottom: vec4<f32>,
+    mode: u32,
+    lod: f32,
+    tan_half_fovy: f32,
+    intensity: f32,
+}
+@group(0) @binding(0)
+var<uniform> background: Background;
+@group(0) @binding(1)
+var environment: texture_cube<f32>;
+@group(0) @binding(2)
+var prefiltered: texture_cube<f32>;
+@group(0) @binding(3)
+var environment_sampler: sampler;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+}
+
+// One triangle that covers the whole screen, at the far plane
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    let ndc = uv * 2.0 - 1.0;
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(ndc, 1.0, 1.0);
+    out.ndc = ndc;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let view_point = background.inv_projection * vec4<f32>(in.ndc, 0.5, 1.0);
+    let view_dir = view_point.xyz / view_point.w;
+
+    // Sampled before branching, textureSample needs uniform control flow
+    let world_dir = normalize((background.inv_view * vec4<f32>(view_dir, 0.0)).xyz);
+    let sharp = textureSampleLevel(environment, environment_sampler, world_dir, 0.0).rgb;
+    let blurred = textureSampleLevel(prefiltered, environment_sampler, world_dir, background.lod).rgb;
+
+    if background.mode == MODE_SKYBOX {
+        let sky = select(blurred, sharp, background.lod == 0.0);
+        return vec4<f32>(sky * background.intensity, 1.0);
+    }
+
+    // The height in the untiled frame, so tiled screenshots get one gradient across all tiles
+    let y = view_dir.y / (-view_dir.z * background.tan_half_fovy);
+    let t = clamp(y * 0.5 + 0.5, 0.0, 1.0);
+    return vec4<f32>(mix(background.bottom.rgb, background.top.rgb, t), 1.0);
+}
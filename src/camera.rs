@@ -1,7 +1,6 @@
 use cgmath::*;
 
 pub use self::orbit_camera::OrbitCamera;
-pub use self::orbit_camera::OrbitCameraBounds;
 use crate::orbit_camera;
 
 /// A camera is used for rendering specific parts of the scene.
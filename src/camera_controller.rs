@@ -79,16 +79,14 @@ impl CameraController {
     }
 
     pub fn process_keyed_events(&mut self, event: &KeyEvent) {
-        match event {
-            KeyEvent {
-                physical_key: PhysicalKey::Code(KeyCode::ShiftLeft),
-                state,
-                ..
-            } => {
-                let is_pressed = *state == ElementState::Pressed;
-                self.is_pan = is_pressed;
-            }
-            _ => (),
+        if let KeyEvent {
+            physical_key: PhysicalKey::Code(KeyCode::ShiftLeft),
+            state,
+            ..
+        } = event
+        {
+            let is_pressed = *state == ElementState::Pressed;
+            self.is_pan = is_pressed;
         }
     }
 }
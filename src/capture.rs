@@ -0,0 +1,210 @@
+use std::path::PathBuf;
+
+use crate::offscreen::{self, OffscreenTarget};
+use crate::orbit_camera::ProjectionTile;
+use crate::renderer::Renderer;
+
+/// The number of tiles along each axis for high resolution screenshots: the fewest that make
+/// the stitched image larger than `max_texture_dimension_2d`, at least 2.
+pub fn high_res_tiles(renderer: &Renderer) -> u32 {
+    let max_size = renderer.device.limits().max_texture_dimension_2d;
+    let size = renderer.width.max(renderer.height).max(1);
+    (max_size / size + 1).max(2)
+}
+
+/// Renders the current view into an offscreen target and reads it back as RGBA pixels.
+///
+/// The frame has the size of the [Renderer]. With transparent screenshots enabled in the
+/// [Background](crate::background::Background) the alpha channel holds the model coverage.
+pub fn capture_frame(renderer: &mut Renderer) -> Vec<u8> {
+    let target = OffscreenTarget::new(
+        &renderer.device,
+        renderer.width,
+        renderer.height,
+        renderer.format,
+    );
+    renderer.update();
+    renderer.render_capture(&target.view);
+    let mut pixels = target.read_rgba(&renderer.device, &renderer.command_queue);
+    if renderer.background.settings.transparent_screenshots {
+        unpremultiply(&mut pixels);
+    }
+    pixels
+}
+
+/// Renders the current view as a `tiles` x `tiles` grid of off-center sub-frustums and
+/// stitches them into one image.
+///
+/// Every tile has the size of the [Renderer], so the result can be far larger than
+/// `max_texture_dimension_2d`. The tiles are rendered with a guard band around them that is
+/// cropped away, so the ambient occlusion, bloom and FXAA don't stop at the tile edges and leave
+/// seams.
+///
+/// Returns the width, height and RGBA pixels of the stitched image.
+pub fn capture_tiled(renderer: &mut Renderer, tiles: u32) -> (u32, u32, Vec<u8>) {
+    let (tile_width, tile_height) = (renderer.width, renderer.height);
+    let (width, height) = (tile_width * tiles, tile_height * tiles);
+    let mut pixels = vec![0; width as usize * height as usize * 4];
+
+    // Measure the exposure on the whole view, so every tile gets the same
+    let target = OffscreenTarget::new(&renderer.device, tile_width, tile_height, renderer.format);
+    renderer.update();
+    renderer.render_capture(&target.view);
+    renderer.post.exposure_locked = true;
+
+    // A guard band wider than the tile costs more than the seams it hides
+    let max_size = renderer.device.limits().max_texture_dimension_2d;
+    let guard = renderer
+        .screen_space_reach(tiles)
+        .min(tile_width.max(tile_height))
+        .min(max_size.saturating_sub(tile_width.max(tile_height)) / 2);
+    let (padded_width, padded_height) = (tile_width + 2 * guard, tile_height + 2 * guard);
+    renderer.resize(padded_width, padded_height);
+    // The pixels keep their size, the guard band only shows more of the view
+    renderer.camera.resize_projection(tile_width, tile_height);
+    let target = OffscreenTarget::new(
+        &renderer.device,
+        padded_width,
+        padded_height,
+        renderer.format,
+    );
+    // The occlusion of one tile says nothing about the next
+    let occlusion_culling = std::mem::take(&mut renderer.occlusion.settings.enabled);
+    for row in 0..tiles {
+        for column in 0..tiles {
+            renderer.camera.tile = Some(ProjectionTile {
+                column,
+                row,
+                columns: tiles,
+                rows: tiles,
+                overscan: [
+                    guard as f32 / tile_width as f32,
+                    guard as f32 / tile_height as f32,
+                ],
+            });
+            renderer.update();
+            renderer.render_capture(&target.view);
+            let mut tile = target.read_rgba(&renderer.device, &renderer.command_queue);
+            if renderer.background.settings.transparent_screenshots {
+                unpremultiply(&mut tile);
+            }
+
+            let row_bytes = tile_width as usize * 4;
+            let padded_rows = tile
+                .chunks(padded_width as usize * 4)
+                .skip(guard as usize)
+                .take(tile_height as usize);
+            for (y, padded_row) in padded_rows.enumerate() {
+                let tile_row = &padded_row[guard as usize * 4..][..row_bytes];
+                let start = ((row * tile_height) as usize + y) * width as usize * 4
+                    + (column * tile_width) as usize * 4;
+                pixels[start..start + row_bytes].copy_from_slice(tile_row);
+            }
+        }
+    }
+
+    renderer.camera.tile = None;
+    renderer.resize(tile_width, tile_height);
+    renderer.post.exposure_locked = false;
+    renderer.occlusion.settings.enabled = occlusion_culling;
+    renderer.update();
+
+    (width, height, pixels)
+}
+
+/// Edges resolved from MSAA samples over the transparent clear color are blended with black,
+/// PNG expects straight alpha.
+fn unpremultiply(pixels: &mut [u8]) {
+    for pixel in pixels.chunks_exact_mut(4) {
+        let alpha = pixel[3] as u32;
+        if alpha > 0 && alpha < 255 {
+            for c in &mut pixel[..3] {
+                *c = ((*c as u32 * 255 + alpha / 2) / alpha).min(255) as u8;
+            }
+        }
+    }
+}
+
+/// Captures the current view and saves it as a PNG with a timestamped name in the working directory.
+///
+/// Arguments:
+///
+/// * `tiles`: The number of tiles along each axis, 1 captures a single frame.
+pub fn save_screenshot(renderer: &mut Renderer, tiles: u32) -> anyhow::Result<PathBuf> {
+    let (width, height, pixels) = if tiles > 1 {
+        capture_tiled(renderer, tiles)
+    } else {
+        let pixels = capture_frame(renderer);
+        (renderer.width, renderer.height, pixels)
+    };
+
+    let path = PathBuf::from(format!(
+        "screenshot-{}-{}x{}.png",
+        timestamp(),
+        width,
+        height
+    ));
+    offscreen::save_png(&path, width, height, &pixels)?;
+    Ok(path)
+}
+
+/// The current time in UTC formatted as `YYYYMMDD-HHMMSS`, there is no time zone database to
+/// get the local time from.
+fn timestamp() -> String {
+    let seconds = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    format_timestamp(seconds)
+}
+
+/// Formats `seconds` since the Unix epoch as `YYYYMMDD-HHMMSS` in UTC.
+fn format_timestamp(seconds: u64) -> String {
+    let (days, time_of_day) = (seconds / 86400, seconds % 86400);
+
+    // Civil date from days since the epoch (http://howardhinnant.github.io/date_algorithms.html)
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        time_of_day / 3600,
+        time_of_day % 3600 / 60,
+        time_of_day % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamp_of_the_epoch() {
+        assert_eq!(format_timestamp(0), "19700101-000000");
+    }
+
+    #[test]
+    fn timestamp_of_a_leap_day() {
+        assert_eq!(format_timestamp(951782400), "20000229-000000");
+        assert_eq!(format_timestamp(951782400 + 86399), "20000229-235959");
+        assert_eq!(format_timestamp(951782400 + 86400), "20000301-000000");
+    }
+
+    #[test]
+    fn timestamp_at_the_end_of_a_year() {
+        // 2023-12-31 23:59:59 and one second later
+        assert_eq!(format_timestamp(1704067199), "20231231-235959");
+        assert_eq!(format_timestamp(1704067200), "20240101-000000");
+    }
+}
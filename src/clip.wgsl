@@ -0,0 +1,26 @@
+// The clip planes shared by the shaders that cut the model open, see clipping::shader_source.
+// MAX_CLIP_PLANES is defined in front of it from clipping::MAX_PLANES.
+
+// See clipping::ClipPlanesUniform
+struct ClipPlanes {
+    // The normal in xyz, everything with dot(normal, position) > w is cut away
+    planes: array<vec4<f32>, MAX_CLIP_PLANES>,
+    count: u32,
+}
+
+// Whether `plane` cuts `position` away
+fn plane_side(plane: vec4<f32>, position: vec3<f32>) -> bool {
+    return dot(plane.xyz, position) > plane.w;
+}
+
+// Whether one of `clip_planes` cuts `position` away
+fn clipped(clip_planes: ClipPlanes, position: vec3<f32>) -> bool {
+    // Arrays that are values can only be indexed by constants
+    var planes = clip_planes.planes;
+    for (var i = 0u; i < clip_planes.count; i++) {
+        if plane_side(planes[i], position) {
+            return true;
+        }
+    }
+    return false;
+}
@@ -0,0 +1,118 @@
+use cgmath::{InnerSpace, Vector3};
+
+use crate::clipping;
+use crate::light_editor::{cursor_ray, ray_sphere};
+use crate::renderer::Renderer;
+
+/// Selects clip planes by clicking on their handles and moves them along their normal by
+/// dragging.
+///
+/// The handle of a plane is the point on it closest to the center of the model, see
+/// [Renderer::draw_clip_planes].
+pub struct ClipPlaneEditor {
+    /// The last cursor position in physical pixels.
+    cursor: (f32, f32),
+    // The dragged plane and the distance from the point under the cursor to the plane
+    dragging: Option<(usize, f32)>,
+}
+
+impl ClipPlaneEditor {
+    pub fn new() -> Self {
+        Self {
+            cursor: (0.0, 0.0),
+            dragging: None,
+        }
+    }
+
+    pub fn is_dragging(&self) -> bool {
+        self.dragging.is_some()
+    }
+
+    /// Selects the plane whose handle is under the cursor and starts dragging it.
+    ///
+    /// Returns false if there is no handle under the cursor, so the camera can use the click.
+    pub fn press(&mut self, renderer: &mut Renderer) -> bool {
+        let (min, max) = renderer.obj_model.bounds();
+        let center = (min + max) / 2.0;
+        let radius = (max - min).magnitude() * clipping::HANDLE_SIZE;
+        let (origin, direction) = cursor_ray(
+            &renderer.camera,
+            renderer.width,
+            renderer.height,
+            self.cursor,
+        );
+        let hit = renderer
+            .clipping
+            .planes
+            .iter()
+            .enumerate()
+            .filter_map(|(index, plane)| {
+                ray_sphere(origin, direction, plane.project(center), radius)
+                    .map(|distance| (index, distance))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+
+        let Some((index, _)) = hit else {
+            return false;
+        };
+        renderer.clipping.selected = Some(index);
+        let plane = renderer.clipping.planes[index];
+        let offset = match self.drag_distance(renderer, plane.project(center), plane.normal) {
+            Some(distance) => plane.distance - distance,
+            None => 0.0,
+        };
+        self.dragging = Some((index, offset));
+        true
+    }
+
+    pub fn release(&mut self) {
+        self.dragging = None;
+    }
+
+    /// Remembers the cursor position and moves the dragged plane along.
+    ///
+    /// Returns true if a plane was moved.
+    pub fn cursor_moved(&mut self, renderer: &mut Renderer, x: f32, y: f32) -> bool {
+        self.cursor = (x, y);
+        let Some((index, offset)) = self.dragging else {
+            return false;
+        };
+        let Some(&plane) = renderer.clipping.planes.get(index) else {
+            self.dragging = None;
+            return false;
+        };
+        let (min, max) = renderer.obj_model.bounds();
+        let handle = plane.project((min + max) / 2.0);
+        match self.drag_distance(renderer, handle, plane.normal) {
+            Some(distance) => {
+                renderer.clipping.planes[index].distance = distance + offset;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The plane distance of the point on the line through `handle` along `normal` that is
+    /// closest to the cursor ray. None if the line points at the camera.
+    fn drag_distance(
+        &self,
+        renderer: &Renderer,
+        handle: Vector3<f32>,
+        normal: Vector3<f32>,
+    ) -> Option<f32> {
+        let (origin, direction) = cursor_ray(
+            &renderer.camera,
+            renderer.width,
+            renderer.height,
+            self.cursor,
+        );
+        let along = normal.dot(direction);
+        let denominator = 1.0 - along * along;
+        if denominator < 1e-4 {
+            return None;
+        }
+        let to_handle = handle - origin;
+        let s = (along * direction.dot(to_handle) - normal.dot(to_handle)) / denominator;
+        Some(normal.dot(handle) + s)
+    }
+}
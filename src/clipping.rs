@@ -0,0 +1,467 @@
+use bytemuck::Zeroable;
+use cgmath::{InnerSpace, Matrix4, SquareMatrix, Vector3};
+use wgpu::util::DeviceExt;
+
+use crate::model::{self, Vertex};
+use crate::orbit_camera::OrbitCamera;
+use crate::texture;
+
+/// The most clip planes at once, `MAX_CLIP_PLANES` in the shaders that clip.
+pub const MAX_PLANES: usize = 6;
+
+/// The radius of the handle that drags a plane, as a fraction of the size of the model.
+pub const HANDLE_SIZE: f32 = 0.03;
+
+/// A plane that cuts away everything on the side its normal points to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClipPlane {
+    /// Normalized.
+    pub normal: Vector3<f32>,
+    /// Points with `normal.dot(point) > distance` are cut away.
+    pub distance: f32,
+}
+
+impl ClipPlane {
+    /// The plane with `normal` that goes through `point`.
+    pub fn through(normal: Vector3<f32>, point: Vector3<f32>) -> Self {
+        let normal = normal.normalize();
+        Self {
+            normal,
+            distance: normal.dot(point),
+        }
+    }
+
+    /// The point on the plane that is closest to `point`.
+    pub fn project(&self, point: Vector3<f32>) -> Vector3<f32> {
+        point - self.normal * (self.normal.dot(point) - self.distance)
+    }
+
+    /// Turns the plane to `axis` around the point closest to `pivot`, or flips it if it already
+    /// faces that way.
+    pub fn align(&mut self, axis: Vector3<f32>, pivot: Vector3<f32>) {
+        let point = self.project(pivot);
+        let normal = if self.normal.dot(axis) > 0.999 {
+            -axis
+        } else {
+            axis
+        };
+        *self = Self::through(normal, point);
+    }
+
+    fn to_raw(self) -> [f32; 4] {
+        self.normal.extend(self.distance).into()
+    }
+}
+
+/// The axis of the `index`th plane that is added: +X, +Y, +Z, then the other way around.
+pub fn default_axis(index: usize) -> Vector3<f32> {
+    let axis = match index % 3 {
+        0 => Vector3::unit_x(),
+        1 => Vector3::unit_y(),
+        _ => Vector3::unit_z(),
+    };
+    if index % MAX_PLANES < 3 {
+        axis
+    } else {
+        -axis
+    }
+}
+
+/// Controls the cross-sections of the [Clipping].
+#[derive(Debug, Clone, Copy)]
+pub struct ClipSettings {
+    /// Fill the cross-sections, otherwise the inside of the model shows through the cut.
+    pub caps: bool,
+    /// Linear RGB.
+    pub cap_color: [f32; 3],
+}
+
+impl Default for ClipSettings {
+    fn default() -> Self {
+        Self {
+            caps: true,
+            cap_color: [0.8, 0.15, 0.1],
+        }
+    }
+}
+
+/// The clip planes as every shader that clips the model reads them, from the camera bind group.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ClipPlanesUniform {
+    /// The normal in xyz and the distance in w, see [ClipPlane].
+    pub planes: [[f32; 4]; MAX_PLANES],
+    pub count: u32,
+    pub _padding: [u32; 3],
+}
+
+impl ClipPlanesUniform {
+    /// Takes the first [MAX_PLANES] of `planes`.
+    pub fn new(planes: &[ClipPlane]) -> Self {
+        let mut uniform = Self::zeroed();
+        for (raw, plane) in uniform.planes.iter_mut().zip(planes) {
+            *raw = plane.to_raw();
+        }
+        uniform.count = planes.len().min(MAX_PLANES) as u32;
+        uniform
+    }
+}
+
+// One per plane, each at its own dynamic offset
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct CapUniform {
+    inv_view_proj: [[f32; 4]; 4],
+    color: [f32; 4],
+    plane: u32,
+    _padding: [u32; 3],
+}
+
+/// Up to [MAX_PLANES] planes that cut the model open, with the cross-sections filled in.
+///
+/// The main shader and the debug views discard the fragments beyond any plane. The
+/// cross-sections are capped with the stencil of the main depth buffer, one pass per plane: the
+/// model is drawn without depth test and every fragment on the far side of the plane, seen from
+/// the camera, flips the stencil. A point on the plane is inside the model if a ray from it
+/// crosses the surface an odd number of times, so where the stencil ends up set a fullscreen
+/// pass intersects the view ray with the plane and draws the cap there, tested against the depth
+/// of the main pass and cut by the other planes.
+pub struct Clipping {
+    pub planes: Vec<ClipPlane>,
+    /// The index into `planes` of the plane that is highlighted and edited.
+    pub selected: Option<usize>,
+    pub settings: ClipSettings,
+    buffer: wgpu::Buffer,
+    cap_buffer: wgpu::Buffer,
+    cap_bind_group: wgpu::BindGroup,
+    // The distance between the CapUniforms in their buffer
+    cap_stride: u32,
+    pipeline_layout: wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    // Flips the stencil for the model behind the plane
+    stencil_pipeline: wgpu::RenderPipeline,
+    cap_pipeline: wgpu::RenderPipeline,
+}
+
+impl Clipping {
+    /// Arguments:
+    ///
+    /// * `camera_bind_group_layout`: The layout of the bind group passed to [Clipping::render],
+    ///   which has to hold [Clipping::buffer] at binding 5.
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Clip Planes Buffer"),
+            contents: bytemuck::cast_slice(&[ClipPlanesUniform::zeroed()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let cap_stride = std::mem::size_of::<CapUniform>()
+            .max(device.limits().min_uniform_buffer_offset_alignment as usize);
+        let cap_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Cap Buffer"),
+            size: (cap_stride * MAX_PLANES) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let cap_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: wgpu::BufferSize::new(
+                            std::mem::size_of::<CapUniform>() as u64
+                        ),
+                    },
+                    count: None,
+                }],
+                label: Some("cap_bind_group_layout"),
+            });
+        let cap_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &cap_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &cap_buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(std::mem::size_of::<CapUniform>() as u64),
+                }),
+            }],
+            label: Some("cap_bind_group"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Clipping Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout, &cap_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let (stencil_pipeline, cap_pipeline) =
+            create_pipelines(device, &pipeline_layout, format, sample_count);
+
+        Self {
+            planes: Vec::new(),
+            selected: None,
+            settings: ClipSettings::default(),
+            buffer,
+            cap_buffer,
+            cap_bind_group,
+            cap_stride: cap_stride as u32,
+            pipeline_layout,
+            format,
+            stencil_pipeline,
+            cap_pipeline,
+        }
+    }
+
+    /// Recreates the pipelines for the MSAA sample count of the render pass.
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        (self.stencil_pipeline, self.cap_pipeline) =
+            create_pipelines(device, &self.pipeline_layout, self.format, sample_count);
+    }
+
+    /// The [ClipPlanesUniform], for the camera bind group.
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    /// Adds a plane through `center`, along the next of the [default_axis]es, and selects it.
+    ///
+    /// Returns false if there are [MAX_PLANES] already.
+    pub fn add_plane(&mut self, center: Vector3<f32>) -> bool {
+        if self.planes.len() >= MAX_PLANES {
+            return false;
+        }
+        let axis = default_axis(self.planes.len());
+        self.planes.push(ClipPlane::through(axis, center));
+        self.selected = Some(self.planes.len() - 1);
+        true
+    }
+
+    /// Removes the selected plane and selects the one before it.
+    pub fn remove_selected(&mut self) {
+        let Some(index) = self.selected.filter(|&index| index < self.planes.len()) else {
+            return;
+        };
+        self.planes.remove(index);
+        self.selected = match self.planes.len() {
+            0 => None,
+            _ => Some(index.saturating_sub(1)),
+        };
+    }
+
+    /// Selects the plane after the selected one, wrapping around.
+    pub fn select_next(&mut self) {
+        self.selected = match self.planes.len() {
+            0 => None,
+            count => Some(self.selected.map_or(0, |index| (index + 1) % count)),
+        };
+    }
+
+    /// Uploads the planes and the view the caps are drawn for.
+    pub fn update(&self, queue: &wgpu::Queue, camera: &OrbitCamera) {
+        queue.write_buffer(
+            &self.buffer,
+            0,
+            bytemuck::cast_slice(&[ClipPlanesUniform::new(&self.planes)]),
+        );
+
+        let view_proj = camera.build_projection_matrix() * camera.build_view_matrix();
+        let inv_view_proj = view_proj.invert().unwrap_or(Matrix4::identity());
+        let [r, g, b] = self.settings.cap_color;
+        let mut caps = vec![0; self.cap_stride as usize * MAX_PLANES];
+        for plane in 0..MAX_PLANES {
+            let uniform = CapUniform {
+                inv_view_proj: inv_view_proj.into(),
+                color: [r, g, b, 1.0],
+                plane: plane as u32,
+                _padding: [0; 3],
+            };
+            let offset = plane * self.cap_stride as usize;
+            caps[offset..offset + std::mem::size_of::<CapUniform>()]
+                .copy_from_slice(bytemuck::bytes_of(&uniform));
+        }
+        queue.write_buffer(&self.cap_buffer, 0, &caps);
+    }
+
+    /// Fills the cross-sections of `model` with the cap color. Does nothing without planes or
+    /// with the caps turned off.
+    ///
+    /// Arguments:
+    ///
+    /// * `view`, `resolve_target`: The color attachment of the main pass, whose contents are
+    ///   kept.
+    /// * `depth_view`: The depth and stencil buffer of the main pass, see
+    ///   [texture::Texture::create_depth_stencil_texture]. The caps write their depth.
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        model: &model::Model,
+        view: &wgpu::TextureView,
+        resolve_target: Option<&wgpu::TextureView>,
+        depth_view: &wgpu::TextureView,
+        camera_bind_group: &wgpu::BindGroup,
+    ) {
+        if !self.settings.caps {
+            return;
+        }
+        for plane in 0..self.planes.len().min(MAX_PLANES) {
+            // A pass of its own for every plane, to start from a cleared stencil
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Cap Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: depth_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(0),
+                        store: wgpu::StoreOp::Discard,
+                    }),
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            render_pass.set_bind_group(0, camera_bind_group, &[]);
+            render_pass.set_bind_group(1, &self.cap_bind_group, &[plane as u32 * self.cap_stride]);
+            render_pass.set_pipeline(&self.stencil_pipeline);
+            for mesh in model.meshes.iter().filter(|mesh| !mesh.culled) {
+                render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                render_pass
+                    .set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                render_pass.draw_indexed(0..mesh.num_elements, 0, 0..1);
+            }
+            render_pass.set_pipeline(&self.cap_pipeline);
+            render_pass.set_stencil_reference(0);
+            render_pass.draw(0..3, 0..1);
+        }
+    }
+}
+
+/// The WGSL of a shader that clips, with the `ClipPlanes` struct and the `clipped` function of
+/// `clip.wgsl` in front of `source`.
+pub fn shader_source(source: &str) -> wgpu::ShaderSource<'static> {
+    wgpu::ShaderSource::Wgsl(
+        format!(
+            "const MAX_CLIP_PLANES: u32 = {}u;\n{}\n{}",
+            MAX_PLANES,
+            include_str!("clip.wgsl"),
+            source
+        )
+        .into(),
+    )
+}
+
+/// Creates the stencil and the cap pipeline.
+fn create_pipelines(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+) -> (wgpu::RenderPipeline, wgpu::RenderPipeline) {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Clipping Shader"),
+        source: shader_source(include_str!("clipping.wgsl")),
+    });
+    let multisample = wgpu::MultisampleState {
+        count: sample_count,
+        mask: !0,
+        alpha_to_coverage_enabled: false,
+    };
+    // Only the lowest bit counts, the parity of the surfaces behind the plane
+    let stencil = |compare, pass_op| {
+        let face = wgpu::StencilFaceState {
+            compare,
+            fail_op: wgpu::StencilOperation::Keep,
+            depth_fail_op: wgpu::StencilOperation::Keep,
+            pass_op,
+        };
+        wgpu::StencilState {
+            front: face,
+            back: face,
+            read_mask: 1,
+            write_mask: 1,
+        }
+    };
+
+    let stencil_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Clipping Stencil Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_stencil",
+            buffers: &[model::ModelVertex::desc()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_stencil",
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: None,
+                write_mask: wgpu::ColorWrites::empty(),
+            })],
+        }),
+        // Front and back faces both count
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_STENCIL_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::Always,
+            stencil: stencil(
+                wgpu::CompareFunction::Always,
+                wgpu::StencilOperation::Invert,
+            ),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample,
+        multiview: None,
+    });
+    let cap_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Cap Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_cap",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_cap",
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_STENCIL_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            // The reference is 0, so this passes where the lowest bit is set
+            stencil: stencil(
+                wgpu::CompareFunction::NotEqual,
+                wgpu::StencilOperation::Keep,
+            ),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample,
+        multiview: None,
+    });
+    (stencil_pipeline, cap_pipeline)
+}
@@ -0,0 +1,101 @@
+// The caps of the cross-sections, see clipping.rs
+
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+// See clip.wgsl
+@group(0) @binding(5)
+var<uniform> clip_planes: ClipPlanes;
+
+// See clipping::CapUniform
+struct Cap {
+    inv_view_proj: mat4x4<f32>,
+    color: vec4<f32>,
+    // The index of the plane into clip_planes
+    plane: u32,
+}
+@group(1) @binding(0)
+var<uniform> cap: Cap;
+
+// -- STENCIL -- //
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+}
+
+@vertex
+fn vs_stencil(model: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(model.position, 1.0);
+    out.world_position = model.position;
+    return out;
+}
+
+// Only the surfaces on the other side of the plane than the camera count, they are the ones
+// a ray from the plane away from the camera crosses. The other planes don't matter here.
+@fragment
+fn fs_stencil(in: VertexOutput) -> @location(0) vec4<f32> {
+    let plane = clip_planes.planes[cap.plane];
+    if plane_side(plane, in.world_position) == plane_side(plane, camera.view_pos.xyz) {
+        discard;
+    }
+    return vec4<f32>(0.0);
+}
+
+// -- CAP -- //
+
+struct FullscreenOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+}
+
+// One triangle that covers the whole screen
+@vertex
+fn vs_cap(@builtin(vertex_index) index: u32) -> FullscreenOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: FullscreenOutput;
+    out.ndc = uv * 2.0 - 1.0;
+    out.clip_position = vec4<f32>(out.ndc, 0.0, 1.0);
+    return out;
+}
+
+struct CapOutput {
+    @location(0) color: vec4<f32>,
+    @builtin(frag_depth) depth: f32,
+}
+
+// The point of the plane under the pixel, at its own depth
+@fragment
+fn fs_cap(in: FullscreenOutput) -> CapOutput {
+    // The plane in normalized device coordinates, where its depth is exact for every pixel
+    let plane = clip_planes.planes[cap.plane];
+    let ndc_plane = vec4<f32>(plane.xyz, -plane.w) * cap.inv_view_proj;
+    if abs(ndc_plane.z) < 1e-8 {
+        discard;
+    }
+    let depth = -(dot(ndc_plane.xy, in.ndc) + ndc_plane.w) / ndc_plane.z;
+    if depth < 0.0 || depth > 1.0 {
+        discard;
+    }
+    let world = cap.inv_view_proj * vec4<f32>(in.ndc, depth, 1.0);
+    let position = world.xyz / world.w;
+    for (var i = 0u; i < clip_planes.count; i++) {
+        if i != cap.plane && plane_side(clip_planes.planes[i], position) {
+            discard;
+        }
+    }
+
+    var out: CapOutput;
+    out.color = cap.color;
+    out.depth = depth;
+    return out;
+}
@@ -0,0 +1,174 @@
+use bytemuck::Zeroable;
+use cgmath::{Matrix4, SquareMatrix};
+use wgpu::util::DeviceExt;
+
+use crate::orbit_camera::OrbitCamera;
+
+/// The number of clusters across the screen. Must match `CLUSTERS_X` in the shaders.
+pub const CLUSTERS_X: u32 = 16;
+/// The number of clusters down the screen. Must match `CLUSTERS_Y` in the shaders.
+pub const CLUSTERS_Y: u32 = 9;
+/// The number of depth slices between the near and far plane. Must match `CLUSTERS_Z` in the
+/// shaders.
+pub const CLUSTERS_Z: u32 = 24;
+/// Lights beyond this many in one cluster are left out of it. Must match
+/// `MAX_LIGHTS_PER_CLUSTER` in the shaders.
+pub const MAX_LIGHTS_PER_CLUSTER: u32 = 128;
+
+const CLUSTER_COUNT: u32 = CLUSTERS_X * CLUSTERS_Y * CLUSTERS_Z;
+const WORKGROUP_SIZE: u32 = 64;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ClusterUniform {
+    inv_projection: [[f32; 4]; 4],
+    view: [[f32; 4]; 4],
+    screen_size: [f32; 2],
+    near: f32,
+    far: f32,
+}
+
+/// The light lists of a view space grid of clusters, so every fragment only evaluates the
+/// lights that can reach it.
+///
+/// The grid splits the view frustum of the [OrbitCamera] into tiles across the screen and
+/// exponentially growing slices in depth. A compute pass tests the bounds of every light with
+/// a range against every cluster before the main pass. Lights without a range and directional
+/// lights are in every cluster.
+pub struct Clusters {
+    buffer: wgpu::Buffer,
+    pub light_counts: wgpu::Buffer,
+    pub light_indices: wgpu::Buffer,
+    pipeline: wgpu::ComputePipeline,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Clusters {
+    /// Arguments:
+    ///
+    /// * `light_buffer`: The storage buffer with the lights, see [Clusters::set_light_buffer].
+    pub fn new(device: &wgpu::Device, light_buffer: &wgpu::Buffer) -> Self {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Cluster Buffer"),
+            contents: bytemuck::cast_slice(&[ClusterUniform::zeroed()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let light_counts = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Cluster Light Counts"),
+            size: (CLUSTER_COUNT * 4) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let light_indices = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Cluster Light Indices"),
+            size: (CLUSTER_COUNT * MAX_LIGHTS_PER_CLUSTER * 4) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Cluster Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("clusters.wgsl").into()),
+        });
+        // The layout is derived from the bindings the shader uses
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Cluster Pipeline"),
+            layout: None,
+            module: &shader,
+            entry_point: "bin_lights",
+        });
+
+        let bind_group = create_bind_group(
+            device,
+            &pipeline,
+            light_buffer,
+            &buffer,
+            &light_counts,
+            &light_indices,
+        );
+
+        Self {
+            buffer,
+            light_counts,
+            light_indices,
+            pipeline,
+            bind_group,
+        }
+    }
+
+    /// Must be called whenever the light storage buffer is recreated.
+    pub fn set_light_buffer(&mut self, device: &wgpu::Device, light_buffer: &wgpu::Buffer) {
+        self.bind_group = create_bind_group(
+            device,
+            &self.pipeline,
+            light_buffer,
+            &self.buffer,
+            &self.light_counts,
+            &self.light_indices,
+        );
+    }
+
+    /// Uploads the frustum of `camera` for a render target of `width` x `height` pixels.
+    pub fn update(&self, queue: &wgpu::Queue, camera: &OrbitCamera, width: u32, height: u32) {
+        let inv_projection = camera
+            .build_projection_matrix()
+            .invert()
+            .unwrap_or(Matrix4::identity());
+        let uniform = ClusterUniform {
+            inv_projection: inv_projection.into(),
+            view: camera.build_view_matrix().into(),
+            screen_size: [width as f32, height as f32],
+            near: camera.znear,
+            far: camera.zfar,
+        };
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    /// Rebuilds the light lists. Has to run before the pass that shades with them.
+    pub fn dispatch(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Cluster Pass"),
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.dispatch_workgroups(CLUSTER_COUNT.div_ceil(WORKGROUP_SIZE), 1, 1);
+    }
+
+    /// The uniform with the cluster grid, the shaders in the main pass need it for the lookup.
+    pub fn uniform_binding(&self) -> wgpu::BindingResource<'_> {
+        self.buffer.as_entire_binding()
+    }
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    pipeline: &wgpu::ComputePipeline,
+    light_buffer: &wgpu::Buffer,
+    buffer: &wgpu::Buffer,
+    light_counts: &wgpu::Buffer,
+    light_indices: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout: &pipeline.get_bind_group_layout(0),
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: light_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: light_counts.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: light_indices.as_entire_binding(),
+            },
+        ],
+        label: Some("cluster_bind_group"),
+    })
+}
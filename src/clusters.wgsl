@@ -0,0 +1,95 @@
+// Bins the lights into a view space cluster grid, see clusters.rs
+
+// Must match the constants in clusters.rs
+const CLUSTERS_X: u32 = 16u;
+const CLUSTERS_Y: u32 = 9u;
+const CLUSTERS_Z: u32 = 24u;
+const MAX_LIGHTS_PER_CLUSTER: u32 = 128u;
+
+const LIGHT_DIRECTIONAL: u32 = 2u;
+
+// See lights::LightRaw
+struct Light {
+    position: vec3<f32>,
+    kind: u32,
+    direction: vec3<f32>,
+    range: f32,
+    color: vec3<f32>,
+    intensity: f32,
+    inner_cone_cos: f32,
+    outer_cone_cos: f32,
+}
+
+// See clusters::ClusterUniform
+struct Clusters {
+    inv_projection: mat4x4<f32>,
+    view: mat4x4<f32>,
+    screen_size: vec2<f32>,
+    near: f32,
+    far: f32,
+}
+
+@group(0) @binding(0)
+var<storage, read> lights: array<Light>;
+@group(0) @binding(1)
+var<uniform> clusters: Clusters;
+@group(0) @binding(2)
+var<storage, read_write> cluster_light_counts: array<u32>;
+@group(0) @binding(3)
+var<storage, read_write> cluster_light_indices: array<u32>;
+
+// The view space point on the ray through `ndc` at distance `depth` in front of the camera
+fn view_point(ndc: vec2<f32>, depth: f32) -> vec3<f32> {
+    let point = clusters.inv_projection * vec4<f32>(ndc, 0.5, 1.0);
+    let ray = point.xyz / point.w;
+    return ray * (depth / -ray.z);
+}
+
+// Slices grow exponentially with the distance, so they stay roughly cube shaped
+fn slice_depth(slice: u32) -> f32 {
+    return clusters.near * pow(clusters.far / clusters.near, f32(slice) / f32(CLUSTERS_Z));
+}
+
+@compute @workgroup_size(64)
+fn bin_lights(@builtin(global_invocation_id) id: vec3<u32>) {
+    let cluster = id.x;
+    if cluster >= CLUSTERS_X * CLUSTERS_Y * CLUSTERS_Z {
+        return;
+    }
+    let x = cluster % CLUSTERS_X;
+    let y = cluster / CLUSTERS_X % CLUSTERS_Y;
+    let z = cluster / (CLUSTERS_X * CLUSTERS_Y);
+
+    // Rows count from the top of the screen like framebuffer coordinates
+    let ndc_min = vec2<f32>(f32(x) / f32(CLUSTERS_X) * 2.0 - 1.0, 1.0 - f32(y + 1u) / f32(CLUSTERS_Y) * 2.0);
+    let ndc_max = vec2<f32>(f32(x + 1u) / f32(CLUSTERS_X) * 2.0 - 1.0, 1.0 - f32(y) / f32(CLUSTERS_Y) * 2.0);
+    let near = slice_depth(z);
+    let far = slice_depth(z + 1u);
+
+    var aabb_min = vec3<f32>(1e30);
+    var aabb_max = vec3<f32>(-1e30);
+    for (var corner = 0u; corner < 8u; corner++) {
+        let ndc = select(ndc_min, ndc_max, vec2<bool>((corner & 1u) != 0u, (corner & 2u) != 0u));
+        let point = view_point(ndc, select(near, far, (corner & 4u) != 0u));
+        aabb_min = min(aabb_min, point);
+        aabb_max = max(aabb_max, point);
+    }
+
+    var count = 0u;
+    for (var i = 0u; i < arrayLength(&lights) && count < MAX_LIGHTS_PER_CLUSTER; i++) {
+        let light = lights[i];
+        // Directional lights and lights without a range reach every cluster
+        var reaches = light.kind == LIGHT_DIRECTIONAL || light.range <= 0.0;
+        if !reaches {
+            let center = (clusters.view * vec4<f32>(light.position, 1.0)).xyz;
+            let closest = clamp(center, aabb_min, aabb_max);
+            let offset = closest - center;
+            reaches = dot(offset, offset) <= light.range * light.range;
+        }
+        if reaches {
+            cluster_light_indices[cluster * MAX_LIGHTS_PER_CLUSTER + count] = i;
+            count++;
+        }
+    }
+    cluster_light_counts[cluster] = count;
+}
@@ -0,0 +1,117 @@
+use cgmath::{InnerSpace, Matrix4, Vector3, Vector4};
+
+/// The six planes around the volume a view projection maps into the clip space of wgpu.
+#[derive(Debug, Clone, Copy)]
+pub struct Frustum {
+    // The normal in xyz points inside, points with dot(xyz, p) + w < 0 are outside
+    planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes from the rows of `view_proj` (Gribb and Hartmann), for a depth range
+    /// of 0 to 1.
+    pub fn from_view_projection(view_proj: Matrix4<f32>) -> Self {
+        let row = |i: usize| {
+            Vector4::new(
+                view_proj.x[i],
+                view_proj.y[i],
+                view_proj.z[i],
+                view_proj.w[i],
+            )
+        };
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+        let planes = [w + x, w - x, w + y, w - y, z, w - z];
+        Self { planes }
+    }
+
+    /// Whether any part of the axis aligned box from `min` to `max` may be inside the frustum.
+    ///
+    /// Conservative: boxes near a corner of the frustum can pass without being inside.
+    pub fn intersects_aabb(&self, min: Vector3<f32>, max: Vector3<f32>) -> bool {
+        self.planes.iter().all(|plane| {
+            // The corner furthest along the normal
+            let corner = Vector3::new(
+                if plane.x >= 0.0 { max.x } else { min.x },
+                if plane.y >= 0.0 { max.y } else { min.y },
+                if plane.z >= 0.0 { max.z } else { min.z },
+            );
+            plane.truncate().dot(corner) + plane.w >= 0.0
+        })
+    }
+}
+
+/// How many meshes were drawn and how many were skipped in the last frame.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CullingStats {
+    pub drawn: usize,
+    /// Outside the view.
+    pub culled: usize,
+    /// Inside the view but hidden behind other meshes.
+    pub occluded: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{perspective, Deg, Point3};
+
+    use super::*;
+
+    /// Looking down -z from the origin, with the near plane at 1 and the far plane at 10.
+    fn frustum() -> Frustum {
+        let projection = perspective(Deg(90.0), 1.0, 1.0, 10.0);
+        let view = Matrix4::look_at_rh(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(0.0, 0.0, -1.0),
+            Vector3::unit_y(),
+        );
+        Frustum::from_view_projection(projection * view)
+    }
+
+    #[test]
+    fn box_inside_intersects() {
+        let center = Vector3::new(0.0, 0.0, -5.0);
+        let extent = Vector3::new(0.5, 0.5, 0.5);
+        assert!(frustum().intersects_aabb(center - extent, center + extent));
+    }
+
+    #[test]
+    fn box_outside_does_not_intersect() {
+        let frustum = frustum();
+        // Left of the view, beyond the far plane and in front of the near plane
+        let outside = [
+            (
+                Vector3::new(-20.0, -1.0, -6.0),
+                Vector3::new(-10.0, 1.0, -4.0),
+            ),
+            (
+                Vector3::new(-1.0, -1.0, -30.0),
+                Vector3::new(1.0, 1.0, -20.0),
+            ),
+            (Vector3::new(-0.1, -0.1, -0.9), Vector3::new(0.1, 0.1, -0.5)),
+        ];
+        for (min, max) in outside {
+            assert!(!frustum.intersects_aabb(min, max), "{:?}", (min, max));
+        }
+    }
+
+    #[test]
+    fn box_straddling_a_plane_intersects() {
+        let frustum = frustum();
+        // Across the right plane and across the far plane
+        assert!(
+            frustum.intersects_aabb(Vector3::new(4.0, -1.0, -6.0), Vector3::new(8.0, 1.0, -4.0))
+        );
+        assert!(frustum.intersects_aabb(
+            Vector3::new(-1.0, -1.0, -12.0),
+            Vector3::new(1.0, 1.0, -8.0)
+        ));
+    }
+
+    #[test]
+    fn box_behind_the_camera_does_not_intersect() {
+        let frustum = frustum();
+        assert!(
+            !frustum.intersects_aabb(Vector3::new(-1.0, -1.0, 4.0), Vector3::new(1.0, 1.0, 6.0))
+        );
+    }
+}
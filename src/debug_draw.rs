@@ -0,0 +1,432 @@
+use std::time::Duration;
+
+use cgmath::{InnerSpace, Matrix4, SquareMatrix, Vector3, Vector4};
+
+use crate::model::Vertex;
+use crate::texture;
+
+/// The number of line segments of a circle.
+const CIRCLE_SEGMENTS: usize = 32;
+/// The length of an arrow head as a fraction of the arrow.
+const ARROW_HEAD: f32 = 0.2;
+/// The buffer starts out with room for this many vertices and doubles when it runs out.
+const INITIAL_CAPACITY: usize = 1024;
+
+/// How a primitive of the [DebugDraw] looks and how long it stays.
+#[derive(Debug, Clone, Copy)]
+pub struct DebugStyle {
+    /// Linear RGB.
+    pub color: [f32; 3],
+    /// Whether the model covers the primitive. If not, it is drawn on top of everything.
+    pub depth_test: bool,
+    /// How long the primitive is drawn for. Zero draws it for a single frame.
+    pub lifetime: Duration,
+}
+
+impl Default for DebugStyle {
+    fn default() -> Self {
+        Self {
+            color: [1.0, 1.0, 1.0],
+            depth_test: true,
+            lifetime: Duration::ZERO,
+        }
+    }
+}
+
+impl DebugStyle {
+    /// The default style in `color`.
+    pub fn color(color: [f32; 3]) -> Self {
+        Self {
+            color,
+            ..Default::default()
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct DebugVertex {
+    position: [f32; 3],
+    color: [f32; 3],
+}
+
+impl Vertex for DebugVertex {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<DebugVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+            ],
+        }
+    }
+}
+
+struct Primitive {
+    // Pairs of vertices, one pair per line
+    vertices: Vec<DebugVertex>,
+    depth_test: bool,
+    remaining: Duration,
+}
+
+/// Immediate mode helper geometry: lines, boxes, spheres, arrows, crosses and frusta.
+///
+/// Primitives are added while the application updates and are kept for their
+/// [DebugStyle::lifetime], counted down by [DebugDraw::tick]. Every frame all of them are
+/// written into one vertex buffer, the depth tested ones before the others, and drawn as lines
+/// in a pass after the main pass.
+pub struct DebugDraw {
+    primitives: Vec<Primitive>,
+    buffer: wgpu::Buffer,
+    // The number of vertices the buffer has room for
+    capacity: usize,
+    // The number of depth tested vertices at the start of the buffer and the vertices on top
+    // that follow them
+    depth_tested: u32,
+    on_top: u32,
+    pipeline_layout: wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    depth_tested_pipeline: wgpu::RenderPipeline,
+    on_top_pipeline: wgpu::RenderPipeline,
+}
+
+impl DebugDraw {
+    /// Arguments:
+    ///
+    /// * `camera_bind_group_layout`: The layout of the bind group passed to [DebugDraw::render].
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Debug Draw Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let (depth_tested_pipeline, on_top_pipeline) =
+            create_pipelines(device, &pipeline_layout, format, sample_count);
+
+        Self {
+            primitives: Vec::new(),
+            buffer: create_buffer(device, INITIAL_CAPACITY),
+            capacity: INITIAL_CAPACITY,
+            depth_tested: 0,
+            on_top: 0,
+            pipeline_layout,
+            format,
+            depth_tested_pipeline,
+            on_top_pipeline,
+        }
+    }
+
+    /// Recreates the pipelines for the MSAA sample count of the render pass.
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        (self.depth_tested_pipeline, self.on_top_pipeline) =
+            create_pipelines(device, &self.pipeline_layout, self.format, sample_count);
+    }
+
+    /// Removes the primitives whose lifetime is over and counts down the others. Call it once
+    /// per frame, before adding the primitives of that frame.
+    ///
+    /// Arguments:
+    ///
+    /// * `dt`: The time since the last frame.
+    pub fn tick(&mut self, dt: Duration) {
+        self.primitives.retain_mut(|primitive| {
+            if primitive.remaining.is_zero() {
+                return false;
+            }
+            primitive.remaining = primitive.remaining.saturating_sub(dt);
+            true
+        });
+    }
+
+    /// Whether a primitive outlives the current frame, so there is a reason to draw the next one.
+    pub fn has_lasting_primitives(&self) -> bool {
+        self.primitives
+            .iter()
+            .any(|primitive| !primitive.remaining.is_zero())
+    }
+
+    pub fn line(&mut self, from: Vector3<f32>, to: Vector3<f32>, style: DebugStyle) {
+        self.lines(&[(from, to)], style);
+    }
+
+    /// The edges of the axis aligned box from `min` to `max`.
+    pub fn aabb(&mut self, min: Vector3<f32>, max: Vector3<f32>, style: DebugStyle) {
+        let corner = |index: usize| {
+            Vector3::new(
+                if index & 1 == 0 { min.x } else { max.x },
+                if index & 2 == 0 { min.y } else { max.y },
+                if index & 4 == 0 { min.z } else { max.z },
+            )
+        };
+        self.lines(&box_edges(corner), style);
+    }
+
+    /// Three circles around `center`, one in each axis plane.
+    pub fn sphere(&mut self, center: Vector3<f32>, radius: f32, style: DebugStyle) {
+        let mut lines = Vec::with_capacity(CIRCLE_SEGMENTS * 3);
+        for axis in 0..3 {
+            let point = |segment: usize| {
+                let angle = segment as f32 / CIRCLE_SEGMENTS as f32 * std::f32::consts::TAU;
+                let mut offset = Vector3::new(0.0, 0.0, 0.0);
+                offset[(axis + 1) % 3] = angle.cos() * radius;
+                offset[(axis + 2) % 3] = angle.sin() * radius;
+                center + offset
+            };
+            lines.extend((0..CIRCLE_SEGMENTS).map(|segment| (point(segment), point(segment + 1))));
+        }
+        self.lines(&lines, style);
+    }
+
+    /// A line from `from` to `to` with a head of four lines at `to`.
+    pub fn arrow(&mut self, from: Vector3<f32>, to: Vector3<f32>, style: DebugStyle) {
+        let shaft = to - from;
+        let length = shaft.magnitude();
+        if length <= f32::EPSILON {
+            return;
+        }
+        let direction = shaft / length;
+        let helper = if direction.y.abs() < 0.9 {
+            Vector3::unit_y()
+        } else {
+            Vector3::unit_x()
+        };
+        let side = direction.cross(helper).normalize() * length * ARROW_HEAD * 0.5;
+        let up = direction.cross(side);
+        let base = to - shaft * ARROW_HEAD;
+        self.lines(
+            &[
+                (from, to),
+                (to, base + side),
+                (to, base - side),
+                (to, base + up),
+                (to, base - up),
+            ],
+            style,
+        );
+    }
+
+    /// Three lines of length `size` crossing at `point`, to mark a point.
+    pub fn cross(&mut self, point: Vector3<f32>, size: f32, style: DebugStyle) {
+        let half = size * 0.5;
+        self.lines(
+            &[
+                (
+                    point - Vector3::unit_x() * half,
+                    point + Vector3::unit_x() * half,
+                ),
+                (
+                    point - Vector3::unit_y() * half,
+                    point + Vector3::unit_y() * half,
+                ),
+                (
+                    point - Vector3::unit_z() * half,
+                    point + Vector3::unit_z() * half,
+                ),
+            ],
+            style,
+        );
+    }
+
+    /// The edges of the volume that `view_proj` maps to the clip space cube.
+    pub fn frustum(&mut self, view_proj: Matrix4<f32>, style: DebugStyle) {
+        let Some(inverse) = view_proj.invert() else {
+            return;
+        };
+        let corner = |index: usize| {
+            let ndc = Vector4::new(
+                if index & 1 == 0 { -1.0 } else { 1.0 },
+                if index & 2 == 0 { -1.0 } else { 1.0 },
+                if index & 4 == 0 { 0.0 } else { 1.0 },
+                1.0,
+            );
+            let point = inverse * ndc;
+            point.truncate() / point.w
+        };
+        self.lines(&box_edges(corner), style);
+    }
+
+    fn lines(&mut self, lines: &[(Vector3<f32>, Vector3<f32>)], style: DebugStyle) {
+        let vertex = |position: Vector3<f32>| DebugVertex {
+            position: position.into(),
+            color: style.color,
+        };
+        self.primitives.push(Primitive {
+            vertices: lines
+                .iter()
+                .flat_map(|&(from, to)| [vertex(from), vertex(to)])
+                .collect(),
+            depth_test: style.depth_test,
+            remaining: style.lifetime,
+        });
+    }
+
+    /// Uploads the primitives, growing the buffer if they don't fit.
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let (depth_tested, on_top): (Vec<_>, Vec<_>) = self
+            .primitives
+            .iter()
+            .partition(|primitive| primitive.depth_test);
+        let vertices = depth_tested
+            .iter()
+            .chain(&on_top)
+            .flat_map(|primitive| &primitive.vertices)
+            .copied()
+            .collect::<Vec<_>>();
+        self.depth_tested = depth_tested
+            .iter()
+            .map(|primitive| primitive.vertices.len() as u32)
+            .sum();
+        self.on_top = vertices.len() as u32 - self.depth_tested;
+
+        if vertices.len() > self.capacity {
+            self.capacity = vertices.len().next_power_of_two();
+            self.buffer = create_buffer(device, self.capacity);
+        }
+        if !vertices.is_empty() {
+            queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&vertices));
+        }
+    }
+
+    /// Draws the primitives over the finished frame in a pass of their own.
+    ///
+    /// Arguments:
+    ///
+    /// * `view`, `resolve_target`: The color attachment of the main pass, whose contents are
+    ///   kept.
+    /// * `depth_view`: The depth buffer of the main pass.
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        resolve_target: Option<&wgpu::TextureView>,
+        depth_view: &wgpu::TextureView,
+        camera_bind_group: &wgpu::BindGroup,
+    ) {
+        if self.depth_tested + self.on_top == 0 {
+            return;
+        }
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Debug Draw Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.buffer.slice(..));
+        if self.depth_tested > 0 {
+            render_pass.set_pipeline(&self.depth_tested_pipeline);
+            render_pass.draw(0..self.depth_tested, 0..1);
+        }
+        if self.on_top > 0 {
+            render_pass.set_pipeline(&self.on_top_pipeline);
+            render_pass.draw(self.depth_tested..self.depth_tested + self.on_top, 0..1);
+        }
+    }
+}
+
+/// The twelve edges of a box, given its corners. Bit 0 of the corner index selects the side
+/// along the first axis, bit 1 and 2 the ones along the other axes.
+fn box_edges(corner: impl Fn(usize) -> Vector3<f32>) -> Vec<(Vector3<f32>, Vector3<f32>)> {
+    let mut edges = Vec::with_capacity(12);
+    for index in 0..8 {
+        for bit in [1, 2, 4] {
+            if index & bit == 0 {
+                edges.push((corner(index), corner(index | bit)));
+            }
+        }
+    }
+    edges
+}
+
+fn create_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Debug Draw Vertex Buffer"),
+        size: (capacity * std::mem::size_of::<DebugVertex>()) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+/// Creates the depth tested and the on top pipelines.
+fn create_pipelines(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+) -> (wgpu::RenderPipeline, wgpu::RenderPipeline) {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Debug Draw Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("debug_draw.wgsl").into()),
+    });
+    let pipeline = |depth_compare| {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Debug Draw Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[DebugVertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_STENCIL_FORMAT,
+                depth_write_enabled: false,
+                depth_compare,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        })
+    };
+    (
+        pipeline(wgpu::CompareFunction::LessEqual),
+        pipeline(wgpu::CompareFunction::Always),
+    )
+}
@@ -0,0 +1,444 @@
+use wgpu::util::DeviceExt;
+
+/// The face size of the environment cubemap the equirectangular image is converted to.
+pub const ENVIRONMENT_SIZE: u32 = 512;
+/// The face size of the diffuse irradiance cubemap, it holds very low frequencies only.
+pub const IRRADIANCE_SIZE: u32 = 32;
+/// The face size of the base level of the prefiltered specular cubemap.
+pub const PREFILTERED_SIZE: u32 = 128;
+/// Mip `i` of the prefiltered cubemap is convolved for roughness `i / (levels - 1)`.
+/// Must match `PREFILTERED_MIP_LEVELS` in shader.wgsl.
+pub const PREFILTERED_MIP_LEVELS: u32 = 5;
+/// The width and height of the split sum BRDF lookup table.
+pub const BRDF_LUT_SIZE: u32 = 256;
+
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+const WORKGROUP_SIZE: u32 = 8;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct Params {
+    roughness: f32,
+    face_size: u32,
+    source_size: f32,
+    sample_count: u32,
+}
+
+/// The precomputed maps for image based lighting.
+///
+/// Created from an equirectangular HDR image or from the six faces of a cubemap, all of the
+/// convolutions run in compute passes on the GPU.
+pub struct Environment {
+    /// The environment itself as a cubemap with a full mip chain.
+    pub cubemap_view: wgpu::TextureView,
+    /// Cosine convolved radiance, multiplied with the albedo it gives the diffuse light.
+    pub irradiance_view: wgpu::TextureView,
+    /// GGX convolved radiance with one mip per roughness, see [PREFILTERED_MIP_LEVELS].
+    pub prefiltered_view: wgpu::TextureView,
+    /// Scale and bias to F0 by n.v and roughness.
+    pub brdf_lut_view: wgpu::TextureView,
+    /// Trilinear and clamped, used for all of the maps above.
+    pub sampler: wgpu::Sampler,
+}
+
+impl Environment {
+    /// Builds the lighting maps from linear RGBA pixels of an equirectangular image.
+    pub fn from_equirect(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        width: u32,
+        height: u32,
+        rgba: &[f32],
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let equirect = device.create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label: Some("equirect_texture"),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: FORMAT,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+            bytemuck::cast_slice(
+                &rgba
+                    .iter()
+                    .map(|&c| half::f16::from_f32(c))
+                    .collect::<Vec<_>>(),
+            ),
+        );
+        let equirect_view = equirect.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let environment_mips = ENVIRONMENT_SIZE.ilog2() + 1;
+        let cubemap = create_cubemap(
+            device,
+            "environment_cubemap",
+            ENVIRONMENT_SIZE,
+            environment_mips,
+        );
+
+        let passes = Passes::new(device);
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Environment Encoder"),
+        });
+
+        // --CUBEMAP-- //
+        passes.dispatch(
+            &mut encoder,
+            "equirect_to_cube",
+            ENVIRONMENT_SIZE,
+            6,
+            Params {
+                roughness: 0.0,
+                face_size: ENVIRONMENT_SIZE,
+                source_size: ENVIRONMENT_SIZE as f32,
+                sample_count: 0,
+            },
+            &[
+                (0, wgpu::BindingResource::TextureView(&equirect_view)),
+                (
+                    1,
+                    wgpu::BindingResource::TextureView(&storage_view(&cubemap, 0)),
+                ),
+            ],
+        );
+        Self::precompute(device, queue, &passes, encoder, cubemap, ENVIRONMENT_SIZE)
+    }
+
+    /// Builds the lighting maps from the six faces of a cubemap, in the order +X, -X, +Y, -Y,
+    /// +Z, -Z. Every face holds `size` x `size` linear RGBA pixels.
+    pub fn from_cube_faces(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        size: u32,
+        faces: [&[f32]; 6],
+    ) -> Self {
+        let cubemap = create_cubemap(device, "environment_cubemap", size, size.ilog2() + 1);
+        for (layer, face) in faces.iter().enumerate() {
+            let texels = face
+                .iter()
+                .map(|&c| half::f16::from_f32(c))
+                .collect::<Vec<_>>();
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &cubemap,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: layer as u32,
+                    },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                bytemuck::cast_slice(&texels),
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    // 4 channels of 2 bytes
+                    bytes_per_row: Some(size * 8),
+                    rows_per_image: Some(size),
+                },
+                wgpu::Extent3d {
+                    width: size,
+                    height: size,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+
+        let passes = Passes::new(device);
+        let encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Environment Encoder"),
+        });
+        Self::precompute(device, queue, &passes, encoder, cubemap, size)
+    }
+
+    /// Fills the mips of `cubemap` from its base level and runs the convolutions.
+    fn precompute(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        passes: &Passes,
+        mut encoder: wgpu::CommandEncoder,
+        cubemap: wgpu::Texture,
+        size: u32,
+    ) -> Self {
+        let environment_mips = size.ilog2() + 1;
+        let cubemap_view = cube_view(&cubemap, 0, environment_mips);
+        let irradiance = create_cubemap(device, "irradiance_cubemap", IRRADIANCE_SIZE, 1);
+        let prefiltered = create_cubemap(
+            device,
+            "prefiltered_cubemap",
+            PREFILTERED_SIZE,
+            PREFILTERED_MIP_LEVELS,
+        );
+        let brdf_lut = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("brdf_lut"),
+            size: wgpu::Extent3d {
+                width: BRDF_LUT_SIZE,
+                height: BRDF_LUT_SIZE,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING,
+            view_formats: &[],
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("environment_sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        for level in 1..environment_mips {
+            let face_size = size >> level;
+            passes.dispatch(
+                &mut encoder,
+                "downsample",
+                face_size,
+                6,
+                Params {
+                    roughness: 0.0,
+                    face_size,
+                    source_size: size as f32,
+                    sample_count: 0,
+                },
+                &[
+                    (
+                        1,
+                        wgpu::BindingResource::TextureView(&storage_view(&cubemap, level)),
+                    ),
+                    // Only the levels written so far, the one being written can't be sampled
+                    (
+                        2,
+                        wgpu::BindingResource::TextureView(&cube_view(&cubemap, 0, level)),
+                    ),
+                    (3, wgpu::BindingResource::Sampler(&sampler)),
+                ],
+            );
+        }
+
+        // --IRRADIANCE-- //
+        passes.dispatch(
+            &mut encoder,
+            "irradiance",
+            IRRADIANCE_SIZE,
+            6,
+            Params {
+                roughness: 0.0,
+                face_size: IRRADIANCE_SIZE,
+                source_size: size as f32,
+                // 64 steps around and 16 towards the pole
+                sample_count: 32,
+            },
+            &[
+                (
+                    1,
+                    wgpu::BindingResource::TextureView(&storage_view(&irradiance, 0)),
+                ),
+                (2, wgpu::BindingResource::TextureView(&cubemap_view)),
+                (3, wgpu::BindingResource::Sampler(&sampler)),
+            ],
+        );
+
+        // --PREFILTERED SPECULAR-- //
+        for level in 0..PREFILTERED_MIP_LEVELS {
+            let face_size = PREFILTERED_SIZE >> level;
+            passes.dispatch(
+                &mut encoder,
+                "prefilter",
+                face_size,
+                6,
+                Params {
+                    roughness: level as f32 / (PREFILTERED_MIP_LEVELS - 1) as f32,
+                    face_size,
+                    source_size: size as f32,
+                    sample_count: 128,
+                },
+                &[
+                    (
+                        1,
+                        wgpu::BindingResource::TextureView(&storage_view(&prefiltered, level)),
+                    ),
+                    (2, wgpu::BindingResource::TextureView(&cubemap_view)),
+                    (3, wgpu::BindingResource::Sampler(&sampler)),
+                ],
+            );
+        }
+
+        // --BRDF LUT-- //
+        let brdf_lut_view = brdf_lut.create_view(&wgpu::TextureViewDescriptor::default());
+        passes.dispatch(
+            &mut encoder,
+            "brdf_lut",
+            BRDF_LUT_SIZE,
+            1,
+            Params {
+                roughness: 0.0,
+                face_size: BRDF_LUT_SIZE,
+                source_size: 0.0,
+                sample_count: 256,
+            },
+            &[(5, wgpu::BindingResource::TextureView(&brdf_lut_view))],
+        );
+
+        queue.submit(std::iter::once(encoder.finish()));
+
+        Self {
+            cubemap_view,
+            irradiance_view: cube_view(&irradiance, 0, 1),
+            prefiltered_view: cube_view(&prefiltered, 0, PREFILTERED_MIP_LEVELS),
+            brdf_lut_view,
+            sampler,
+        }
+    }
+
+    /// A soft sky gradient over a grey ground, used when no HDR image is given.
+    ///
+    /// It is about as bright as the constant ambient term the viewer used before.
+    pub fn procedural_sky(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let (width, height) = (64, 32);
+        let zenith = [0.20, 0.26, 0.34];
+        let horizon = [0.30, 0.30, 0.30];
+        let ground = [0.10, 0.09, 0.08];
+
+        let mut rgba = Vec::with_capacity((width * height * 4) as usize);
+        for y in 0..height {
+            // 1 at the zenith, 0 at the horizon and -1 straight down
+            let elevation = ((y as f32 + 0.5) / height as f32 * std::f32::consts::PI).cos();
+            let color: [f32; 3] = std::array::from_fn(|i| {
+                if elevation >= 0.0 {
+                    horizon[i] + (zenith[i] - horizon[i]) * elevation.sqrt()
+                } else {
+                    horizon[i] + (ground[i] - horizon[i]) * (-elevation).sqrt()
+                }
+            });
+            for _ in 0..width {
+                rgba.extend_from_slice(&[color[0], color[1], color[2], 1.0]);
+            }
+        }
+
+        Self::from_equirect(device, queue, width, height, &rgba)
+    }
+}
+
+/// Creates the compute pipelines for the entry points of environment.wgsl as they are needed.
+struct Passes<'a> {
+    device: &'a wgpu::Device,
+    shader: wgpu::ShaderModule,
+}
+
+impl<'a> Passes<'a> {
+    fn new(device: &'a wgpu::Device) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Environment Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("environment.wgsl").into()),
+        });
+        Self { device, shader }
+    }
+
+    /// Runs `entry_point` over `layers` faces of `size` x `size` texels.
+    fn dispatch(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        entry_point: &str,
+        size: u32,
+        layers: u32,
+        params: Params,
+        resources: &[(u32, wgpu::BindingResource)],
+    ) {
+        // The layout is derived from the bindings the entry point actually uses
+        let pipeline = self
+            .device
+            .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: None,
+                module: &self.shader,
+                entry_point,
+            });
+
+        let params = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Environment Params"),
+                contents: bytemuck::cast_slice(&[params]),
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+        let mut entries = resources
+            .iter()
+            .map(|(binding, resource)| wgpu::BindGroupEntry {
+                binding: *binding,
+                resource: resource.clone(),
+            })
+            .collect::<Vec<_>>();
+        entries.push(wgpu::BindGroupEntry {
+            binding: 4,
+            resource: params.as_entire_binding(),
+        });
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(entry_point),
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &entries,
+        });
+
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some(entry_point),
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        let groups = size.div_ceil(WORKGROUP_SIZE);
+        pass.dispatch_workgroups(groups, groups, layers);
+    }
+}
+
+fn create_cubemap(device: &wgpu::Device, label: &str, size: u32, mip_levels: u32) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 6,
+        },
+        mip_level_count: mip_levels,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: FORMAT,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING
+            | wgpu::TextureUsages::STORAGE_BINDING
+            | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    })
+}
+
+fn cube_view(texture: &wgpu::Texture, base_mip_level: u32, mip_levels: u32) -> wgpu::TextureView {
+    texture.create_view(&wgpu::TextureViewDescriptor {
+        dimension: Some(wgpu::TextureViewDimension::Cube),
+        base_mip_level,
+        mip_level_count: Some(mip_levels),
+        ..Default::default()
+    })
+}
+
+/// All six faces of a single mip level, as needed for writing with a storage texture.
+fn storage_view(texture: &wgpu::Texture, mip_level: u32) -> wgpu::TextureView {
+    texture.create_view(&wgpu::TextureViewDescriptor {
+        dimension: Some(wgpu::TextureViewDimension::D2Array),
+        base_mip_level: mip_level,
+        mip_level_count: Some(1),
+        ..Default::default()
+    })
+}
@@ -0,0 +1,215 @@
+// Compute passes that turn an equirectangular HDR image into the maps used for image based lighting
+
+struct Params {
+    // Roughness of the prefiltered mip that is being written
+    roughness: f32,
+    // Size of the faces that are being written
+    face_size: u32,
+    // Size of the base level of the environment cubemap
+    source_size: f32,
+    sample_count: u32,
+}
+
+@group(0) @binding(0)
+var equirect: texture_2d<f32>;
+@group(0) @binding(1)
+var cube_out: texture_storage_2d_array<rgba16float, write>;
+@group(0) @binding(2)
+var cube_in: texture_cube<f32>;
+@group(0) @binding(3)
+var cube_sampler: sampler;
+@group(0) @binding(4)
+var<uniform> params: Params;
+@group(0) @binding(5)
+var lut_out: texture_storage_2d<rgba16float, write>;
+
+const PI: f32 = 3.14159265;
+
+// The direction through texel `id` of cube face `id.z`, using the usual +X, -X, +Y, -Y, +Z, -Z order
+fn cube_direction(id: vec3<u32>, size: u32) -> vec3<f32> {
+    let uv = (vec2<f32>(id.xy) + 0.5) / f32(size) * 2.0 - 1.0;
+    switch id.z {
+        case 0u: { return normalize(vec3<f32>(1.0, -uv.y, -uv.x)); }
+        case 1u: { return normalize(vec3<f32>(-1.0, -uv.y, uv.x)); }
+        case 2u: { return normalize(vec3<f32>(uv.x, 1.0, uv.y)); }
+        case 3u: { return normalize(vec3<f32>(uv.x, -1.0, -uv.y)); }
+        case 4u: { return normalize(vec3<f32>(uv.x, -uv.y, 1.0)); }
+        default: { return normalize(vec3<f32>(-uv.x, -uv.y, -1.0)); }
+    }
+}
+
+// Bilinear lookup by hand, so the longitude wraps around while the poles clamp
+fn sample_equirect(direction: vec3<f32>) -> vec4<f32> {
+    let size = vec2<f32>(textureDimensions(equirect));
+    let uv = vec2<f32>(atan2(direction.z, direction.x) / (2.0 * PI) + 0.5, acos(clamp(direction.y, -1.0, 1.0)) / PI);
+    let texel = uv * size - 0.5;
+    let base = floor(texel);
+    let f = texel - base;
+    let max_texel = vec2<i32>(size) - 1;
+    var corners: array<vec4<f32>, 4>;
+    for (var i = 0; i < 4; i++) {
+        var coords = vec2<i32>(base) + vec2<i32>(i & 1, i >> 1u);
+        // Wrap around horizontally, clamp at the poles
+        coords.x = (coords.x + max_texel.x + 1) % (max_texel.x + 1);
+        coords.y = clamp(coords.y, 0, max_texel.y);
+        corners[i] = textureLoad(equirect, coords, 0);
+    }
+    return mix(mix(corners[0], corners[1], f.x), mix(corners[2], corners[3], f.x), f.y);
+}
+
+fn in_bounds(id: vec3<u32>) -> bool {
+    return id.x < params.face_size && id.y < params.face_size;
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn equirect_to_cube(@builtin(global_invocation_id) id: vec3<u32>) {
+    if !in_bounds(id) {
+        return;
+    }
+    let color = sample_equirect(cube_direction(id, params.face_size));
+    textureStore(cube_out, id.xy, id.z, vec4<f32>(color.rgb, 1.0));
+}
+
+// Averages 2x2 texels of the previous mip. The center of a texel lies on the corner of the
+// four texels above it, so one bilinear sample of the previous level does the job.
+@compute @workgroup_size(8, 8, 1)
+fn downsample(@builtin(global_invocation_id) id: vec3<u32>) {
+    if !in_bounds(id) {
+        return;
+    }
+    let previous_level = log2(params.source_size / f32(params.face_size)) - 1.0;
+    let color = textureSampleLevel(cube_in, cube_sampler, cube_direction(id, params.face_size), previous_level);
+    textureStore(cube_out, id.xy, id.z, color);
+}
+
+fn tangent_frame(normal: vec3<f32>) -> mat3x3<f32> {
+    var up = vec3<f32>(0.0, 1.0, 0.0);
+    if abs(normal.y) > 0.999 {
+        up = vec3<f32>(0.0, 0.0, 1.0);
+    }
+    let tangent = normalize(cross(up, normal));
+    let bitangent = cross(normal, tangent);
+    return mat3x3<f32>(tangent, bitangent, normal);
+}
+
+// Cosine weighted convolution of the environment, the result times the albedo is the diffuse light
+@compute @workgroup_size(8, 8, 1)
+fn irradiance(@builtin(global_invocation_id) id: vec3<u32>) {
+    if !in_bounds(id) {
+        return;
+    }
+    let frame = tangent_frame(cube_direction(id, params.face_size));
+    // A blurry mip is enough and keeps a coarse grid of samples from aliasing
+    let level = max(log2(params.source_size / 32.0), 0.0);
+
+    let steps_phi = params.sample_count * 2u;
+    let steps_theta = params.sample_count / 2u;
+    var sum = vec3<f32>(0.0);
+    for (var i = 0u; i < steps_phi; i++) {
+        let phi = (f32(i) + 0.5) / f32(steps_phi) * 2.0 * PI;
+        for (var j = 0u; j < steps_theta; j++) {
+            let theta = (f32(j) + 0.5) / f32(steps_theta) * 0.5 * PI;
+            let local = vec3<f32>(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            let radiance = textureSampleLevel(cube_in, cube_sampler, frame * local, level).rgb;
+            sum += radiance * cos(theta) * sin(theta);
+        }
+    }
+    let color = PI * sum / f32(steps_phi * steps_theta);
+    textureStore(cube_out, id.xy, id.z, vec4<f32>(color, 1.0));
+}
+
+fn hammersley(i: u32, count: u32) -> vec2<f32> {
+    return vec2<f32>(f32(i) / f32(count), f32(reverseBits(i)) * 2.3283064365386963e-10);
+}
+
+// A half vector around `normal` distributed by GGX
+fn importance_sample_ggx(xi: vec2<f32>, frame: mat3x3<f32>, alpha: f32) -> vec3<f32> {
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (alpha * alpha - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    return frame * vec3<f32>(sin_theta * cos(phi), sin_theta * sin(phi), cos_theta);
+}
+
+fn distribution_ggx(n_dot_h: f32, alpha: f32) -> f32 {
+    let alpha2 = alpha * alpha;
+    let d = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+    return alpha2 / (PI * d * d);
+}
+
+// The environment convolved with GGX for one roughness, assuming the view along the normal
+@compute @workgroup_size(8, 8, 1)
+fn prefilter(@builtin(global_invocation_id) id: vec3<u32>) {
+    if !in_bounds(id) {
+        return;
+    }
+    let normal = cube_direction(id, params.face_size);
+    if params.roughness == 0.0 {
+        // A mirror, only the resolution changes
+        let level = max(log2(params.source_size / f32(params.face_size)), 0.0);
+        let color = textureSampleLevel(cube_in, cube_sampler, normal, level);
+        textureStore(cube_out, id.xy, id.z, vec4<f32>(color.rgb, 1.0));
+        return;
+    }
+
+    let frame = tangent_frame(normal);
+    let alpha = params.roughness * params.roughness;
+    let texel_solid_angle = 4.0 * PI / (6.0 * params.source_size * params.source_size);
+    var sum = vec3<f32>(0.0);
+    var weight = 0.0;
+    for (var i = 0u; i < params.sample_count; i++) {
+        let half_dir = importance_sample_ggx(hammersley(i, params.sample_count), frame, alpha);
+        let n_dot_h = max(dot(normal, half_dir), 0.0);
+        let light_dir = normalize(2.0 * n_dot_h * half_dir - normal);
+        let n_dot_l = dot(normal, light_dir);
+        if n_dot_l > 0.0 {
+            // Filtered importance sampling: read from the mip whose texels cover the
+            // solid angle of the sample, which removes most of the fireflies
+            let pdf = distribution_ggx(n_dot_h, alpha) * 0.25 + 0.0001;
+            let sample_solid_angle = 1.0 / (f32(params.sample_count) * pdf);
+            let level = max(0.5 * log2(sample_solid_angle / texel_solid_angle) + 1.0, 0.0);
+            sum += textureSampleLevel(cube_in, cube_sampler, light_dir, level).rgb * n_dot_l;
+            weight += n_dot_l;
+        }
+    }
+    textureStore(cube_out, id.xy, id.z, vec4<f32>(sum / max(weight, 1e-4), 1.0));
+}
+
+// Height correlated Smith masking-shadowing, already divided by 4 n.l n.v
+fn visibility_smith_ggx(n_dot_v: f32, n_dot_l: f32, alpha: f32) -> f32 {
+    let alpha2 = alpha * alpha;
+    let ggx_v = n_dot_l * sqrt(n_dot_v * n_dot_v * (1.0 - alpha2) + alpha2);
+    let ggx_l = n_dot_v * sqrt(n_dot_l * n_dot_l * (1.0 - alpha2) + alpha2);
+    return 0.5 / max(ggx_v + ggx_l, 1e-5);
+}
+
+// Split sum BRDF integration, x is the scale and y the bias applied to F0. Indexed by
+// n.v horizontally and roughness vertically.
+@compute @workgroup_size(8, 8, 1)
+fn brdf_lut(@builtin(global_invocation_id) id: vec3<u32>) {
+    if !in_bounds(id) {
+        return;
+    }
+    let n_dot_v = (f32(id.x) + 0.5) / f32(params.face_size);
+    let roughness = (f32(id.y) + 0.5) / f32(params.face_size);
+    let alpha = roughness * roughness;
+    let view_dir = vec3<f32>(sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+    let frame = mat3x3<f32>(vec3<f32>(1.0, 0.0, 0.0), vec3<f32>(0.0, 1.0, 0.0), vec3<f32>(0.0, 0.0, 1.0));
+
+    var scale = 0.0;
+    var bias = 0.0;
+    for (var i = 0u; i < params.sample_count; i++) {
+        let half_dir = importance_sample_ggx(hammersley(i, params.sample_count), frame, alpha);
+        let light_dir = normalize(2.0 * dot(view_dir, half_dir) * half_dir - view_dir);
+        let n_dot_l = max(light_dir.z, 0.0);
+        let n_dot_h = max(half_dir.z, 0.0);
+        let v_dot_h = max(dot(view_dir, half_dir), 0.0);
+        if n_dot_l > 0.0 {
+            let visibility = 4.0 * visibility_smith_ggx(n_dot_v, n_dot_l, alpha) * n_dot_l * v_dot_h / max(n_dot_h, 1e-5);
+            let fresnel = pow(1.0 - v_dot_h, 5.0);
+            scale += (1.0 - fresnel) * visibility;
+            bias += fresnel * visibility;
+        }
+    }
+    let count = f32(params.sample_count);
+    textureStore(lut_out, id.xy, vec4<f32>(scale / count, bias / count, 0.0, 1.0));
+}
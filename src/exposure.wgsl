@@ -0,0 +1,76 @@
+// Measures the average luminance of the HDR frame and adapts the exposure to it, see post.rs
+
+// See post::PostUniform
+struct Post {
+    exposure: f32,
+    auto_exposure: u32,
+    adaptation: f32,
+    tonemapper: u32,
+    bloom: u32,
+    bloom_threshold: f32,
+    bloom_intensity: f32,
+    color_grading: u32,
+    lut_size: f32,
+}
+@group(0) @binding(0)
+var<uniform> post: Post;
+@group(0) @binding(1)
+var hdr: texture_2d<f32>;
+
+struct Exposure {
+    value: f32,
+}
+@group(0) @binding(2)
+var<storage, read_write> exposure: Exposure;
+
+// The frame is measured at GRID x GRID evenly spread pixels, SAMPLES x SAMPLES per invocation
+const GRID: u32 = 64u;
+const SAMPLES: u32 = 4u;
+const WORKGROUP_SIZE: u32 = 16u;
+// The average luminance is mapped to middle grey
+const KEY: f32 = 0.18;
+const MIN_EXPOSURE: f32 = 0.015625;
+const MAX_EXPOSURE: f32 = 64.0;
+
+var<workgroup> log_sums: array<f32, 256>;
+var<workgroup> counts: array<f32, 256>;
+
+@compute @workgroup_size(16, 16)
+fn measure_exposure(
+    @builtin(local_invocation_id) id: vec3<u32>,
+    @builtin(local_invocation_index) index: u32,
+) {
+    let size = vec2<f32>(textureDimensions(hdr));
+    var log_sum = 0.0;
+    var count = 0.0;
+    for (var y = 0u; y < SAMPLES; y++) {
+        for (var x = 0u; x < SAMPLES; x++) {
+            let cell = id.xy * SAMPLES + vec2<u32>(x, y);
+            let pixel = vec2<i32>((vec2<f32>(cell) + 0.5) / f32(GRID) * size);
+            let color = textureLoad(hdr, pixel, 0);
+            // Transparent captures have nothing behind the model
+            if color.a > 0.0 {
+                let luminance = dot(color.rgb, vec3<f32>(0.2126, 0.7152, 0.0722));
+                log_sum += log2(max(luminance, 1e-4));
+                count += 1.0;
+            }
+        }
+    }
+    log_sums[index] = log_sum;
+    counts[index] = count;
+    workgroupBarrier();
+
+    for (var stride = WORKGROUP_SIZE * WORKGROUP_SIZE / 2u; stride > 0u; stride /= 2u) {
+        if index < stride {
+            log_sums[index] += log_sums[index + stride];
+            counts[index] += counts[index + stride];
+        }
+        workgroupBarrier();
+    }
+
+    if index == 0u && counts[0] > 0.0 {
+        let average = exp2(log_sums[0] / counts[0]);
+        let target_exposure = clamp(KEY / average, MIN_EXPOSURE, MAX_EXPOSURE);
+        exposure.value = mix(exposure.value, target_exposure, post.adaptation);
+    }
+}
@@ -0,0 +1,160 @@
+use cgmath::{Matrix4, SquareMatrix};
+use wgpu::util::DeviceExt;
+
+use crate::orbit_camera::OrbitCamera;
+use crate::texture;
+
+/// How far the grid reaches, as a multiple of [OrbitCamera::distance].
+const FADE_DISTANCE: f32 = 30.0;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct GridUniform {
+    view_proj: [[f32; 4]; 4],
+    inv_view_proj: [[f32; 4]; 4],
+    eye: [f32; 4],
+    spacing: f32,
+    fine_fade: f32,
+    fade_distance: f32,
+    _padding: f32,
+}
+
+/// An infinite grid on the ground plane through the origin, with the X axis in red and the Z
+/// axis in blue.
+///
+/// A fullscreen triangle finds the point on the plane for every pixel and draws anti-aliased
+/// lines there, writing the depth of that point so the model covers the grid. The lines are
+/// spaced in powers of ten that follow [OrbitCamera::distance]: every tenth line stays while
+/// the others fade out as the camera moves away.
+pub struct Grid {
+    pub visible: bool,
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pipeline_layout: wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl Grid {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, sample_count: u32) -> Self {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Grid Buffer"),
+            contents: bytemuck::cast_slice(&[<GridUniform as bytemuck::Zeroable>::zeroed()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("grid_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("grid_bind_group"),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Grid Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = create_pipeline(device, &pipeline_layout, format, sample_count);
+
+        Self {
+            visible: false,
+            buffer,
+            bind_group,
+            pipeline_layout,
+            format,
+            pipeline,
+        }
+    }
+
+    /// Recreates the pipeline for the MSAA sample count of the render pass.
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        self.pipeline = create_pipeline(device, &self.pipeline_layout, self.format, sample_count);
+    }
+
+    /// Uploads the view of `camera` and picks the line spacing for its distance.
+    pub fn update(&self, queue: &wgpu::Queue, camera: &OrbitCamera) {
+        let view_proj = camera.build_projection_matrix() * camera.build_view_matrix();
+        let inv_view_proj = view_proj.invert().unwrap_or(Matrix4::identity());
+        // About ten to a hundred fine lines between the camera and its target
+        let level = camera.distance.max(f32::EPSILON).log10();
+        let uniform = GridUniform {
+            view_proj: view_proj.into(),
+            inv_view_proj: inv_view_proj.into(),
+            eye: camera.eye.extend(1.0).into(),
+            spacing: 10f32.powf(level.floor() - 1.0),
+            fine_fade: level - level.floor(),
+            fade_distance: (camera.distance * FADE_DISTANCE).min(camera.zfar),
+            _padding: 0.0,
+        };
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    /// Draws the grid if it is visible. Must come after the background, the grid is blended
+    /// over it.
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if !self.visible {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+) -> wgpu::RenderPipeline {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Grid Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("grid.wgsl").into()),
+    });
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Grid Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_STENCIL_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
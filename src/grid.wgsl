@@ -0,0 +1,108 @@
+// The ground grid on the y = 0 plane, see grid.rs
+
+// See grid::GridUniform
+struct Grid {
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    eye: vec4<f32>,
+    // The distance between the fine lines in world units, the coarse ones are ten times as far apart
+    spacing: f32,
+    // How far the fine lines have faded out, 1 right before the spacing grows tenfold. The coarse
+    // lines look the same as the fine ones then, so the switch is seamless
+    fine_fade: f32,
+    // The distance from the camera at which the grid is gone
+    fade_distance: f32,
+}
+@group(0) @binding(0)
+var<uniform> grid: Grid;
+
+const LINE_COLOR: vec3<f32> = vec3<f32>(0.5, 0.5, 0.5);
+const X_AXIS_COLOR: vec3<f32> = vec3<f32>(0.9, 0.15, 0.15);
+const Z_AXIS_COLOR: vec3<f32> = vec3<f32>(0.15, 0.3, 0.9);
+const LINE_ALPHA: f32 = 0.5;
+const AXIS_ALPHA: f32 = 0.9;
+// In pixels
+const AXIS_WIDTH: f32 = 1.5;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+}
+
+// One triangle that covers the whole screen, the fragments find their point on the plane
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    let ndc = uv * 2.0 - 1.0;
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(ndc, 0.0, 1.0);
+    out.ndc = ndc;
+    return out;
+}
+
+fn unproject(ndc: vec3<f32>) -> vec3<f32> {
+    let point = grid.inv_view_proj * vec4<f32>(ndc, 1.0);
+    return point.xyz / point.w;
+}
+
+// How much of the pixel at `coord` is covered by lines one unit apart, a pixel wide
+fn lines(coord: vec2<f32>) -> f32 {
+    let width = fwidth(coord);
+    let distance = abs(fract(coord - 0.5) - 0.5) / width;
+    return 1.0 - min(min(distance.x, distance.y), 1.0);
+}
+
+// Coverage of a line along `coord` = 0, a bit wider than the others so it stays unbroken at
+// grazing angles
+fn axis(coord: f32) -> f32 {
+    return clamp(AXIS_WIDTH - abs(coord) / fwidth(coord), 0.0, 1.0);
+}
+
+struct FragmentOutput {
+    @location(0) color: vec4<f32>,
+    // The depth of the point on the plane, so the model covers the grid
+    @builtin(frag_depth) depth: f32,
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> FragmentOutput {
+    let near = unproject(vec3<f32>(in.ndc, 0.0));
+    let far = unproject(vec3<f32>(in.ndc, 1.0));
+    let direction = far - near;
+    let t = -near.y / direction.y;
+    let position = near + direction * t;
+
+    // Derivatives first, they need uniform control flow
+    let coord = position.xz / grid.spacing;
+    let fine = lines(coord);
+    let coarse = lines(coord * 0.1);
+    let x_axis = axis(position.z);
+    let z_axis = axis(position.x);
+
+    // Above the horizon or beyond the far plane
+    if t <= 0.0 || t >= 1.0 {
+        discard;
+    }
+
+    var color = vec4<f32>(LINE_COLOR, max(fine * (1.0 - grid.fine_fade), coarse) * LINE_ALPHA);
+    if x_axis > 0.0 {
+        color = mix(color, vec4<f32>(X_AXIS_COLOR, AXIS_ALPHA), x_axis);
+    }
+    if z_axis > 0.0 {
+        color = mix(color, vec4<f32>(Z_AXIS_COLOR, AXIS_ALPHA), z_axis);
+    }
+
+    // Fade out with the distance and towards the horizon, where the lines get too dense
+    let distance = length(position - grid.eye.xyz);
+    let view_angle = abs(normalize(direction).y);
+    color.a *= (1.0 - smoothstep(0.25, 1.0, distance / grid.fade_distance)) * smoothstep(0.0, 0.15, view_angle);
+    if color.a <= 0.0 {
+        discard;
+    }
+
+    let clip = grid.view_proj * vec4<f32>(position, 1.0);
+    var out: FragmentOutput;
+    out.color = color;
+    out.depth = clip.z / clip.w;
+    return out;
+}
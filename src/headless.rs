@@ -0,0 +1,444 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, ensure, Context};
+use cgmath::{InnerSpace, Vector3};
+
+use crate::background::BackgroundSettings;
+use crate::clipping::{self, ClipPlane, ClipSettings};
+use crate::lights::LightKind;
+use crate::occlusion::OcclusionSettings;
+use crate::outline::OutlineSettings;
+use crate::post::PostSettings;
+use crate::renderer::{self, Renderer};
+use crate::shading::{ShadingModel, ToonSettings};
+use crate::shadow::ShadowSettings;
+use crate::ssao::SsaoSettings;
+use crate::transparency::TransparencySettings;
+use crate::turntable::{self, TurntableFormat, TurntableOptions};
+use crate::vectors::VectorSettings;
+use crate::visualization::VisualizationMode;
+use crate::wireframe::WireframeSettings;
+use crate::{capture, offscreen};
+
+/// The color format used for offscreen renders. It matches the sRGB swapchains the shaders expect.
+pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+/// Settings for rendering a single image without opening a window.
+#[derive(Debug, Clone)]
+pub struct HeadlessOptions {
+    pub model: String,
+    /// Where to write the result, defaults to `render.png` or the turntable formats' default.
+    pub output: Option<PathBuf>,
+    pub width: u32,
+    pub height: u32,
+    /// The distance of the [OrbitCamera](crate::orbit_camera::OrbitCamera) from its target.
+    pub distance: f32,
+    /// The pitch angle of the camera in radians.
+    pub pitch: f32,
+    /// The yaw angle of the camera in radians.
+    pub yaw: f32,
+    pub subdivision_level: u32,
+    /// Render the image as a `tiles` x `tiles` grid, so the output is `tiles` times the given size.
+    pub tiles: u32,
+    /// The requested MSAA sample count, clamped to what the adapter supports.
+    pub sample_count: u32,
+    pub shading_model: ShadingModel,
+    /// An image of a shaded sphere for [ShadingModel::Matcap].
+    pub matcap: Option<String>,
+    pub toon: ToonSettings,
+    /// An equirectangular `.hdr` or `.exr` file for image based lighting.
+    pub environment: Option<String>,
+    pub environment_intensity: f32,
+    /// A scene configuration with the lights, see [SceneConfig](crate::scene::SceneConfig).
+    pub scene: Option<String>,
+    /// Overrides the kind of the shadow casting light.
+    pub light_kind: Option<LightKind>,
+    pub shadow: ShadowSettings,
+    pub background: BackgroundSettings,
+    /// Render the number of lights per cluster instead of the shading.
+    pub cluster_heatmap: bool,
+    /// Draw a sphere at every light.
+    pub light_gizmos: bool,
+    /// Draw the ground grid.
+    pub grid: bool,
+    /// Skip the meshes outside the view, see [Renderer::frustum_culling].
+    pub frustum_culling: bool,
+    pub occlusion: OcclusionSettings,
+    /// Draw the bounds, lights and shadow frustum as lines, see [Renderer::draw_helpers].
+    pub helpers: bool,
+    pub ssao: SsaoSettings,
+    pub post: PostSettings,
+    /// A `.cube` file used as the color grading LUT, enables the color grading.
+    pub lut: Option<String>,
+    pub wireframe: WireframeSettings,
+    /// The indices of the meshes to outline.
+    pub selection: Vec<usize>,
+    pub outline: OutlineSettings,
+    /// Up to [clipping::MAX_PLANES] planes that cut the model open.
+    pub clip_planes: Vec<ClipPlane>,
+    pub clipping: ClipSettings,
+    pub transparency: TransparencySettings,
+    pub vectors: VectorSettings,
+    pub view: VisualizationMode,
+    /// If set a turntable with this many frames is exported instead of a single image.
+    pub turntable_frames: Option<u32>,
+    pub turntable_format: TurntableFormat,
+    pub frames_per_second: u32,
+    /// Use a software adapter such as lavapipe/llvmpipe or WARP.
+    pub force_fallback_adapter: bool,
+}
+
+impl HeadlessOptions {
+    /// Parses the command line arguments.
+    ///
+    /// Returns `None` if `--headless` was not given and the regular window should be opened.
+    pub fn from_args(args: &[String], default_model: &str) -> anyhow::Result<Option<Self>> {
+        if !args.iter().any(|a| a == "--headless") {
+            return Ok(None);
+        }
+
+        let mut options = HeadlessOptions {
+            model: default_model.to_string(),
+            output: None,
+            width: 1280,
+            height: 720,
+            distance: 2.0,
+            pitch: 0.0,
+            yaw: 0.0,
+            subdivision_level: 0,
+            tiles: 1,
+            frustum_culling: true,
+            occlusion: OcclusionSettings::default(),
+            sample_count: renderer::DEFAULT_SAMPLE_COUNT,
+            shading_model: ShadingModel::Pbr,
+            matcap: None,
+            toon: ToonSettings::default(),
+            environment: None,
+            environment_intensity: 1.0,
+            scene: None,
+            light_kind: None,
+            shadow: ShadowSettings::default(),
+            background: BackgroundSettings::default(),
+            cluster_heatmap: false,
+            light_gizmos: false,
+            grid: false,
+            helpers: false,
+            ssao: SsaoSettings::default(),
+            post: PostSettings::default(),
+            lut: None,
+            wireframe: WireframeSettings::default(),
+            selection: Vec::new(),
+            outline: OutlineSettings::default(),
+            clip_planes: Vec::new(),
+            clipping: ClipSettings::default(),
+            transparency: TransparencySettings::default(),
+            vectors: VectorSettings::default(),
+            view: VisualizationMode::Shaded,
+            turntable_frames: None,
+            turntable_format: TurntableFormat::Gif,
+            frames_per_second: turntable::DEFAULT_FRAMES_PER_SECOND,
+            force_fallback_adapter: false,
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .with_context(|| format!("missing value for {}", arg))
+            };
+            match arg.as_str() {
+                "--headless" => {}
+                "--fallback" => options.force_fallback_adapter = true,
+                "--model" => options.model = value()?.clone(),
+                "--output" => options.output = Some(PathBuf::from(value()?)),
+                "--width" => options.width = parse(arg, value()?)?,
+                "--height" => options.height = parse(arg, value()?)?,
+                "--distance" => options.distance = parse(arg, value()?)?,
+                "--pitch" => options.pitch = parse(arg, value()?)?,
+                "--yaw" => options.yaw = parse(arg, value()?)?,
+                "--subdivision" => options.subdivision_level = parse(arg, value()?)?,
+                "--tiles" => options.tiles = parse(arg, value()?)?,
+                "--msaa" => options.sample_count = parse(arg, value()?)?,
+                "--shading" => options.shading_model = parse(arg, value()?)?,
+                "--matcap" => options.matcap = Some(value()?.clone()),
+                "--toon-bands" => options.toon.bands = parse::<u32>(arg, value()?)?.max(1),
+                "--rim-intensity" => options.toon.rim_intensity = parse(arg, value()?)?,
+                "--rim-width" => options.toon.rim_width = parse(arg, value()?)?,
+                "--environment" => options.environment = Some(value()?.clone()),
+                "--environment-intensity" => options.environment_intensity = parse(arg, value()?)?,
+                "--scene" => options.scene = Some(value()?.clone()),
+                "--light" => options.light_kind = Some(parse(arg, value()?)?),
+                "--shadow-resolution" => options.shadow.resolution = parse(arg, value()?)?,
+                "--shadow-bias" => options.shadow.depth_bias = parse(arg, value()?)?,
+                "--shadow-normal-bias" => options.shadow.normal_bias = parse(arg, value()?)?,
+                "--pcf" => options.shadow.pcf_radius = parse(arg, value()?)?,
+                "--background" => options.background.mode = parse(arg, value()?)?,
+                "--background-color" => options.background.color = parse_color(arg, value()?)?,
+                "--gradient-top" => options.background.gradient_top = parse_color(arg, value()?)?,
+                "--gradient-bottom" => {
+                    options.background.gradient_bottom = parse_color(arg, value()?)?
+                }
+                "--background-blur" => options.background.blur = parse(arg, value()?)?,
+                "--transparent" => options.background.transparent_screenshots = true,
+                "--cluster-heatmap" => options.cluster_heatmap = true,
+                "--light-gizmos" => options.light_gizmos = true,
+                "--grid" => options.grid = true,
+                "--helpers" => options.helpers = true,
+                "--no-frustum-culling" => options.frustum_culling = false,
+                "--occlusion-culling" => options.occlusion.enabled = true,
+                "--show-occluded" => options.occlusion.show_occluded = true,
+                "--no-ssao" => options.ssao.enabled = false,
+                "--ssao-radius" => options.ssao.radius = parse(arg, value()?)?,
+                "--ssao-intensity" => options.ssao.intensity = parse(arg, value()?)?,
+                "--ssao-samples" => {
+                    options.ssao.sample_count =
+                        parse::<u32>(arg, value()?)?.clamp(1, crate::ssao::MAX_SAMPLES)
+                }
+                "--exposure" => options.post.exposure = parse(arg, value()?)?,
+                "--auto-exposure" => {
+                    options.post.auto_exposure = true;
+                    // There are no previous frames to adapt from
+                    options.post.adaptation_speed = f32::INFINITY;
+                }
+                "--tonemap" => options.post.tonemapper = parse(arg, value()?)?,
+                "--no-bloom" => options.post.bloom = false,
+                "--bloom-threshold" => options.post.bloom_threshold = parse(arg, value()?)?,
+                "--bloom-intensity" => options.post.bloom_intensity = parse(arg, value()?)?,
+                "--fxaa" => options.post.fxaa = true,
+                "--lut" => {
+                    options.lut = Some(value()?.clone());
+                    options.post.color_grading = true;
+                }
+                "--wireframe" => options.wireframe.mode = parse(arg, value()?)?,
+                "--wireframe-width" => options.wireframe.width = parse(arg, value()?)?,
+                "--select" => {
+                    options.selection = value()?
+                        .split(',')
+                        .map(|index| parse(arg, index.trim()))
+                        .collect::<anyhow::Result<_>>()?
+                }
+                "--outline-width" => options.outline.width = parse(arg, value()?)?,
+                "--clip" => {
+                    ensure!(
+                        options.clip_planes.len() < clipping::MAX_PLANES,
+                        "at most {} clip planes are supported",
+                        clipping::MAX_PLANES
+                    );
+                    options.clip_planes.push(parse_plane(arg, value()?)?)
+                }
+                "--no-caps" => options.clipping.caps = false,
+                "--cap-color" => options.clipping.cap_color = parse_color(arg, value()?)?,
+                "--xray" => options.transparency.xray = true,
+                "--xray-opacity" => options.transparency.xray_opacity = parse(arg, value()?)?,
+                "--vectors" => options.vectors.mode = parse(arg, value()?)?,
+                "--vector-length" => options.vectors.length = parse(arg, value()?)?,
+                "--view" => options.view = parse(arg, value()?)?,
+                "--turntable" => options.turntable_frames = Some(parse(arg, value()?)?),
+                "--turntable-format" => options.turntable_format = parse(arg, value()?)?,
+                "--fps" => options.frames_per_second = parse(arg, value()?)?,
+                _ => bail!("unknown argument {}", arg),
+            }
+        }
+
+        ensure!(
+            options.width > 0
+                && options.height > 0
+                && options.tiles > 0
+                && options.shadow.resolution > 0,
+            "the output size, tile count and shadow resolution must not be zero"
+        );
+        Ok(Some(options))
+    }
+}
+
+fn parse<T: std::str::FromStr>(name: &str, value: &str) -> anyhow::Result<T>
+where
+    T::Err: Into<anyhow::Error>,
+{
+    value
+        .parse()
+        .map_err(Into::into)
+        .with_context(|| format!("invalid value {:?} for {}", value, name))
+}
+
+/// Parses a linear RGB color given as `r,g,b`.
+fn parse_color(name: &str, value: &str) -> anyhow::Result<[f32; 3]> {
+    let channels = value
+        .split(',')
+        .map(|c| parse(name, c.trim()))
+        .collect::<anyhow::Result<Vec<f32>>>()?;
+    channels
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("expected r,g,b for {}, got {:?}", name, value))
+}
+
+/// Parses `nx,ny,nz,distance`, see [ClipPlane].
+fn parse_plane(name: &str, value: &str) -> anyhow::Result<ClipPlane> {
+    let numbers = value
+        .split(',')
+        .map(|n| parse(name, n.trim()))
+        .collect::<anyhow::Result<Vec<f32>>>()?;
+    let [x, y, z, distance]: [f32; 4] = numbers
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("expected nx,ny,nz,distance for {}, got {:?}", name, value))?;
+    let normal = Vector3::new(x, y, z);
+    ensure!(
+        normal.magnitude2() > 0.0,
+        "the normal of {} must not be zero",
+        name
+    );
+    Ok(ClipPlane {
+        normal: normal.normalize(),
+        distance,
+    })
+}
+
+/// Requests an adapter that doesn't need to present to a surface.
+pub async fn request_adapter(force_fallback_adapter: bool) -> anyhow::Result<wgpu::Adapter> {
+    let wgpu_instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+        backends: wgpu::Backends::all(),
+        ..Default::default()
+    });
+
+    let adapter = wgpu_instance
+        .request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            compatible_surface: None,
+            force_fallback_adapter,
+        })
+        .await
+        .context("no suitable graphics adapter found")?;
+
+    let info = adapter.get_info();
+    println!("Adapter: {} ({:?})", info.name, info.backend);
+    Ok(adapter)
+}
+
+/// Renders the scene with the camera from `options` into an offscreen texture and writes it
+/// to a PNG, or exports a turntable if one was requested.
+pub async fn run(options: &HeadlessOptions) -> anyhow::Result<()> {
+    let adapter = request_adapter(options.force_fallback_adapter).await?;
+    let (device, command_queue) = renderer::request_device(&adapter).await?;
+
+    let max_size = device.limits().max_texture_dimension_2d;
+    ensure!(
+        options.width <= max_size && options.height <= max_size,
+        "{}x{} exceeds the maximum texture size of {}",
+        options.width,
+        options.height,
+        max_size
+    );
+
+    let mut renderer = Renderer::new(
+        &adapter,
+        device,
+        command_queue,
+        FORMAT,
+        options.width,
+        options.height,
+        options.sample_count,
+        &options.model,
+    )
+    .await?;
+    println!("MSAA: {}x", renderer.sample_count);
+
+    renderer.camera.set_distance(options.distance);
+    renderer.camera.set_pitch(options.pitch);
+    renderer.camera.set_yaw(options.yaw);
+    renderer.shading_model = options.shading_model;
+    if let Some(matcap) = &options.matcap {
+        renderer.load_matcap(matcap).await?;
+    }
+    renderer.toon = options.toon;
+    if let Some(environment) = &options.environment {
+        renderer.load_environment(environment).await?;
+    }
+    renderer.environment_intensity = options.environment_intensity;
+    if let Some(scene) = &options.scene {
+        renderer.load_scene(scene).await?;
+    }
+    if let Some(kind) = options.light_kind {
+        renderer.set_light_kind(kind);
+    }
+    renderer.set_shadow_resolution(options.shadow.resolution);
+    renderer.shadow_map.settings = ShadowSettings {
+        resolution: renderer.shadow_map.settings.resolution,
+        ..options.shadow
+    };
+    renderer.background.settings = options.background;
+    renderer.cluster_heatmap = options.cluster_heatmap;
+    renderer.show_light_gizmos = options.light_gizmos;
+    renderer.grid.visible = options.grid;
+    if options.helpers {
+        renderer.draw_helpers();
+    }
+    renderer.ssao.settings = options.ssao;
+    renderer.post.settings = options.post;
+    renderer.wireframe.settings = options.wireframe;
+    renderer.selection = options.selection.clone();
+    renderer.outline.settings = options.outline;
+    renderer.clipping.planes = options.clip_planes.clone();
+    renderer.clipping.settings = options.clipping;
+    if options.helpers {
+        renderer.draw_clip_planes();
+    }
+    renderer.transparency.settings = options.transparency;
+    renderer.frustum_culling = options.frustum_culling;
+    renderer.occlusion.settings = options.occlusion;
+    renderer.vectors.settings = options.vectors;
+    renderer.visualization.mode = options.view;
+    if let Some(lut) = &options.lut {
+        renderer.load_color_grading_lut(lut).await?;
+    }
+    if options.subdivision_level > 0 {
+        renderer
+            .set_subdivision_level(options.subdivision_level.min(crate::subdivision::MAX_LEVEL));
+    }
+
+    if let Some(frames) = options.turntable_frames {
+        let output = options
+            .output
+            .clone()
+            .unwrap_or_else(|| options.turntable_format.default_output());
+        turntable::export(
+            &mut renderer,
+            &TurntableOptions {
+                frames,
+                frames_per_second: options.frames_per_second,
+                format: options.turntable_format,
+                output: output.clone(),
+            },
+        )?;
+        println!("Saved {}", output.display());
+        return Ok(());
+    }
+
+    let (width, height, pixels) = if options.tiles > 1 {
+        capture::capture_tiled(&mut renderer, options.tiles)
+    } else {
+        if options.occlusion.enabled {
+            // The occlusion queries only apply to the frame after the one they are measured in
+            capture::capture_frame(&mut renderer);
+            if options.occlusion.show_occluded {
+                // Picks up the results of the frame above for the boxes
+                renderer.update();
+                renderer.draw_occluded();
+            }
+        }
+        let pixels = capture::capture_frame(&mut renderer);
+        println!(
+            "Meshes: {} drawn, {} culled, {} occluded",
+            renderer.culling.drawn, renderer.culling.culled, renderer.culling.occluded
+        );
+        (options.width, options.height, pixels)
+    };
+
+    let output = options
+        .output
+        .clone()
+        .unwrap_or_else(|| PathBuf::from("render.png"));
+    offscreen::save_png(&output, width, height, &pixels)?;
+    println!("Saved {}", output.display());
+
+    Ok(())
+}
@@ -0,0 +1,70 @@
+// Text drawn over the final image, see hud.rs
+
+// See hud::HudUniform
+struct Hud {
+    // The size of the target in pixels
+    screen_size: vec2<f32>,
+    // Pixels per font pixel
+    scale: f32,
+}
+@group(0) @binding(0)
+var<uniform> hud: Hud;
+// Every glyph is GLYPH_WIDTH x GLYPH_HEIGHT texels, next to each other in ASCII order from ' '
+@group(0) @binding(1)
+var font: texture_2d<f32>;
+
+const GLYPH_WIDTH: u32 = 5u;
+const GLYPH_HEIGHT: u32 = 7u;
+// A glyph plus the spacing around it, in font pixels
+const CELL_SIZE: vec2<f32> = vec2<f32>(6.0, 9.0);
+const TEXT_COLOR: vec4<f32> = vec4<f32>(1.0, 1.0, 1.0, 1.0);
+const BACKGROUND_COLOR: vec4<f32> = vec4<f32>(0.0, 0.0, 0.0, 0.6);
+
+struct GlyphInput {
+    // The top left corner of the cell in pixels
+    @location(0) position: vec2<f32>,
+    // The index into the font, the character minus ' '
+    @location(1) glyph: u32,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // The position within the cell in font pixels
+    @location(0) cell_position: vec2<f32>,
+    @location(1) @interpolate(flat) glyph: u32,
+}
+
+// Two triangles per glyph instance
+@vertex
+fn vs_main(glyph: GlyphInput, @builtin(vertex_index) index: u32) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(1.0, 1.0),
+    );
+    let corner = corners[index];
+    let pixel = glyph.position + corner * CELL_SIZE * hud.scale;
+    let ndc = pixel / hud.screen_size * 2.0 - 1.0;
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(ndc.x, -ndc.y, 0.0, 1.0);
+    out.cell_position = corner * CELL_SIZE;
+    out.glyph = glyph.glyph;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // One font pixel of spacing on the left and top
+    let texel = vec2<i32>(floor(in.cell_position)) - vec2<i32>(1, 1);
+    if texel.x >= 0 && texel.y >= 0 && texel.x < i32(GLYPH_WIDTH) && texel.y < i32(GLYPH_HEIGHT) {
+        let coverage = textureLoad(font, vec2<i32>(i32(in.glyph * GLYPH_WIDTH) + texel.x, texel.y), 0).r;
+        if coverage > 0.5 {
+            return TEXT_COLOR;
+        }
+    }
+    return BACKGROUND_COLOR;
+}
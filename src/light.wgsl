@@ -0,0 +1,75 @@
+// Gizmos for the lights, one instance per entry of `lights`
+
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+struct Shading {
+    model: u32,
+    environment_intensity: f32,
+    cluster_heatmap: u32,
+    selected_light: u32,
+    ambient_occlusion: u32,
+    toon_bands: u32,
+    rim_intensity: f32,
+    rim_width: f32,
+    xray: u32,
+    xray_opacity: f32,
+}
+@group(0) @binding(1)
+var<uniform> shading: Shading;
+
+// See lights::LightRaw
+struct Light {
+    position: vec3<f32>,
+    kind: u32,
+    direction: vec3<f32>,
+    range: f32,
+    color: vec3<f32>,
+    intensity: f32,
+    inner_cone_cos: f32,
+    outer_cone_cos: f32,
+}
+@group(1) @binding(0)
+var<storage, read> lights: array<Light>;
+
+// Must match lights::GIZMO_RADIUS
+const GIZMO_RADIUS: f32 = 0.06;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+}
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    @builtin(instance_index) index: u32,
+) -> VertexOutput {
+    let light = lights[index];
+    // The selected light is drawn larger
+    let scale = select(1.0, 1.6, index == shading.selected_light);
+
+    var out: VertexOutput;
+    let world_position = light.position + model.position * GIZMO_RADIUS * scale;
+    out.clip_position = camera.view_proj * vec4<f32>(world_position, 1.0);
+    out.color = light.color;
+    out.normal = model.normal;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Brightest at the top, so the sphere still reads as round without lighting
+    let color = in.color / max(max(in.color.r, max(in.color.g, in.color.b)), 1e-4);
+    return vec4<f32>(color * (0.6 + 0.4 * normalize(in.normal).y), 1.0);
+}
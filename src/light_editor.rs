@@ -0,0 +1,166 @@
+use cgmath::{InnerSpace, Matrix4, SquareMatrix, Vector3, Vector4};
+
+use crate::camera::Camera;
+use crate::debug_draw::DebugStyle;
+use crate::lights;
+use crate::orbit_camera::OrbitCamera;
+use crate::renderer::Renderer;
+
+/// Gizmos can be clicked a bit outside of the sphere that is drawn.
+const PICK_RADIUS: f32 = lights::GIZMO_RADIUS * 1.5;
+/// How long the point that was clicked stays marked.
+const PICK_MARKER_LIFETIME: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Selects lights by clicking on their gizmos and moves them by dragging.
+///
+/// A dragged light stays in the plane through its position that faces the camera.
+pub struct LightEditor {
+    /// The last cursor position in physical pixels.
+    cursor: (f32, f32),
+    // The dragged light and the offset from the point under the cursor to its position
+    dragging: Option<(usize, Vector3<f32>)>,
+}
+
+impl LightEditor {
+    pub fn new() -> Self {
+        Self {
+            cursor: (0.0, 0.0),
+            dragging: None,
+        }
+    }
+
+    pub fn is_dragging(&self) -> bool {
+        self.dragging.is_some()
+    }
+
+    /// Selects the light under the cursor and starts dragging it.
+    ///
+    /// Returns false if the gizmos are hidden or there is no light under the cursor, so the
+    /// camera can use the click.
+    pub fn press(&mut self, renderer: &mut Renderer) -> bool {
+        if !renderer.show_light_gizmos {
+            return false;
+        }
+        let (origin, direction) = cursor_ray(
+            &renderer.camera,
+            renderer.width,
+            renderer.height,
+            self.cursor,
+        );
+        let hit = renderer
+            .lights
+            .iter()
+            .enumerate()
+            .filter_map(|(index, light)| {
+                ray_sphere(origin, direction, light.position.into(), PICK_RADIUS)
+                    .map(|distance| (index, distance))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+
+        let Some((index, distance)) = hit else {
+            return false;
+        };
+        renderer.selected_light = Some(index);
+        renderer.debug_draw.cross(
+            origin + direction * distance,
+            PICK_RADIUS,
+            DebugStyle {
+                color: [1.0, 1.0, 0.0],
+                depth_test: false,
+                lifetime: PICK_MARKER_LIFETIME,
+            },
+        );
+        let position = Vector3::from(renderer.lights[index].position);
+        let offset =
+            match self.drag_point(&renderer.camera, renderer.width, renderer.height, position) {
+                Some(point) => position - point,
+                None => Vector3::new(0.0, 0.0, 0.0),
+            };
+        self.dragging = Some((index, offset));
+        true
+    }
+
+    pub fn release(&mut self) {
+        self.dragging = None;
+    }
+
+    /// Remembers the cursor position and moves the dragged light along.
+    ///
+    /// Returns true if a light was moved.
+    pub fn cursor_moved(&mut self, renderer: &mut Renderer, x: f32, y: f32) -> bool {
+        self.cursor = (x, y);
+        let Some((index, offset)) = self.dragging else {
+            return false;
+        };
+        let Some(light) = renderer.lights.get(index) else {
+            self.dragging = None;
+            return false;
+        };
+        let position = Vector3::from(light.position);
+        match self.drag_point(&renderer.camera, renderer.width, renderer.height, position) {
+            Some(point) => {
+                renderer.lights[index].position = (point + offset).into();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Where the cursor ray meets the plane through `position` that faces the camera.
+    fn drag_point(
+        &self,
+        camera: &OrbitCamera,
+        width: u32,
+        height: u32,
+        position: Vector3<f32>,
+    ) -> Option<Vector3<f32>> {
+        let (origin, direction) = cursor_ray(camera, width, height, self.cursor);
+        let normal = (camera.target - camera.eye).normalize();
+        let denominator = direction.dot(normal);
+        if denominator.abs() < 1e-6 {
+            return None;
+        }
+        let distance = (position - origin).dot(normal) / denominator;
+        (distance > 0.0).then(|| origin + direction * distance)
+    }
+}
+
+/// The world space ray through the `cursor` position in physical pixels, as origin and
+/// normalized direction.
+pub fn cursor_ray(
+    camera: &OrbitCamera,
+    width: u32,
+    height: u32,
+    cursor: (f32, f32),
+) -> (Vector3<f32>, Vector3<f32>) {
+    let ndc_x = cursor.0 / width as f32 * 2.0 - 1.0;
+    let ndc_y = 1.0 - cursor.1 / height as f32 * 2.0;
+    let inverse = camera
+        .build_view_projection_matrix()
+        .invert()
+        .unwrap_or(Matrix4::identity());
+    let unproject = |depth: f32| {
+        let point = inverse * Vector4::new(ndc_x, ndc_y, depth, 1.0);
+        point.truncate() / point.w
+    };
+    let near = unproject(0.0);
+    let far = unproject(1.0);
+    (near, (far - near).normalize())
+}
+
+/// The distance along the ray to the first intersection with the sphere, if there is one.
+pub fn ray_sphere(
+    origin: Vector3<f32>,
+    direction: Vector3<f32>,
+    center: Vector3<f32>,
+    radius: f32,
+) -> Option<f32> {
+    let to_center = center - origin;
+    let along = to_center.dot(direction);
+    let distance2 = to_center.magnitude2() - along * along;
+    if distance2 > radius * radius {
+        return None;
+    }
+    let distance = along - (radius * radius - distance2).sqrt();
+    (distance > 0.0).then_some(distance)
+}
@@ -1,10 +1,203 @@
+use cgmath::{InnerSpace, Quaternion, Rad, Rotation, Rotation3, Vector3};
+
+use crate::model;
+
+/// The radius of the spheres that show where the lights are. Must match `GIZMO_RADIUS` in
+/// light.wgsl.
+pub const GIZMO_RADIUS: f32 = 0.06;
+
+/// The colors a light can be given at runtime, in linear RGB.
+pub const COLORS: [[f32; 3]; 6] = [
+    [1.0, 1.0, 1.0],
+    [1.0, 0.8, 0.6],
+    [0.6, 0.8, 1.0],
+    [1.0, 0.2, 0.1],
+    [0.2, 1.0, 0.2],
+    [0.2, 0.3, 1.0],
+];
+
+/// How a light emits light, stored in [LightRaw::kind].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LightKind {
+    /// Shines in all directions from `position`.
+    Point = 0,
+    /// Shines from `position` along `direction`, limited to a cone.
+    Spot = 1,
+    /// Infinitely far away, shines along `direction` everywhere.
+    Directional = 2,
+}
+
+impl LightKind {
+    /// The next kind, wrapping around to [LightKind::Point].
+    pub fn next(self) -> Self {
+        match self {
+            Self::Point => Self::Spot,
+            Self::Spot => Self::Directional,
+            Self::Directional => Self::Point,
+        }
+    }
+}
+
+impl std::str::FromStr for LightKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "point" => Ok(Self::Point),
+            "spot" => Ok(Self::Spot),
+            "directional" => Ok(Self::Directional),
+            _ => anyhow::bail!(
+                "unknown light kind {:?}, expected point, spot or directional",
+                s
+            ),
+        }
+    }
+}
+
+/// A light of the scene, as read from the scene configuration.
+///
+/// Point and spot lights fall off with the inverse square of the distance, so their
+/// `intensity` is the radiance at a distance of 1. For directional lights it is the radiance
+/// everywhere.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize)]
+#[serde(default)]
+pub struct Light {
+    pub kind: LightKind,
+    pub position: [f32; 3],
+    /// The direction the light travels in, for spot and directional lights.
+    pub direction: [f32; 3],
+    /// Linear RGB, multiplied with `intensity`.
+    pub color: [f32; 3],
+    pub intensity: f32,
+    /// Point and spot lights fade out smoothly towards this distance, 0 means unlimited.
+    pub range: f32,
+    /// Spot lights are at full strength inside this angle from `direction`, in degrees.
+    pub inner_cone_angle: f32,
+    /// Spot lights give no light outside this angle from `direction`, in degrees.
+    pub outer_cone_angle: f32,
+}
+
+impl Default for Light {
+    fn default() -> Self {
+        Self {
+            kind: LightKind::Point,
+            position: [2.0, 2.0, 2.0],
+            direction: [-1.0, -1.0, -1.0],
+            color: [1.0, 1.0, 1.0],
+            intensity: 40.0,
+            range: 0.0,
+            inner_cone_angle: 25.0,
+            outer_cone_angle: 35.0,
+        }
+    }
+}
+
+impl Light {
+    pub fn to_raw(self) -> LightRaw {
+        let direction = Vector3::from(self.direction);
+        let direction = if direction.magnitude2() > 0.0 {
+            direction.normalize()
+        } else {
+            -Vector3::unit_y()
+        };
+        LightRaw {
+            position: self.position,
+            kind: self.kind as u32,
+            direction: direction.into(),
+            range: self.range,
+            color: self.color,
+            intensity: self.intensity,
+            inner_cone_cos: self.inner_cone_angle.to_radians().cos(),
+            outer_cone_cos: self.outer_cone_angle.to_radians().cos(),
+            _padding: [0.0; 2],
+        }
+    }
+
+    /// Changes the kind and aims the light at `target`.
+    ///
+    /// The intensity is converted so the light arrives at `target` as bright as before, a
+    /// directional light has no distance falloff.
+    pub fn set_kind(&mut self, kind: LightKind, target: Vector3<f32>) {
+        let to_target = target - Vector3::from(self.position);
+        let distance2 = to_target.magnitude2().max(1e-4);
+        match (
+            self.kind == LightKind::Directional,
+            kind == LightKind::Directional,
+        ) {
+            (false, true) => self.intensity /= distance2,
+            (true, false) => self.intensity *= distance2,
+            _ => {}
+        }
+        self.kind = kind;
+        self.direction = to_target.into();
+    }
+
+    /// Rotates the light by `angle` around the vertical axis through `center`.
+    pub fn orbit(&mut self, angle: Rad<f32>, center: Vector3<f32>) {
+        let rotation = Quaternion::from_axis_angle(Vector3::unit_y(), angle);
+        let position = center + rotation.rotate_vector(Vector3::from(self.position) - center);
+        self.position = position.into();
+        self.direction = rotation.rotate_vector(self.direction.into()).into();
+    }
+}
+
+/// A UV sphere of radius 1 that is drawn at every light, see light.wgsl.
+pub fn create_gizmo(device: &wgpu::Device) -> model::Model {
+    const RINGS: u32 = 8;
+    const SEGMENTS: u32 = 12;
+
+    let mut vertices = Vec::new();
+    for ring in 0..=RINGS {
+        let theta = std::f32::consts::PI * ring as f32 / RINGS as f32;
+        for segment in 0..=SEGMENTS {
+            let phi = 2.0 * std::f32::consts::PI * segment as f32 / SEGMENTS as f32;
+            let normal = [
+                theta.sin() * phi.cos(),
+                theta.cos(),
+                -theta.sin() * phi.sin(),
+            ];
+            vertices.push(model::ModelVertex {
+                position: normal,
+                normal,
+                tex_coords: [segment as f32 / SEGMENTS as f32, ring as f32 / RINGS as f32],
+            });
+        }
+    }
+
+    // Counter clockwise quads, the ones at the poles collapse to triangles
+    let index = |ring: u32, segment: u32| ring * (SEGMENTS + 1) + segment;
+    let mut faces = Vec::new();
+    for ring in 0..RINGS {
+        for segment in 0..SEGMENTS {
+            faces.push(vec![
+                index(ring, segment),
+                index(ring + 1, segment),
+                index(ring + 1, segment + 1),
+                index(ring, segment + 1),
+            ]);
+        }
+    }
+
+    model::Model {
+        meshes: vec![model::Mesh::new(device, "light_gizmo", vertices, faces, 0)],
+        materials: Vec::new(),
+    }
+}
+
+/// One element of the light storage buffer, see `Light` in shader.wgsl.
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-pub struct LightUniform {
+pub struct LightRaw {
     pub position: [f32; 3],
-    // Due to uniforms requiring 16 byte (4 float) spacing, we need to use a padding field here
-    pub _padding: u32,
+    // A LightKind, it also fills the 16 byte (4 float) spacing after the vec3
+    pub kind: u32,
+    pub direction: [f32; 3],
+    pub range: f32,
     pub color: [f32; 3],
-    // Due to uniforms requiring 16 byte (4 float) spacing, we need to use a padding field here
-    pub _padding2: u32,
+    pub intensity: f32,
+    pub inner_cone_cos: f32,
+    pub outer_cone_cos: f32,
+    // The array stride has to be a multiple of 16 bytes
+    pub _padding: [f32; 2],
 }
@@ -1,13 +1,52 @@
+mod background;
 mod camera;
 mod camera_controller;
+mod capture;
+mod clip_editor;
+mod clipping;
+mod clusters;
+mod culling;
+mod debug_draw;
+mod environment;
+mod grid;
+mod headless;
+mod hud;
+mod light_editor;
 mod lights;
 mod model;
+mod occlusion;
+mod offscreen;
 mod orbit_camera;
+mod outline;
+mod post;
+mod renderer;
 mod resources;
+mod scene;
+mod shading;
+mod shadow;
+mod ssao;
+mod subdivision;
 mod texture;
+mod transparency;
+mod turntable;
+mod vectors;
+mod visualization;
+mod wireframe;
 
 const OBJMODEL_NAME: &str = "manycubes.obj";
 
+// An equirectangular .hdr or .exr in the res folder for image based lighting, None uses a plain sky
+const ENVIRONMENT_NAME: Option<&str> = None;
+
+// A TOML scene configuration in the res folder with the lights, None uses a single point light
+const SCENE_NAME: Option<&str> = None;
+
+// A .cube color grading LUT in the res folder, toggled with y. None uses an identity LUT
+const LUT_NAME: Option<&str> = None;
+
+// An image of a shaded sphere in the res folder for the matcap shading, None uses a grey clay
+const MATCAP_NAME: Option<&str> = None;
+
 //MODEL NAMES:
 // JaggedLandscape
 // Suzanne
@@ -15,8 +54,7 @@ const OBJMODEL_NAME: &str = "manycubes.obj";
 // TwistedTorus
 
 use camera_controller::CameraController;
-use cgmath::Vector3;
-use orbit_camera::OrbitCamera;
+use renderer::Renderer;
 use winit::{
     event::*,
     event_loop::{ControlFlow, EventLoop},
@@ -25,28 +63,20 @@ use winit::{
     window::WindowBuilder,
 };
 
-use wgpu::util::DeviceExt;
-
-use model::Vertex;
 struct Application {
     window: Window,
     window_surface: wgpu::Surface,
-    device: wgpu::Device,
-    command_queue: wgpu::Queue,
     size: winit::dpi::PhysicalSize<u32>,
     config: wgpu::SurfaceConfiguration,
-    render_pipeline: wgpu::RenderPipeline,
-    camera: orbit_camera::OrbitCamera,
-    camera_uniform: camera::CameraUniform,
+    renderer: Renderer,
     camera_controller: camera_controller::CameraController,
     mouse_pressed: bool,
-    camera_buffer: wgpu::Buffer,
-    camera_bind_group: wgpu::BindGroup,
-    obj_model: model::Model,
-    depth_texture: texture::Texture,
-    light_bind_group: wgpu::BindGroup,
-    debug_pipeline: wgpu::RenderPipeline,
-    debug: bool,
+    light_editor: light_editor::LightEditor,
+    clip_editor: clip_editor::ClipPlaneEditor,
+    orbit_lights: bool,
+    // Draw the bounds, lights and shadow frustum with the DebugDraw
+    show_helpers: bool,
+    last_update: instant::Instant,
 }
 
 impl Application {
@@ -64,7 +94,7 @@ impl Application {
             .with_resizable(true)
             .with_inner_size(winit::dpi::LogicalSize::new(1280, 720))
             // .with_fullscreen(Some(Fullscreen::Borderless(None)))
-            .build(&event_loop)
+            .build(event_loop)
             .unwrap();
 
         let size = window.inner_size();
@@ -85,21 +115,9 @@ impl Application {
             .unwrap();
 
         // Create device and command queue from adapter
-        // Extra features from bulb example, idk what what do specifically (https://docs.rs/wgpu/latest/wgpu/struct.Features.html)
-        let (device, command_queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    label: Some("main device"),
-                    features: wgpu::Features::default() | wgpu::Features::POLYGON_MODE_LINE, //wgpu::Features::POLYGON_MODE_LINE,
-                    limits: wgpu::Limits {
-                        max_push_constant_size: 8,
-                        ..Default::default()
-                    },
-                },
-                None,
-            )
+        let (device, command_queue) = renderer::request_device(&adapter)
             .await
-            .unwrap();
+            .expect("failed to create the device");
 
         let surface_caps = window_surface.get_capabilities(&adapter);
         // Shader code in this tutorial assumes an sRGB surface texture. Using a different
@@ -109,8 +127,7 @@ impl Application {
             .formats
             .iter()
             .copied()
-            .filter(|f| f.is_srgb())
-            .next()
+            .find(|f| f.is_srgb())
             .unwrap_or(surface_caps.formats[0]);
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
@@ -123,161 +140,55 @@ impl Application {
         };
         window_surface.configure(&device, &config);
 
-        // --CAMERA-- //
-        let mut camera = OrbitCamera::new(
-            2.0,
-            0.0,
-            0.0,
-            Vector3::new(0.0, 0.0, 0.0),
-            size.width as f32 / size.height as f32,
-        );
-        camera.bounds.min_distance = Some(1.1);
         let camera_controller = CameraController::new(0.0025, 0.1);
-        let mut camera_uniform = camera::CameraUniform::default();
-        camera_uniform.update_view_proj(&camera);
-
-        // this is a uniform buffer for the camera
-        let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Camera Buffer"),
-            contents: bytemuck::cast_slice(&[camera_uniform]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
-
-        // now lets create a bind group with the buffer, we need a layout for this
-        let camera_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false, //means that the location of the data in the buffer wont change
-                        min_binding_size: None, //smallest size the buffer can be (dont need to specify -> https://docs.rs/wgpu/latest/wgpu/enum.BindingType.html#variant.Buffer.field.min_binding_size)
-                    },
-                    count: None,
-                }],
-                label: Some("camera_bind_group_layout"),
-            });
-
-        // create the bind group
-        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &camera_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: camera_buffer.as_entire_binding(),
-            }],
-            label: Some("camera_bind_group"),
-        });
-
-        // --DEPTH-- //
-        let depth_texture =
-            texture::Texture::create_depth_texture(&device, &config, "depth_texture");
-
-        // --LIGHTS-- //
-        let light_uniform = lights::LightUniform {
-            position: [2.0, 2.0, 2.0],
-            _padding: 0,
-            color: [1.0, 1.0, 1.0],
-            _padding2: 0,
-        };
 
-        // We'll want to update our lights position, so we use COPY_DST
-        let light_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Light VB"),
-            contents: bytemuck::cast_slice(&[light_uniform]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
-
-        // now create a bind group (with of course the layout as per usual)
-        let light_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
-                label: None,
-            });
-
-        let light_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &light_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: light_buffer.as_entire_binding(),
-            }],
-            label: None,
-        });
-
-        // RENDER PIPELINES
-
-        let render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[&camera_bind_group_layout, &light_bind_group_layout],
-                push_constant_ranges: &[],
-            });
-
-        let render_pipeline = {
-            let shader = wgpu::ShaderModuleDescriptor {
-                label: Some("Normal Shader"),
-                source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
-            };
-            create_render_pipeline(
-                &device,
-                &render_pipeline_layout,
-                config.format,
-                Some(texture::Texture::DEPTH_FORMAT),
-                &[model::ModelVertex::desc()],
-                shader,
-                wgpu::PolygonMode::Fill,
-            )
-        };
-
-        let debug_pipeline = {
-            let shader = wgpu::ShaderModuleDescriptor {
-                label: Some("Debug Pipeline"),
-                source: wgpu::ShaderSource::Wgsl(include_str!("debug.wgsl").into()),
-            };
-            println!("Here");
-            create_render_pipeline(
-                &device,
-                &render_pipeline_layout,
-                config.format,
-                Some(texture::Texture::DEPTH_FORMAT),
-                &[model::ModelVertex::desc()],
-                shader,
-                wgpu::PolygonMode::Line,
-            )
-        };
-
-        // --MODELS-- //
-
-        let obj_model = resources::load_model(OBJMODEL_NAME, &device).await.unwrap();
+        let mut renderer = Renderer::new(
+            &adapter,
+            device,
+            command_queue,
+            config.format,
+            size.width,
+            size.height,
+            renderer::DEFAULT_SAMPLE_COUNT,
+            OBJMODEL_NAME,
+        )
+        .await
+        .unwrap();
+        renderer.show_light_gizmos = true;
+        if let Some(scene) = SCENE_NAME {
+            if let Err(e) = renderer.load_scene(scene).await {
+                eprintln!("Failed to load scene {}: {:#}", scene, e);
+            }
+        }
+        if let Some(environment) = ENVIRONMENT_NAME {
+            if let Err(e) = renderer.load_environment(environment).await {
+                eprintln!("Failed to load environment {}: {:#}", environment, e);
+            }
+        }
+        if let Some(matcap) = MATCAP_NAME {
+            if let Err(e) = renderer.load_matcap(matcap).await {
+                eprintln!("Failed to load matcap {}: {:#}", matcap, e);
+            }
+        }
+        if let Some(lut) = LUT_NAME {
+            if let Err(e) = renderer.load_color_grading_lut(lut).await {
+                eprintln!("Failed to load color grading LUT {}: {:#}", lut, e);
+            }
+        }
 
         Application {
             window,
             window_surface,
-            device,
-            command_queue,
             size,
             config,
-            render_pipeline,
-            camera,
-            camera_uniform,
-            camera_buffer,
-            camera_bind_group,
+            renderer,
             camera_controller,
             mouse_pressed: false,
-            obj_model,
-            depth_texture,
-            light_bind_group,
-            debug_pipeline,
-            debug: false,
+            light_editor: light_editor::LightEditor::new(),
+            clip_editor: clip_editor::ClipPlaneEditor::new(),
+            orbit_lights: false,
+            show_helpers: false,
+            last_update: instant::Instant::now(),
         }
     }
 
@@ -287,66 +198,72 @@ impl Application {
         event_loop.set_control_flow(ControlFlow::Poll);
         let _ = event_loop.run(move |event, elwt| {
             match event {
+                // The mouse moves the light or clip plane instead of the camera while one is dragged
+                Event::DeviceEvent {
+                    event: DeviceEvent::MouseMotion { .. },
+                    ..
+                } if self.light_editor.is_dragging() || self.clip_editor.is_dragging() => {}
                 Event::DeviceEvent { ref event, .. } => {
-                    self.camera_controller
-                        .process_events(event, &self.window, &mut self.camera);
+                    self.camera_controller.process_events(
+                        event,
+                        &self.window,
+                        &mut self.renderer.camera,
+                    );
                 }
 
                 Event::WindowEvent {
                     window_id,
                     ref event,
                 } if window_id == self.window.id() && !self.input(event) => {
-                    if !self.input(event) {
-                        match event {
-                            WindowEvent::CloseRequested
-                            | WindowEvent::KeyboardInput {
-                                event:
-                                    KeyEvent {
-                                        logical_key: Key::Named(NamedKey::Escape),
-                                        ..
-                                    },
-                                ..
-                            } => {
-                                elwt.exit();
-                            }
+                    match event {
+                        WindowEvent::CloseRequested
+                        | WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    logical_key: Key::Named(NamedKey::Escape),
+                                    ..
+                                },
+                            ..
+                        } => {
+                            elwt.exit();
+                        }
 
-                            WindowEvent::KeyboardInput { event, .. } => {
-                                self.camera_controller.process_keyed_events(&event)
-                            }
-                            // Resizing
-                            WindowEvent::Resized(physical_size) => {
-                                self.resize(*physical_size);
-                            }
+                        WindowEvent::KeyboardInput { event, .. } => {
+                            self.camera_controller.process_keyed_events(event)
+                        }
+                        // Resizing
+                        WindowEvent::Resized(physical_size) => {
+                            self.resize(*physical_size);
+                        }
+
+                        WindowEvent::RedrawRequested => {
+                            // Redraw the application.
+                            //
+                            // It's preferable for applications that do not render continuously to render in
+                            // this event rather than in AboutToWait, since rendering in here allows
+                            // the program to gracefully handle redraws requested by the OS.
 
-                            WindowEvent::RedrawRequested => {
-                                // Redraw the application.
-                                //
-                                // It's preferable for applications that do not render continuously to render in
-                                // this event rather than in AboutToWait, since rendering in here allows
-                                // the program to gracefully handle redraws requested by the OS.
-
-                                let now = instant::Instant::now();
-
-                                self.update();
-
-                                match self.render() {
-                                    Ok(_) => {}
-                                    // Reconfigure the surface if lost
-                                    Err(wgpu::SurfaceError::Lost) => self.resize(self.size),
-                                    // The system is out of memory, we should probably quit
-                                    Err(wgpu::SurfaceError::OutOfMemory) => elwt.exit(),
-                                    // All other errors (Outdated, Timeout) should be resolved by the next frame
-                                    Err(e) => eprintln!("{:?}", e),
-                                }
-
-                                // FRAMERATE CALC
-                                let elapsed = now.elapsed().as_millis();
-                                println!("{:#?}ms", elapsed)
+                            let now = instant::Instant::now();
+
+                            self.update();
+
+                            match self.render() {
+                                Ok(_) => {}
+                                // Reconfigure the surface if lost
+                                Err(wgpu::SurfaceError::Lost) => self.resize(self.size),
+                                // The system is out of memory, we should probably quit
+                                Err(wgpu::SurfaceError::OutOfMemory) => elwt.exit(),
+                                // All other errors (Outdated, Timeout) should be resolved by the next frame
+                                Err(e) => eprintln!("{:?}", e),
                             }
 
-                            _ => (),
-                        } //  match winodw end
-                    }
+                            // FRAMERATE CALC
+                            let elapsed = now.elapsed().as_millis();
+                            println!("{:#?}ms", elapsed)
+                        }
+
+                        _ => (),
+                    } //  match winodw end
                 } // end 1st event match
                 _ => (),
             }
@@ -358,38 +275,45 @@ impl Application {
             self.size = new_size;
             self.config.width = new_size.width;
             self.config.height = new_size.height;
-            self.window_surface.configure(&self.device, &self.config);
+            self.window_surface
+                .configure(&self.renderer.device, &self.config);
         }
 
-        self.camera
-            .resize_projection(new_size.width, new_size.height);
-
-        self.depth_texture =
-            texture::Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
+        self.renderer.resize(new_size.width, new_size.height);
 
         self.window.request_redraw();
     }
 
     fn update(&mut self) {
-        self.camera_uniform.update_view_proj(&self.camera);
-        self.command_queue.write_buffer(
-            &self.camera_buffer,
-            0,
-            bytemuck::cast_slice(&[self.camera_uniform]),
-        );
-
-        // // Update the light position
-        // let old_position: cgmath::Vector3<_> = self.light_uniform.position.into();
-        // self.light_uniform.position = (cgmath::Quaternion::from_axis_angle(
-        //     (0.0, 1.0, 0.0).into(),
-        //     cgmath::Deg(60.0 * dt.as_secs_f32()),
-        // ) * old_position)
-        //     .into();
-        // self.command_queue.write_buffer(
-        //     &self.light_buffer,
-        //     0,
-        //     bytemuck::cast_slice(&[self.light_uniform]),
-        // );
+        let now = instant::Instant::now();
+        let dt = now - self.last_update;
+        self.last_update = now;
+        if self.orbit_lights {
+            self.renderer.orbit_lights(dt);
+            self.window.request_redraw();
+        }
+        self.renderer.debug_draw.tick(dt);
+        if self.show_helpers {
+            self.renderer.draw_helpers();
+        }
+        self.renderer.draw_clip_planes();
+        if self.renderer.debug_draw.has_lasting_primitives() {
+            self.window.request_redraw();
+        }
+        // The occlusion results arrive after the frame they are measured in
+        if self.renderer.update() {
+            self.window.request_redraw();
+        }
+        self.renderer.draw_occluded();
+        let view = self.renderer.visualization.mode.name();
+        let culling = self.renderer.culling;
+        self.renderer.hud.set_lines(vec![
+            format!("View: {}", view),
+            format!(
+                "Meshes: {} drawn, {} culled, {} occluded",
+                culling.drawn, culling.culled, culling.occluded
+            ),
+        ]);
     }
 
     fn input(&mut self, event: &WindowEvent) -> bool {
@@ -400,21 +324,687 @@ impl Application {
             } => match key_event {
                 KeyEvent {
                     logical_key: Key::Character(c),
-                    repeat,
-                    state,
+                    state: ElementState::Pressed,
+                    repeat: false,
                     ..
                 } if c == "j" => {
-                    if !repeat && state.is_pressed() {
-                        if self.debug {
-                            println!("Debug: false");
-                            self.debug = false;
-                            self.window.request_redraw();
-                        } else {
-                            println!("Debug: true");
-                            self.debug = true;
-                            self.window.request_redraw();
+                    let visualization = &mut self.renderer.visualization;
+                    visualization.mode = visualization.mode.next();
+                    println!("View: {}", visualization.mode.name());
+                    self.window.request_redraw();
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                } if c == "J" => {
+                    self.renderer.hud.visible = !self.renderer.hud.visible;
+                    self.window.request_redraw();
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    ..
+                } if c == "[" || c == "]" => {
+                    let level = if c == "]" {
+                        (self.renderer.subdivision_level + 1).min(subdivision::MAX_LEVEL)
+                    } else {
+                        self.renderer.subdivision_level.saturating_sub(1)
+                    };
+                    if level != self.renderer.subdivision_level {
+                        self.renderer.set_subdivision_level(level);
+                        println!("Subdivision level: {}", level);
+                        self.window.request_redraw();
+                    }
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                } if c == "p" || c == "P" => {
+                    // Shift+P renders a tiled image several times the window size
+                    let tiles = if c == "P" {
+                        capture::high_res_tiles(&self.renderer)
+                    } else {
+                        1
+                    };
+                    match capture::save_screenshot(&mut self.renderer, tiles) {
+                        Ok(path) => println!("Saved screenshot {}", path.display()),
+                        Err(e) => eprintln!("Screenshot failed: {:#}", e),
+                    }
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                } if c == "t" => {
+                    let options = turntable::TurntableOptions {
+                        frames: turntable::DEFAULT_FRAMES,
+                        frames_per_second: turntable::DEFAULT_FRAMES_PER_SECOND,
+                        format: turntable::TurntableFormat::Gif,
+                        output: turntable::TurntableFormat::Gif.default_output(),
+                    };
+                    println!("Exporting turntable to {}", options.output.display());
+                    match turntable::export(&mut self.renderer, &options) {
+                        Ok(()) => println!("Saved {}", options.output.display()),
+                        Err(e) => eprintln!("Turntable export failed: {:#}", e),
+                    }
+                    self.window.request_redraw();
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                } if c == "m" => {
+                    let sample_count = self
+                        .renderer
+                        .set_sample_count(self.renderer.next_sample_count());
+                    println!("MSAA: {}x", sample_count);
+                    self.window.request_redraw();
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                } if c == "l" => {
+                    let kind = self.renderer.light_kind().next();
+                    self.renderer.set_light_kind(kind);
+                    println!("Light: {:?}", kind);
+                    self.window.request_redraw();
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                } if c == "b" => {
+                    self.renderer.shading_model = self.renderer.shading_model.next();
+                    println!("Shading: {:?}", self.renderer.shading_model);
+                    self.window.request_redraw();
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                } if c == "i" => {
+                    let toon = &mut self.renderer.toon;
+                    toon.bands = toon.bands % 6 + 1;
+                    println!("Toon bands: {}", toon.bands);
+                    self.window.request_redraw();
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                } if c == "I" => {
+                    let toon = &mut self.renderer.toon;
+                    toon.rim_intensity = if toon.rim_intensity >= 1.0 {
+                        0.0
+                    } else {
+                        toon.rim_intensity + 0.5
+                    };
+                    println!("Rim light: {}", toon.rim_intensity);
+                    self.window.request_redraw();
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                } if c == "k" => {
+                    let resolution = self.renderer.shadow_map.next_resolution();
+                    self.renderer.set_shadow_resolution(resolution);
+                    println!("Shadow map: {0}x{0}", resolution);
+                    self.window.request_redraw();
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    ..
+                } if c == "," || c == "." => {
+                    // Halve or double, the useful range spans several orders of magnitude
+                    let settings = &mut self.renderer.shadow_map.settings;
+                    if c == "." {
+                        settings.depth_bias *= 2.0;
+                    } else {
+                        settings.depth_bias /= 2.0;
+                    }
+                    println!("Shadow bias: {}", settings.depth_bias);
+                    self.window.request_redraw();
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                } if c == "h" => {
+                    self.renderer.cluster_heatmap = !self.renderer.cluster_heatmap;
+                    println!("Cluster heatmap: {}", self.renderer.cluster_heatmap);
+                    self.window.request_redraw();
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                } if c == "g" => {
+                    let settings = &mut self.renderer.background.settings;
+                    settings.mode = settings.mode.next();
+                    println!("Background: {:?}", settings.mode);
+                    self.window.request_redraw();
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                } if c == "G" => {
+                    // Picking a color also switches to it, otherwise nothing would change
+                    let settings = &mut self.renderer.background.settings;
+                    let index = background::SOLID_COLORS
+                        .iter()
+                        .position(|&color| color == settings.color)
+                        .map_or(0, |i| (i + 1) % background::SOLID_COLORS.len());
+                    settings.color = background::SOLID_COLORS[index];
+                    settings.mode = background::BackgroundMode::Solid;
+                    println!("Background color: {:?}", settings.color);
+                    self.window.request_redraw();
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    ..
+                } if c == "{" || c == "}" => {
+                    let settings = &mut self.renderer.background.settings;
+                    let step = if c == "}" { 0.1 } else { -0.1 };
+                    settings.blur = (settings.blur + step).clamp(0.0, 1.0);
+                    println!("Background blur: {:.1}", settings.blur);
+                    self.window.request_redraw();
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                } if c == "T" => {
+                    let settings = &mut self.renderer.transparency.settings;
+                    settings.xray = !settings.xray;
+                    println!("X-ray: {}", settings.xray);
+                    self.window.request_redraw();
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                } if c == "O" => {
+                    let settings = &mut self.renderer.transparency.settings;
+                    settings.xray_opacity = match settings.xray_opacity {
+                        x if x < 0.2 => 0.25,
+                        x if x < 0.4 => 0.5,
+                        _ => 0.1,
+                    };
+                    println!("X-ray opacity: {}", settings.xray_opacity);
+                    self.window.request_redraw();
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                } if c == "o" => {
+                    let settings = &mut self.renderer.background.settings;
+                    settings.transparent_screenshots = !settings.transparent_screenshots;
+                    println!(
+                        "Transparent screenshots: {}",
+                        settings.transparent_screenshots
+                    );
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                } if c == "a" => {
+                    let settings = &mut self.renderer.ssao.settings;
+                    settings.enabled = !settings.enabled;
+                    println!("SSAO: {}", settings.enabled);
+                    self.window.request_redraw();
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                } if c == "A" => {
+                    let sample_count = self.renderer.ssao.next_sample_count();
+                    self.renderer.ssao.settings.sample_count = sample_count;
+                    println!("SSAO samples: {}", sample_count);
+                    self.window.request_redraw();
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    ..
+                } if c == "(" || c == ")" => {
+                    let settings = &mut self.renderer.ssao.settings;
+                    let factor = if c == ")" { 1.25 } else { 1.0 / 1.25 };
+                    settings.radius *= factor;
+                    println!("SSAO radius: {:.3}", settings.radius);
+                    self.window.request_redraw();
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    ..
+                } if c == "9" || c == "0" => {
+                    let settings = &mut self.renderer.ssao.settings;
+                    let step = if c == "0" { 0.25 } else { -0.25 };
+                    settings.intensity = (settings.intensity + step).clamp(0.0, 4.0);
+                    println!("SSAO intensity: {:.2}", settings.intensity);
+                    self.window.request_redraw();
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                } if c == "L" => {
+                    self.renderer.show_light_gizmos = !self.renderer.show_light_gizmos;
+                    if !self.renderer.show_light_gizmos {
+                        self.light_editor.release();
+                    }
+                    println!("Light gizmos: {}", self.renderer.show_light_gizmos);
+                    self.window.request_redraw();
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    ..
+                } if c == "n" => {
+                    let count = self.renderer.lights.len();
+                    if count > 0 {
+                        let index = self.renderer.selected_light.map_or(0, |i| (i + 1) % count);
+                        self.renderer.selected_light = Some(index);
+                        println!("Selected light {}", index);
+                        self.window.request_redraw();
+                    }
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    ..
+                } if c == "-" || c == "=" || c == "+" => {
+                    if let Some(light) = self
+                        .renderer
+                        .selected_light
+                        .and_then(|i| self.renderer.lights.get_mut(i))
+                    {
+                        let factor = if c == "-" { 1.0 / 1.25 } else { 1.25 };
+                        light.intensity *= factor;
+                        println!("Light intensity: {}", light.intensity);
+                        self.window.request_redraw();
+                    }
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    ..
+                } if c == "c" => {
+                    if let Some(light) = self
+                        .renderer
+                        .selected_light
+                        .and_then(|i| self.renderer.lights.get_mut(i))
+                    {
+                        let index = lights::COLORS
+                            .iter()
+                            .position(|&color| color == light.color)
+                            .map_or(0, |i| (i + 1) % lights::COLORS.len());
+                        light.color = lights::COLORS[index];
+                        println!("Light color: {:?}", light.color);
+                        self.window.request_redraw();
+                    }
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                } if c == "r" => {
+                    self.orbit_lights = !self.orbit_lights;
+                    // Otherwise the first frame would catch up on the whole pause
+                    self.last_update = instant::Instant::now();
+                    println!("Orbiting lights: {}", self.orbit_lights);
+                    self.window.request_redraw();
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    ..
+                } if c == "e" || c == "E" => {
+                    let settings = &mut self.renderer.post.settings;
+                    settings.exposure += if c == "E" { 0.5 } else { -0.5 };
+                    println!("Exposure: {:+.1} EV", settings.exposure);
+                    self.window.request_redraw();
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                } if c == "x" => {
+                    let settings = &mut self.renderer.post.settings;
+                    settings.auto_exposure = !settings.auto_exposure;
+                    println!("Auto exposure: {}", settings.auto_exposure);
+                    self.window.request_redraw();
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                } if c == "u" => {
+                    let settings = &mut self.renderer.post.settings;
+                    settings.tonemapper = settings.tonemapper.next();
+                    println!("Tonemapper: {:?}", settings.tonemapper);
+                    self.window.request_redraw();
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                } if c == "B" => {
+                    let settings = &mut self.renderer.post.settings;
+                    settings.bloom = !settings.bloom;
+                    println!("Bloom: {}", settings.bloom);
+                    self.window.request_redraw();
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                } if c == "F" => {
+                    self.renderer.frustum_culling = !self.renderer.frustum_culling;
+                    println!("Frustum culling: {}", self.renderer.frustum_culling);
+                    self.window.request_redraw();
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                } if c == "H" => {
+                    let settings = &mut self.renderer.occlusion.settings;
+                    settings.enabled = !settings.enabled;
+                    println!("Occlusion culling: {}", settings.enabled);
+                    self.window.request_redraw();
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                } if c == "Q" => {
+                    let settings = &mut self.renderer.occlusion.settings;
+                    settings.show_occluded = !settings.show_occluded;
+                    self.window.request_redraw();
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                } if c == "f" => {
+                    let settings = &mut self.renderer.post.settings;
+                    settings.fxaa = !settings.fxaa;
+                    println!("FXAA: {}", settings.fxaa);
+                    self.window.request_redraw();
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                } if c == "y" => {
+                    let settings = &mut self.renderer.post.settings;
+                    settings.color_grading = !settings.color_grading;
+                    println!("Color grading: {}", settings.color_grading);
+                    self.window.request_redraw();
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                } if c == "w" => {
+                    let settings = &mut self.renderer.wireframe.settings;
+                    settings.mode = settings.mode.next();
+                    println!("Wireframe: {:?}", settings.mode);
+                    self.window.request_redraw();
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                } if c == "s" => {
+                    // none, then every mesh on its own
+                    let count = self.renderer.obj_model.meshes.len();
+                    let next = match self.renderer.selection.as_slice() {
+                        [] => 0,
+                        [index] => index + 1,
+                        _ => count,
+                    };
+                    self.renderer.selection = (next < count).then_some(next).into_iter().collect();
+                    match self.renderer.selection.first() {
+                        Some(&index) => {
+                            println!("Selected: {}", self.renderer.obj_model.meshes[index].name)
                         }
+                        None => println!("Selected: none"),
+                    }
+                    self.window.request_redraw();
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                } if c == "q" => {
+                    self.show_helpers = !self.show_helpers;
+                    println!("Helpers: {}", self.show_helpers);
+                    self.window.request_redraw();
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                } if c == "z" => {
+                    self.renderer.grid.visible = !self.renderer.grid.visible;
+                    println!("Grid: {}", self.renderer.grid.visible);
+                    self.window.request_redraw();
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                } if c == "v" => {
+                    let settings = &mut self.renderer.vectors.settings;
+                    settings.mode = settings.mode.next();
+                    println!("Vectors: {:?}", settings.mode);
+                    self.window.request_redraw();
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                } if c == "d" => {
+                    let (min, max) = self.renderer.obj_model.bounds();
+                    let clipping = &mut self.renderer.clipping;
+                    if clipping.add_plane((min + max) / 2.0) {
+                        println!("Clip planes: {}", clipping.planes.len());
+                    } else {
+                        println!("At most {} clip planes", clipping::MAX_PLANES);
+                    }
+                    self.window.request_redraw();
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                } if c == "D" => {
+                    let clipping = &mut self.renderer.clipping;
+                    clipping.remove_selected();
+                    println!("Clip planes: {}", clipping.planes.len());
+                    self.window.request_redraw();
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                } if c == "N" => {
+                    let clipping = &mut self.renderer.clipping;
+                    clipping.select_next();
+                    if let Some(index) = clipping.selected {
+                        println!("Selected clip plane {}", index);
+                    }
+                    self.window.request_redraw();
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                } if c == "X" || c == "Y" || c == "Z" => {
+                    let axis = match c.as_str() {
+                        "X" => cgmath::Vector3::unit_x(),
+                        "Y" => cgmath::Vector3::unit_y(),
+                        _ => cgmath::Vector3::unit_z(),
                     };
+                    let (min, max) = self.renderer.obj_model.bounds();
+                    let clipping = &mut self.renderer.clipping;
+                    if let Some(plane) = clipping.selected.and_then(|i| clipping.planes.get_mut(i))
+                    {
+                        plane.align(axis, (min + max) / 2.0);
+                        println!("Clip plane normal: {:?}", plane.normal);
+                        self.window.request_redraw();
+                    }
+
+                    true
+                }
+                KeyEvent {
+                    logical_key: Key::Character(c),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                } if c == "C" => {
+                    let settings = &mut self.renderer.clipping.settings;
+                    settings.caps = !settings.caps;
+                    println!("Section caps: {}", settings.caps);
+                    self.window.request_redraw();
 
                     true
                 }
@@ -430,15 +1020,32 @@ impl Application {
                 ..
             } => {
                 self.mouse_pressed = *state == ElementState::Pressed;
+                if self.mouse_pressed {
+                    if self.light_editor.press(&mut self.renderer)
+                        || self.clip_editor.press(&mut self.renderer)
+                    {
+                        self.window.request_redraw();
+                    }
+                } else {
+                    self.light_editor.release();
+                    self.clip_editor.release();
+                }
+                true
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                let (x, y) = (position.x as f32, position.y as f32);
+                // Both editors have to see every cursor position
+                let light_moved = self.light_editor.cursor_moved(&mut self.renderer, x, y);
+                let plane_moved = self.clip_editor.cursor_moved(&mut self.renderer, x, y);
+                if light_moved || plane_moved {
+                    self.window.request_redraw();
+                }
                 true
             }
             _ => false,
         }
     }
 
-    // ===================================================================== //
-    // ============================= RENDER ================================ //
-    // ===================================================================== //
     fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
         // The get_current_texture function will wait for the surface to provide a new
         // SurfaceTexture that we will render to. We'll store this in output for later.
@@ -449,130 +1056,30 @@ impl Application {
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
 
-        // we need a command buffer to send instructions to the gpu. This encoder does that
-        let mut encoder = self
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("Render Encoder"),
-            });
-
-        // now use the encoder to create a render pass, which has all the methods for actual drawing
-
-        //we need the nesting because begin_render_pass BORROWS encoder mutably (&mut self) so we can't
-        // call encoder.finish() until we release this mutable borrow
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
-                            a: 1.0,
-                        }),
-                        store: wgpu::StoreOp::Store,
-                    },
-                })],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    //attach depth texture to stencil attatchement of render pass
-                    view: &self.depth_texture.view,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
-                        store: wgpu::StoreOp::Store,
-                    }),
-                    stencil_ops: None,
-                }),
-                occlusion_query_set: None,
-                timestamp_writes: None,
-            });
-
-            use model::DrawModel;
-            if self.debug {
-                render_pass.set_pipeline(&self.debug_pipeline);
-            } else {
-                render_pass.set_pipeline(&self.render_pipeline);
-            }
-            render_pass.draw_model(
-                // or could add ...model_instanced with (0..self.instances.len() as u32) parameter to do instancing
-                &self.obj_model,
-                &self.camera_bind_group,
-                &self.light_bind_group,
-            );
-        }
-
-        // could do drop(render_pass) here if we dont want braces nesting
-
-        // submit will accept anything that implements IntoIter
-        self.command_queue.submit(std::iter::once(encoder.finish()));
+        self.renderer.render(&view);
         output.present();
 
         Ok(())
     }
 }
 
-fn create_render_pipeline(
-    device: &wgpu::Device,
-    layout: &wgpu::PipelineLayout,
-    color_format: wgpu::TextureFormat,
-    depth_format: Option<wgpu::TextureFormat>,
-    vertex_layouts: &[wgpu::VertexBufferLayout],
-    shader: wgpu::ShaderModuleDescriptor,
-    poly_mode: wgpu::PolygonMode,
-) -> wgpu::RenderPipeline {
-    let shader = device.create_shader_module(shader);
-
-    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-        label: Some("Render Pipeline"),
-        layout: Some(layout),
-        vertex: wgpu::VertexState {
-            module: &shader,
-            entry_point: "vs_main",
-            buffers: vertex_layouts,
-        },
-        fragment: Some(wgpu::FragmentState {
-            module: &shader,
-            entry_point: "fs_main",
-            targets: &[Some(wgpu::ColorTargetState {
-                format: color_format,
-                blend: Some(wgpu::BlendState {
-                    alpha: wgpu::BlendComponent::REPLACE,
-                    color: wgpu::BlendComponent::REPLACE,
-                }),
-                write_mask: wgpu::ColorWrites::ALL,
-            })],
-        }),
-        primitive: wgpu::PrimitiveState {
-            topology: wgpu::PrimitiveTopology::TriangleList,
-            strip_index_format: None,
-            front_face: wgpu::FrontFace::Ccw,
-            cull_mode: Some(wgpu::Face::Back),
-            // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
-            polygon_mode: poly_mode,
-            // Requires Features::DEPTH_CLIP_CONTROL
-            unclipped_depth: false,
-            // Requires Features::CONSERVATIVE_RASTERIZATION
-            conservative: false,
-        },
-        depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
-            format,
-            depth_write_enabled: true,
-            depth_compare: wgpu::CompareFunction::Less,
-            stencil: wgpu::StencilState::default(),
-            bias: wgpu::DepthBiasState::default(),
-        }),
-        multisample: wgpu::MultisampleState {
-            count: 1,
-            mask: !0,
-            alpha_to_coverage_enabled: false,
-        },
-        multiview: None,
-    })
-}
-
 fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    match headless::HeadlessOptions::from_args(&args, OBJMODEL_NAME) {
+        Ok(Some(options)) => {
+            if let Err(e) = pollster::block_on(headless::run(&options)) {
+                eprintln!("Headless render failed: {:#}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("{:#}", e);
+            std::process::exit(2);
+        }
+    }
+
     let event_loop = EventLoop::new().unwrap();
     let mut application = pollster::block_on(Application::new(&event_loop));
     application.run(event_loop);
@@ -1,5 +1,11 @@
 use std::ops::Range;
 
+use cgmath::Vector3;
+use wgpu::util::DeviceExt;
+
+use crate::culling::{CullingStats, Frustum};
+use crate::{subdivision, texture};
+
 pub trait Vertex {
     fn desc() -> wgpu::VertexBufferLayout<'static>;
 }
@@ -9,6 +15,7 @@ pub trait Vertex {
 pub struct ModelVertex {
     pub position: [f32; 3],
     pub normal: [f32; 3],
+    pub tex_coords: [f32; 2],
 }
 
 impl Vertex for ModelVertex {
@@ -17,6 +24,44 @@ impl Vertex for ModelVertex {
         wgpu::VertexBufferLayout {
             array_stride: mem::size_of::<ModelVertex>() as wgpu::BufferAddress,
             step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 6]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+            ],
+        }
+    }
+}
+
+/// A corner of a triangle as the [Wireframe](crate::wireframe::Wireframe) draws it. The
+/// triangles don't share vertices, because every corner needs its own barycentric coordinates.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct WireframeVertex {
+    pub position: [f32; 3],
+    /// One at this corner and zero at the other two. The coordinate of a corner is offset by
+    /// one if the edge opposite it is not drawn.
+    pub barycentric: [f32; 3],
+}
+
+impl Vertex for WireframeVertex {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<WireframeVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
             attributes: &[
                 wgpu::VertexAttribute {
                     offset: 0,
@@ -33,19 +78,309 @@ impl Vertex for ModelVertex {
     }
 }
 
+/// The constant inputs of a metallic-roughness material. Each one is multiplied with the
+/// matching texture of the [Material].
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MaterialUniform {
+    /// Linear RGB and alpha.
+    pub base_color: [f32; 4],
+    /// Linear RGB light emitted by the surface.
+    pub emissive: [f32; 3],
+    pub metallic: f32,
+    /// Perceptual roughness, squared before it is used as the GGX alpha.
+    pub roughness: f32,
+    /// How much of the occlusion texture is applied, 0 ignores it.
+    pub occlusion_strength: f32,
+    // Due to uniforms requiring 16 byte (4 float) spacing, we need to use a padding field here
+    pub _padding: [f32; 2],
+}
+
+impl Default for MaterialUniform {
+    /// The material used for files without one, a dull off-white.
+    fn default() -> Self {
+        Self {
+            base_color: [0.9, 0.8, 0.8, 1.0],
+            emissive: [0.0; 3],
+            metallic: 0.0,
+            roughness: 0.5,
+            occlusion_strength: 1.0,
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+/// The textures of a [Material]. Metallic, roughness and occlusion are read from the red channel.
+pub struct MaterialTextures {
+    pub base_color: texture::Texture,
+    pub metallic: texture::Texture,
+    pub roughness: texture::Texture,
+    pub occlusion: texture::Texture,
+    pub emissive: texture::Texture,
+}
+
+impl MaterialTextures {
+    /// White 1x1 textures for every slot, so only the [MaterialUniform] factors have an effect.
+    pub fn white(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let white =
+            |label, srgb| texture::Texture::single_pixel(device, queue, [255; 4], label, srgb);
+        Self {
+            base_color: white("base_color_texture", true),
+            metallic: white("metallic_texture", false),
+            roughness: white("roughness_texture", false),
+            occlusion: white("occlusion_texture", false),
+            emissive: white("emissive_texture", true),
+        }
+    }
+}
+
+#[allow(dead_code)]
 pub struct Material {
     pub name: String,
-    // pub diffuse_texture: texture::Texture,
+    pub uniform: MaterialUniform,
+    pub textures: MaterialTextures,
+    pub buffer: wgpu::Buffer,
     pub bind_group: wgpu::BindGroup,
 }
 
-#[derive(Debug)]
+impl Material {
+    pub fn new(
+        device: &wgpu::Device,
+        name: &str,
+        uniform: MaterialUniform,
+        textures: MaterialTextures,
+        layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Material Buffer", name)),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&textures.base_color.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&textures.metallic.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&textures.roughness.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&textures.occlusion.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(&textures.emissive.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    // All slots share the sampler settings, so any of them will do
+                    resource: wgpu::BindingResource::Sampler(&textures.base_color.sampler),
+                },
+            ],
+            label: Some(&format!("{:?} Material Bind Group", name)),
+        });
+
+        Self {
+            name: name.to_string(),
+            uniform,
+            textures,
+            buffer,
+            bind_group,
+        }
+    }
+
+    /// Whether the material lets the surfaces behind it show through, like an MTL `d` below 1.
+    pub fn is_transparent(&self) -> bool {
+        self.uniform.base_color[3] < 1.0
+    }
+
+    /// The layout of [Material::bind_group]: the uniform, five textures and a sampler.
+    pub fn create_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                texture_entry(1),
+                texture_entry(2),
+                texture_entry(3),
+                texture_entry(4),
+                texture_entry(5),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 6,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("material_bind_group_layout"),
+        })
+    }
+}
+
 pub struct Mesh {
     pub name: String,
     pub vertex_buffer: wgpu::Buffer,
     pub index_buffer: wgpu::Buffer,
     pub num_elements: u32,
     pub material: usize,
+    // The vertices and polygons as loaded, kept so the buffers can be rebuilt at any subdivision level
+    pub vertices: Vec<ModelVertex>,
+    pub faces: Vec<Vec<u32>>,
+    // Welded copy of the faces above, this is what actually gets subdivided
+    pub cage: subdivision::PolyMesh,
+    /// The axis aligned box around `vertices` as (min, max), which holds at every subdivision
+    /// level, see [Model::bounds].
+    pub bounds: (Vector3<f32>, Vector3<f32>),
+    /// Set by [Model::cull] if the mesh is outside the view or hidden, the draws of the whole
+    /// model skip it.
+    pub culled: bool,
+    /// Set by [Model::cull] if the mesh is culled because it is hidden behind other meshes.
+    pub occluded: bool,
+}
+
+impl Mesh {
+    pub fn new(
+        device: &wgpu::Device,
+        name: &str,
+        vertices: Vec<ModelVertex>,
+        faces: Vec<Vec<u32>>,
+        material: usize,
+    ) -> Self {
+        let indices = subdivision::fan_triangulate(&faces);
+        let (vertex_buffer, index_buffer) = create_buffers(device, name, &vertices, &indices);
+        let cage = subdivision::PolyMesh::welded(&vertices, &faces);
+        let bounds = vertex_bounds(&vertices);
+
+        Self {
+            name: name.to_string(),
+            vertex_buffer,
+            index_buffer,
+            num_elements: indices.len() as u32,
+            material,
+            vertices,
+            faces,
+            cage,
+            bounds,
+            culled: false,
+            occluded: false,
+        }
+    }
+
+    /// Rebuilds the GPU buffers for the given subdivision level.
+    pub fn set_subdivision_level(&mut self, device: &wgpu::Device, level: u32) {
+        let (vertices, indices, _) = self.triangles(level);
+        let (vertex_buffer, index_buffer) = create_buffers(device, &self.name, &vertices, &indices);
+        self.vertex_buffer = vertex_buffer;
+        self.index_buffer = index_buffer;
+        self.num_elements = indices.len() as u32;
+    }
+
+    /// The vertices and triangle indices at the given subdivision level, and for every triangle
+    /// whether the edges opposite its corners are polygon edges, see
+    /// [subdivision::fan_polygon_edges].
+    ///
+    /// Level 0 uses the vertices straight from the file, so hard edges in the normals are kept.
+    pub fn triangles(&self, level: u32) -> (Vec<ModelVertex>, Vec<u32>, Vec<[bool; 3]>) {
+        if level == 0 {
+            (
+                self.vertices.clone(),
+                subdivision::fan_triangulate(&self.faces),
+                subdivision::fan_polygon_edges(&self.faces),
+            )
+        } else {
+            let subdivided = self.cage.subdivide(level);
+            let (vertices, indices) = subdivided.to_triangles();
+            (
+                vertices,
+                indices,
+                subdivision::fan_polygon_edges(&subdivided.faces),
+            )
+        }
+    }
+}
+
+impl std::fmt::Debug for Mesh {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Mesh")
+            .field("name", &self.name)
+            .field("num_elements", &self.num_elements)
+            .field("material", &self.material)
+            .field("faces", &self.faces.len())
+            .field("scheme", &self.cage.scheme())
+            .finish()
+    }
+}
+
+fn create_buffers(
+    device: &wgpu::Device,
+    name: &str,
+    vertices: &[ModelVertex],
+    indices: &[u32],
+) -> (wgpu::Buffer, wgpu::Buffer) {
+    let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(&format!("{:?} Vertex Buffer", name)),
+        contents: bytemuck::cast_slice(vertices),
+        // Also read by the compute pass of crate::vectors::VertexVectors
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE,
+    });
+    let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(&format!("{:?} Index Buffer", name)),
+        contents: bytemuck::cast_slice(indices),
+        usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::STORAGE,
+    });
+    (vertex_buffer, index_buffer)
+}
+
+/// The axis aligned box around `vertices` as (min, max), an empty box at the origin if there are
+/// none.
+fn vertex_bounds<'a>(
+    vertices: impl IntoIterator<Item = &'a ModelVertex>,
+) -> (Vector3<f32>, Vector3<f32>) {
+    let mut min = Vector3::new(f32::MAX, f32::MAX, f32::MAX);
+    let mut max = Vector3::new(f32::MIN, f32::MIN, f32::MIN);
+    for vertex in vertices {
+        for axis in 0..3 {
+            min[axis] = min[axis].min(vertex.position[axis]);
+            max[axis] = max[axis].max(vertex.position[axis]);
+        }
+    }
+    if min.x > max.x {
+        // No vertices at all
+        return (Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0));
+    }
+    (min, max)
 }
 
 pub struct Model {
@@ -53,16 +388,60 @@ pub struct Model {
     pub materials: Vec<Material>,
 }
 
+impl Model {
+    /// The axis aligned box around all vertices as loaded, as (min, max).
+    ///
+    /// Subdivision only ever shrinks a mesh towards its cage, so this box holds at every level.
+    pub fn bounds(&self) -> (Vector3<f32>, Vector3<f32>) {
+        vertex_bounds(self.meshes.iter().flat_map(|mesh| &mesh.vertices))
+    }
+
+    /// Marks the meshes whose bounds are outside `frustum` or that are `hidden` as culled.
+    ///
+    /// Arguments:
+    ///
+    /// * `frustum`: The view of the camera, None keeps the meshes outside of it.
+    /// * `hidden`: Per mesh whether it is covered by others, see
+    ///   [OcclusionCulling](crate::occlusion::OcclusionCulling).
+    pub fn cull(&mut self, frustum: Option<&Frustum>, hidden: Option<&[bool]>) -> CullingStats {
+        let mut stats = CullingStats::default();
+        for (index, mesh) in self.meshes.iter_mut().enumerate() {
+            let (min, max) = mesh.bounds;
+            let outside = frustum.is_some_and(|frustum| !frustum.intersects_aabb(min, max));
+            mesh.occluded = !outside && hidden.is_some_and(|hidden| hidden[index]);
+            mesh.culled = outside || mesh.occluded;
+            if outside {
+                stats.culled += 1;
+            } else if mesh.occluded {
+                stats.occluded += 1;
+            } else {
+                stats.drawn += 1;
+            }
+        }
+        stats
+    }
+
+    /// Subdivides every mesh of the model to `level` and uploads the result.
+    pub fn set_subdivision_level(&mut self, device: &wgpu::Device, level: u32) {
+        for mesh in &mut self.meshes {
+            mesh.set_subdivision_level(device, level);
+        }
+    }
+}
+
+#[allow(dead_code)]
 pub trait DrawModel<'a> {
     fn draw_mesh(
         &mut self,
         mesh: &'a Mesh,
+        material: &'a Material,
         camera_bind_group: &'a wgpu::BindGroup,
         light_bind_group: &'a wgpu::BindGroup,
     );
     fn draw_mesh_instanced(
         &mut self,
         mesh: &'a Mesh,
+        material: &'a Material,
         instances: Range<u32>,
         camera_bind_group: &'a wgpu::BindGroup,
         light_bind_group: &'a wgpu::BindGroup,
@@ -89,15 +468,17 @@ where
     fn draw_mesh(
         &mut self,
         mesh: &'b Mesh,
+        material: &'b Material,
         camera_bind_group: &'b wgpu::BindGroup,
         light_bind_group: &'b wgpu::BindGroup,
     ) {
-        self.draw_mesh_instanced(mesh, 0..1, camera_bind_group, light_bind_group);
+        self.draw_mesh_instanced(mesh, material, 0..1, camera_bind_group, light_bind_group);
     }
 
     fn draw_mesh_instanced(
         &mut self,
         mesh: &'b Mesh,
+        material: &'b Material,
         instances: Range<u32>,
         camera_bind_group: &'b wgpu::BindGroup,
         light_bind_group: &'b wgpu::BindGroup,
@@ -106,6 +487,7 @@ where
         self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
         self.set_bind_group(0, camera_bind_group, &[]);
         self.set_bind_group(1, light_bind_group, &[]);
+        self.set_bind_group(3, &material.bind_group, &[]);
         self.draw_indexed(0..mesh.num_elements, 0, instances);
     }
     fn draw_model(
@@ -124,20 +506,21 @@ where
         camera_bind_group: &'b wgpu::BindGroup,
         light_bind_group: &'b wgpu::BindGroup,
     ) {
-        for mesh in &model.meshes {
-            self.draw_mesh_instanced(mesh, instances.clone(), camera_bind_group, light_bind_group);
+        for mesh in model.meshes.iter().filter(|mesh| !mesh.culled) {
+            let material = &model.materials[mesh.material];
+            self.draw_mesh_instanced(
+                mesh,
+                material,
+                instances.clone(),
+                camera_bind_group,
+                light_bind_group,
+            );
         }
     }
 }
 
 // model.rs
 pub trait DrawLight<'a> {
-    fn draw_light_mesh(
-        &mut self,
-        mesh: &'a Mesh,
-        camera_bind_group: &'a wgpu::BindGroup,
-        light_bind_group: &'a wgpu::BindGroup,
-    );
     fn draw_light_mesh_instanced(
         &mut self,
         mesh: &'a Mesh,
@@ -145,12 +528,6 @@ pub trait DrawLight<'a> {
         camera_bind_group: &'a wgpu::BindGroup,
         light_bind_group: &'a wgpu::BindGroup,
     );
-    fn draw_light_model(
-        &mut self,
-        model: &'a Model,
-        camera_bind_group: &'a wgpu::BindGroup,
-        light_bind_group: &'a wgpu::BindGroup,
-    );
     fn draw_light_model_instanced(
         &mut self,
         model: &'a Model,
@@ -164,15 +541,6 @@ impl<'a, 'b> DrawLight<'b> for wgpu::RenderPass<'a>
 where
     'b: 'a,
 {
-    fn draw_light_mesh(
-        &mut self,
-        mesh: &'b Mesh,
-        camera_bind_group: &'b wgpu::BindGroup,
-        light_bind_group: &'b wgpu::BindGroup,
-    ) {
-        self.draw_light_mesh_instanced(mesh, 0..1, camera_bind_group, light_bind_group);
-    }
-
     fn draw_light_mesh_instanced(
         &mut self,
         mesh: &'b Mesh,
@@ -187,14 +555,6 @@ where
         self.draw_indexed(0..mesh.num_elements, 0, instances);
     }
 
-    fn draw_light_model(
-        &mut self,
-        model: &'b Model,
-        camera_bind_group: &'b wgpu::BindGroup,
-        light_bind_group: &'b wgpu::BindGroup,
-    ) {
-        self.draw_light_model_instanced(model, 0..1, camera_bind_group, light_bind_group);
-    }
     fn draw_light_model_instanced(
         &mut self,
         model: &'b Model,
@@ -212,3 +572,33 @@ where
         }
     }
 }
+
+/// Draws only the geometry of a model, the caller binds whatever bind groups its pipeline needs.
+///
+/// Used by passes that don't shade, such as the ambient occlusion prepass.
+pub trait DrawGeometry<'a> {
+    fn draw_mesh_geometry(&mut self, mesh: &'a Mesh);
+    /// Draws the meshes that aren't culled and pass `filter`.
+    fn draw_model_geometry(&mut self, model: &'a Model, filter: impl Fn(&Mesh) -> bool);
+}
+
+impl<'a, 'b> DrawGeometry<'b> for wgpu::RenderPass<'a>
+where
+    'b: 'a,
+{
+    fn draw_mesh_geometry(&mut self, mesh: &'b Mesh) {
+        self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        self.draw_indexed(0..mesh.num_elements, 0, 0..1);
+    }
+
+    fn draw_model_geometry(&mut self, model: &'b Model, filter: impl Fn(&Mesh) -> bool) {
+        for mesh in model
+            .meshes
+            .iter()
+            .filter(|mesh| !mesh.culled && filter(mesh))
+        {
+            self.draw_mesh_geometry(mesh);
+        }
+    }
+}
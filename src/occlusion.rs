@@ -0,0 +1,356 @@
+use std::sync::mpsc;
+
+use cgmath::{InnerSpace, Vector3};
+use wgpu::util::DeviceExt;
+
+use crate::model;
+use crate::orbit_camera::OrbitCamera;
+use crate::texture;
+
+/// The corners of the 12 triangles of a box, as indices into its 8 corners, where bit 0 picks
+/// the max x, bit 1 the max y and bit 2 the max z.
+const BOX_CORNERS: [usize; 36] = [
+    0, 2, 3, 0, 3, 1, // -z
+    4, 5, 7, 4, 7, 6, // +z
+    0, 4, 6, 0, 6, 2, // -x
+    1, 3, 7, 1, 7, 5, // +x
+    0, 1, 5, 0, 5, 4, // -y
+    2, 6, 7, 2, 7, 3, // +y
+];
+const BOX_VERTICES: u32 = BOX_CORNERS.len() as u32;
+// Models with more meshes are split over several query sets, each in its own pass
+const QUERIES_PER_SET: u32 = wgpu::QUERY_SET_MAX_QUERIES;
+
+/// Controls the [OcclusionCulling].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OcclusionSettings {
+    /// Skips the meshes that were completely hidden in the last frame.
+    pub enabled: bool,
+    /// Draws the bounds of the skipped meshes in red, on top of everything.
+    pub show_occluded: bool,
+}
+
+/// Finds the meshes that are hidden behind other meshes with occlusion queries.
+///
+/// After the opaque pass the bounding box of every mesh is drawn against its depth, without
+/// writing anything, with one occlusion query per mesh. A mesh whose box had no sample pass the
+/// depth test is completely hidden. The results are read back without waiting for the GPU, so
+/// they are applied to the frame after the one they were measured in. Skipped meshes are still
+/// tested every frame, so they come back one frame after they are uncovered.
+pub struct OcclusionCulling {
+    pub settings: OcclusionSettings,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipeline: wgpu::RenderPipeline,
+    // Created for the number of meshes of the model
+    queries: Option<Queries>,
+    // Set while the results of the last queries are being mapped
+    pending: Option<mpsc::Receiver<Result<(), wgpu::BufferAsyncError>>>,
+    // Whether queries were recorded into the frame that is about to be submitted
+    recorded: bool,
+    // Per mesh whether its box was visible in the last results
+    visible: Vec<bool>,
+    // Per mesh whether it is skipped in the next frame, empty if nothing is skipped
+    hidden: Vec<bool>,
+}
+
+struct Queries {
+    // QUERIES_PER_SET meshes per set, the last one takes the rest
+    query_sets: Vec<wgpu::QuerySet>,
+    // BOX_VERTICES positions per mesh
+    boxes: wgpu::Buffer,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    count: u32,
+}
+
+impl OcclusionCulling {
+    /// Arguments:
+    ///
+    /// * `camera_bind_group_layout`: The layout of the bind group passed to
+    ///   [OcclusionCulling::render].
+    pub fn new(
+        device: &wgpu::Device,
+        sample_count: u32,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Occlusion Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = create_pipeline(device, &pipeline_layout, sample_count);
+
+        Self {
+            settings: OcclusionSettings::default(),
+            pipeline_layout,
+            pipeline,
+            queries: None,
+            pending: None,
+            recorded: false,
+            visible: Vec::new(),
+            hidden: Vec::new(),
+        }
+    }
+
+    /// Recreates the pipeline for the MSAA sample count of the depth buffer.
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        self.pipeline = create_pipeline(device, &self.pipeline_layout, sample_count);
+    }
+
+    /// Picks up the results of the last queries if they have arrived and decides which meshes of
+    /// `model` are skipped. The device has to be polled for the results to arrive.
+    ///
+    /// Returns whether another frame is needed, because the skipped meshes changed or results
+    /// are still on their way.
+    pub fn update(&mut self, camera: &OrbitCamera, model: &model::Model) -> bool {
+        if let Some(receiver) = &self.pending {
+            match receiver.try_recv() {
+                Ok(result) => {
+                    if let (Ok(()), Some(queries)) = (result, &self.queries) {
+                        let slice = queries.readback_buffer.slice(..);
+                        let samples: Vec<u64> =
+                            bytemuck::cast_slice(&slice.get_mapped_range()).to_vec();
+                        queries.readback_buffer.unmap();
+                        self.visible = samples.iter().map(|&count| count > 0).collect();
+                    }
+                    self.pending = None;
+                }
+                Err(mpsc::TryRecvError::Empty) => {}
+                Err(mpsc::TryRecvError::Disconnected) => self.pending = None,
+            }
+        }
+
+        let previous = std::mem::take(&mut self.hidden);
+        if !self.settings.enabled {
+            return !previous.is_empty();
+        }
+        if self.visible.len() != model.meshes.len() {
+            // The first results of the model are still to be measured
+            return true;
+        }
+        // The near plane cuts into the box of a mesh around the camera, the rest of the box can
+        // then be hidden while the mesh isn't
+        let tan = (camera.fovy.0 / 2.0).tan();
+        let reach = camera.znear * (1.0 + tan * tan * (1.0 + camera.aspect * camera.aspect)).sqrt();
+        let margin = Vector3::new(reach, reach, reach);
+        self.hidden = model
+            .meshes
+            .iter()
+            .zip(&self.visible)
+            .map(|(mesh, &visible)| {
+                let (min, max) = mesh.bounds;
+                let (min, max) = (min - margin, max + margin);
+                let around_eye = (0..3)
+                    .all(|axis| min[axis] <= camera.eye[axis] && camera.eye[axis] <= max[axis]);
+                !visible && !around_eye
+            })
+            .collect();
+        // Without the queries of a new frame nothing changes, which ends the redraws once the
+        // skipped meshes have settled
+        self.hidden != previous || self.pending.is_some()
+    }
+
+    /// Per mesh whether it is hidden and can be skipped, None if no mesh is skipped.
+    pub fn hidden(&self) -> Option<&[bool]> {
+        (!self.hidden.is_empty()).then_some(self.hidden.as_slice())
+    }
+
+    /// Tests the bounds of every mesh of `model` against the depth of the opaque pass. Does
+    /// nothing while the results of the last test are still being read back.
+    ///
+    /// Arguments:
+    ///
+    /// * `depth_view`: The depth buffer of the main pass, only read.
+    pub fn render(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        model: &model::Model,
+        depth_view: &wgpu::TextureView,
+        camera_bind_group: &wgpu::BindGroup,
+    ) {
+        let count = model.meshes.len() as u32;
+        if !self.settings.enabled || self.pending.is_some() || count == 0 {
+            return;
+        }
+        // The bounds don't change with the subdivision level, only with another model
+        if self
+            .queries
+            .as_ref()
+            .is_none_or(|queries| queries.count != count)
+        {
+            self.queries = Some(Queries::new(device, model));
+        }
+        let Some(queries) = &self.queries else {
+            return;
+        };
+
+        for (set, query_set) in queries.query_sets.iter().enumerate() {
+            let first = set as u32 * QUERIES_PER_SET;
+            let set_count = (count - first).min(QUERIES_PER_SET);
+            {
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Occlusion Pass"),
+                    color_attachments: &[],
+                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                        view: depth_view,
+                        depth_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: wgpu::StoreOp::Store,
+                        }),
+                        stencil_ops: None,
+                    }),
+                    occlusion_query_set: Some(query_set),
+                    timestamp_writes: None,
+                });
+                render_pass.set_pipeline(&self.pipeline);
+                render_pass.set_bind_group(0, camera_bind_group, &[]);
+                render_pass.set_vertex_buffer(0, queries.boxes.slice(..));
+                for query in 0..set_count {
+                    let index = first + query;
+                    render_pass.begin_occlusion_query(query);
+                    render_pass.draw(index * BOX_VERTICES..(index + 1) * BOX_VERTICES, 0..1);
+                    render_pass.end_occlusion_query();
+                }
+            }
+
+            // A full set of u64 results keeps the offset of the next one aligned
+            let offset = first as wgpu::BufferAddress * std::mem::size_of::<u64>() as u64;
+            encoder.resolve_query_set(query_set, 0..set_count, &queries.resolve_buffer, offset);
+        }
+
+        encoder.copy_buffer_to_buffer(
+            &queries.resolve_buffer,
+            0,
+            &queries.readback_buffer,
+            0,
+            queries.readback_buffer.size(),
+        );
+        self.recorded = true;
+    }
+
+    /// Starts reading back the results of the queries [OcclusionCulling::render] recorded, once
+    /// the frame they are in has been submitted.
+    pub fn read_back(&mut self) {
+        if !std::mem::take(&mut self.recorded) {
+            return;
+        }
+        let Some(queries) = &self.queries else {
+            return;
+        };
+        let (sender, receiver) = mpsc::channel();
+        queries
+            .readback_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                let _ = sender.send(result);
+            });
+        self.pending = Some(receiver);
+    }
+}
+
+impl Queries {
+    fn new(device: &wgpu::Device, model: &model::Model) -> Self {
+        let count = model.meshes.len() as u32;
+        // A little larger than the meshes, so the boxes of flat meshes don't fight with them in
+        // the depth test
+        let (model_min, model_max) = model.bounds();
+        let margin = (model_max - model_min).magnitude() * 1e-3 + 1e-5;
+        let margin = Vector3::new(margin, margin, margin);
+        let positions = model
+            .meshes
+            .iter()
+            .flat_map(|mesh| {
+                let (min, max) = (mesh.bounds.0 - margin, mesh.bounds.1 + margin);
+                BOX_CORNERS.map(|corner| {
+                    [
+                        if corner & 1 != 0 { max.x } else { min.x },
+                        if corner & 2 != 0 { max.y } else { min.y },
+                        if corner & 4 != 0 { max.z } else { min.z },
+                    ]
+                })
+            })
+            .collect::<Vec<_>>();
+        let boxes = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Occlusion Box Buffer"),
+            contents: bytemuck::cast_slice(&positions),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        let query_sets = (0..count.div_ceil(QUERIES_PER_SET))
+            .map(|set| {
+                device.create_query_set(&wgpu::QuerySetDescriptor {
+                    label: Some("Occlusion Query Set"),
+                    ty: wgpu::QueryType::Occlusion,
+                    count: (count - set * QUERIES_PER_SET).min(QUERIES_PER_SET),
+                })
+            })
+            .collect();
+        // One u64 sample count per query
+        let size = count as wgpu::BufferAddress * std::mem::size_of::<u64>() as wgpu::BufferAddress;
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Occlusion Resolve Buffer"),
+            size,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Occlusion Readback Buffer"),
+            size,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            query_sets,
+            boxes,
+            resolve_buffer,
+            readback_buffer,
+            count,
+        }
+    }
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    sample_count: u32,
+) -> wgpu::RenderPipeline {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Occlusion Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("occlusion.wgsl").into()),
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Occlusion Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[wgpu::VertexBufferLayout {
+                array_stride: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                step_mode: wgpu::VertexStepMode::Vertex,
+                attributes: &wgpu::vertex_attr_array![0 => Float32x3],
+            }],
+        },
+        fragment: None,
+        primitive: wgpu::PrimitiveState {
+            // From inside a box only its back faces are left
+            cull_mode: None,
+            ..Default::default()
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_STENCIL_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::LessEqual,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
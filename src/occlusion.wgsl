@@ -0,0 +1,14 @@
+// The bounding boxes tested by the occlusion queries, see occlusion.rs
+
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+// Only the depth test matters, so there is no fragment shader
+@vertex
+fn vs_main(@location(0) position: vec3<f32>) -> @builtin(position) vec4<f32> {
+    return camera.view_proj * vec4<f32>(position, 1.0);
+}
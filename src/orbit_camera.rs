@@ -6,8 +6,8 @@ use crate::camera::Camera;
 pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
     1.0, 0.0, 0.0, 0.0,
     0.0, 1.0, 0.0, 0.0,
-    0.0, 0.0, 0.5, 0.5,
-    0.0, 0.0, 0.0, 1.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
 );
 
 /// An [OrbitCamera] only permits rotation of the eye on a spherical shell around a target.
@@ -46,16 +46,59 @@ pub struct OrbitCamera {
 
     /// The far clipping plane of the camera.
     pub zfar: f32,
+
+    /// If set only this tile of the view is projected, see [ProjectionTile].
+    pub tile: Option<ProjectionTile>,
+}
+
+/// One cell of a grid of sub-frustums that together cover the cameras' view.
+///
+/// Rendering every tile with an off-center projection and stitching the results gives an
+/// image that is larger than a single render target is allowed to be.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProjectionTile {
+    /// The column of the tile, counted from the left.
+    pub column: u32,
+
+    /// The row of the tile, counted from the top.
+    pub row: u32,
+
+    /// The number of columns in the grid.
+    pub columns: u32,
+
+    /// The number of rows in the grid.
+    pub rows: u32,
+
+    /// How much more than the tile is projected beyond each of its edges, as a fraction of the
+    /// tile width and height. Screen space effects can then read past the edge of the tile.
+    pub overscan: [f32; 2],
+}
+
+impl ProjectionTile {
+    /// Builds the matrix that scales and offsets clip space so that this tile fills the viewport.
+    ///
+    /// Applied after the full projection this gives the off-center projection of the tile.
+    pub fn build_crop_matrix(&self) -> Matrix4<f32> {
+        let scale_x = self.columns as f32 / (1.0 + 2.0 * self.overscan[0]);
+        let scale_y = self.rows as f32 / (1.0 + 2.0 * self.overscan[1]);
+        // Center of the tile in normalized device coordinates, rows go from top to bottom
+        let center_x = -1.0 + (2 * self.column + 1) as f32 / self.columns as f32;
+        let center_y = 1.0 - (2 * self.row + 1) as f32 / self.rows as f32;
+
+        #[rustfmt::skip]
+        let crop = Matrix4::new(
+            scale_x, 0.0, 0.0, 0.0,
+            0.0, scale_y, 0.0, 0.0,
+            0.0, 0.0, 1.0, 0.0,
+            -scale_x * center_x, -scale_y * center_y, 0.0, 1.0,
+        );
+        crop
+    }
 }
 
 impl Camera for OrbitCamera {
     fn build_view_projection_matrix(&self) -> Matrix4<f32> {
-        let eye = Point3::from_vec(self.eye);
-        let target = Point3::from_vec(self.target);
-        let view = Matrix4::look_at_rh(eye, target, self.up);
-        let proj =
-            OPENGL_TO_WGPU_MATRIX * perspective(self.fovy, self.aspect, self.znear, self.zfar);
-        proj * view
+        self.build_projection_matrix() * self.build_view_matrix()
     }
 }
 
@@ -82,6 +125,7 @@ impl OrbitCamera {
             fovy: cgmath::Rad(std::f32::consts::PI / 2.0),
             znear: 0.1,
             zfar: 1000.0,
+            tile: None,
         };
         camera.update();
         camera
@@ -180,6 +224,23 @@ impl OrbitCamera {
     pub fn resize_projection(&mut self, width: u32, height: u32) {
         self.aspect = width as f32 / height as f32;
     }
+
+    /// Builds the matrix that transforms world space into view space.
+    pub fn build_view_matrix(&self) -> Matrix4<f32> {
+        let eye = Point3::from_vec(self.eye);
+        let target = Point3::from_vec(self.target);
+        Matrix4::look_at_rh(eye, target, self.up)
+    }
+
+    /// Builds the projection matrix, restricted to `tile` if one is set.
+    pub fn build_projection_matrix(&self) -> Matrix4<f32> {
+        let proj =
+            OPENGL_TO_WGPU_MATRIX * perspective(self.fovy, self.aspect, self.znear, self.zfar);
+        match self.tile {
+            Some(tile) => tile.build_crop_matrix() * proj,
+            None => proj,
+        }
+    }
 }
 
 /// The boundaries for how an [OrbitCamera] can be rotated.
@@ -236,9 +297,9 @@ fn calculate_cartesian_eye_position(
     distance: f32,
     target: Vector3<f32>,
 ) -> Vector3<f32> {
-    return Vector3::new(
+    Vector3::new(
         distance * yaw.sin() * pitch.cos(),
         distance * pitch.sin(),
         distance * yaw.cos() * pitch.cos(),
-    ) + target;
+    ) + target
 }
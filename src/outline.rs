@@ -0,0 +1,464 @@
+use wgpu::util::DeviceExt;
+
+use crate::clipping;
+use crate::model::{self, Vertex};
+use crate::texture;
+
+/// The widest outline in pixels. Must match `MAX_WIDTH` in outline.wgsl.
+pub const MAX_WIDTH: f32 = 8.0;
+
+const MASK_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg8Unorm;
+// The stencil value of the pixels covered by a selected mesh
+const SELECTED: u32 = 1;
+
+/// Controls what the [Outline] looks like.
+#[derive(Debug, Clone, Copy)]
+pub struct OutlineSettings {
+    /// Linear RGB.
+    pub color: [f32; 3],
+    /// The opacity of the outline around the parts that are covered by other geometry.
+    pub hidden_opacity: f32,
+    /// The width in pixels, up to [MAX_WIDTH].
+    pub width: f32,
+}
+
+impl Default for OutlineSettings {
+    fn default() -> Self {
+        Self {
+            color: [1.0, 0.45, 0.0],
+            hidden_opacity: 0.35,
+            width: 3.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct OutlineUniform {
+    color: [f32; 4],
+    hidden_color: [f32; 4],
+    width: f32,
+    _padding: [f32; 3],
+}
+
+/// Draws a silhouette of constant width around the selected meshes, over the finished frame.
+///
+/// A mask pass draws the selected meshes twice into a two channel texture: once without a depth
+/// test, which also sets their pixels in the stencil buffer of the main pass, and once tested
+/// against the depth of the main pass, so the mask knows which parts are visible. A fullscreen
+/// pass then runs only where the stencil is not set and colors the pixels that have a selected
+/// one within the outline width. Where the closest selected pixel is covered by other geometry
+/// the outline is more transparent.
+pub struct Outline {
+    pub settings: OutlineSettings,
+    buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    mask_pipeline_layout: wgpu::PipelineLayout,
+    outline_pipeline_layout: wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+    // Writes the green channel of the mask and the stencil wherever a selected mesh is
+    silhouette_pipeline: wgpu::RenderPipeline,
+    // Writes the red channel of the mask where a selected mesh is visible
+    visible_pipeline: wgpu::RenderPipeline,
+    outline_pipeline: wgpu::RenderPipeline,
+    mask: wgpu::TextureView,
+    // The multisampled mask that is resolved into `mask`, if the main pass uses MSAA
+    msaa_mask: Option<wgpu::TextureView>,
+}
+
+impl Outline {
+    /// Arguments:
+    ///
+    /// * `camera_bind_group_layout`: The layout of the bind group passed to [Outline::render].
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let settings = OutlineSettings::default();
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Outline Buffer"),
+            contents: bytemuck::cast_slice(&[OutlineUniform::new(&settings)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("outline_bind_group_layout"),
+        });
+        let (mask, msaa_mask) = create_masks(device, width, height, sample_count);
+        let bind_group = create_bind_group(device, &bind_group_layout, &buffer, &mask);
+
+        let mask_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Outline Mask Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let outline_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Outline Pipeline Layout"),
+                bind_group_layouts: &[&bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let (silhouette_pipeline, visible_pipeline, outline_pipeline) = create_pipelines(
+            device,
+            &mask_pipeline_layout,
+            &outline_pipeline_layout,
+            format,
+            sample_count,
+        );
+
+        Self {
+            settings,
+            buffer,
+            bind_group_layout,
+            bind_group,
+            mask_pipeline_layout,
+            outline_pipeline_layout,
+            format,
+            sample_count,
+            silhouette_pipeline,
+            visible_pipeline,
+            outline_pipeline,
+            mask,
+            msaa_mask,
+        }
+    }
+
+    /// Recreates the pipelines for the MSAA sample count of the render pass. The mask follows
+    /// with the next [Outline::resize].
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        self.sample_count = sample_count;
+        (
+            self.silhouette_pipeline,
+            self.visible_pipeline,
+            self.outline_pipeline,
+        ) = create_pipelines(
+            device,
+            &self.mask_pipeline_layout,
+            &self.outline_pipeline_layout,
+            self.format,
+            sample_count,
+        );
+    }
+
+    /// Recreates the mask for a frame of `width` x `height` pixels.
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        (self.mask, self.msaa_mask) = create_masks(device, width, height, self.sample_count);
+        self.bind_group =
+            create_bind_group(device, &self.bind_group_layout, &self.buffer, &self.mask);
+    }
+
+    /// Uploads the settings.
+    pub fn update(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.buffer,
+            0,
+            bytemuck::cast_slice(&[OutlineUniform::new(&self.settings)]),
+        );
+    }
+
+    /// Outlines the meshes of `model` at the indices in `selection`. Does nothing if none are
+    /// selected.
+    ///
+    /// Arguments:
+    ///
+    /// * `view`, `resolve_target`: The color attachment of the main pass, whose contents are
+    ///   kept.
+    /// * `depth_view`: The depth and stencil buffer of the main pass, see
+    ///   [texture::Texture::create_depth_stencil_texture].
+    #[allow(clippy::too_many_arguments)]
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        model: &model::Model,
+        selection: &[usize],
+        view: &wgpu::TextureView,
+        resolve_target: Option<&wgpu::TextureView>,
+        depth_view: &wgpu::TextureView,
+        camera_bind_group: &wgpu::BindGroup,
+    ) {
+        let meshes = selection
+            .iter()
+            .filter_map(|&index| model.meshes.get(index))
+            .collect::<Vec<_>>();
+        if meshes.is_empty() {
+            return;
+        }
+
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Outline Mask Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: self.msaa_mask.as_ref().unwrap_or(&self.mask),
+                    resolve_target: self.msaa_mask.as_ref().map(|_| &self.mask),
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: depth_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            render_pass.set_bind_group(0, camera_bind_group, &[]);
+            render_pass.set_stencil_reference(SELECTED);
+            for pipeline in [&self.silhouette_pipeline, &self.visible_pipeline] {
+                render_pass.set_pipeline(pipeline);
+                for mesh in &meshes {
+                    render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                    render_pass
+                        .set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                    render_pass.draw_indexed(0..mesh.num_elements, 0, 0..1);
+                }
+            }
+        }
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Outline Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                }),
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        render_pass.set_pipeline(&self.outline_pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_stencil_reference(SELECTED);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+impl OutlineUniform {
+    fn new(settings: &OutlineSettings) -> Self {
+        let [r, g, b] = settings.color;
+        Self {
+            color: [r, g, b, 1.0],
+            hidden_color: [r, g, b, settings.hidden_opacity],
+            width: settings.width.clamp(0.0, MAX_WIDTH),
+            _padding: [0.0; 3],
+        }
+    }
+}
+
+/// Creates the mask and, with MSAA, the multisampled mask that is resolved into it.
+fn create_masks(
+    device: &wgpu::Device,
+    width: u32,
+    height: u32,
+    sample_count: u32,
+) -> (wgpu::TextureView, Option<wgpu::TextureView>) {
+    let mask = |sample_count, usage| {
+        device
+            .create_texture(&wgpu::TextureDescriptor {
+                label: Some("Outline Mask"),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count,
+                dimension: wgpu::TextureDimension::D2,
+                format: MASK_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | usage,
+                view_formats: &[],
+            })
+            .create_view(&wgpu::TextureViewDescriptor::default())
+    };
+    let resolved = mask(1, wgpu::TextureUsages::TEXTURE_BINDING);
+    let multisampled = (sample_count > 1).then(|| mask(sample_count, wgpu::TextureUsages::empty()));
+    (resolved, multisampled)
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    buffer: &wgpu::Buffer,
+    mask: &wgpu::TextureView,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(mask),
+            },
+        ],
+        label: Some("outline_bind_group"),
+    })
+}
+
+/// Creates the silhouette, visible and outline pipelines.
+fn create_pipelines(
+    device: &wgpu::Device,
+    mask_layout: &wgpu::PipelineLayout,
+    outline_layout: &wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+) -> (
+    wgpu::RenderPipeline,
+    wgpu::RenderPipeline,
+    wgpu::RenderPipeline,
+) {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Outline Shader"),
+        source: clipping::shader_source(include_str!("outline.wgsl")),
+    });
+    let multisample = wgpu::MultisampleState {
+        count: sample_count,
+        mask: !0,
+        alpha_to_coverage_enabled: false,
+    };
+    let stencil = |compare, pass_op, write_mask| {
+        let face = wgpu::StencilFaceState {
+            compare,
+            fail_op: wgpu::StencilOperation::Keep,
+            depth_fail_op: wgpu::StencilOperation::Keep,
+            pass_op,
+        };
+        wgpu::StencilState {
+            front: face,
+            back: face,
+            read_mask: 0xff,
+            write_mask,
+        }
+    };
+    let mask_pipeline = |write_mask, depth_compare, stencil| {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Outline Mask Pipeline"),
+            layout: Some(mask_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_mask",
+                buffers: &[model::ModelVertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_mask",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: MASK_FORMAT,
+                    blend: None,
+                    write_mask,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                cull_mode: Some(wgpu::Face::Back),
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_STENCIL_FORMAT,
+                depth_write_enabled: false,
+                depth_compare,
+                stencil,
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample,
+            multiview: None,
+        })
+    };
+
+    let silhouette = mask_pipeline(
+        wgpu::ColorWrites::GREEN,
+        wgpu::CompareFunction::Always,
+        stencil(
+            wgpu::CompareFunction::Always,
+            wgpu::StencilOperation::Replace,
+            0xff,
+        ),
+    );
+    let visible = mask_pipeline(
+        wgpu::ColorWrites::RED,
+        // The model was drawn at the same depth in the main pass
+        wgpu::CompareFunction::LessEqual,
+        wgpu::StencilState::default(),
+    );
+    let outline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Outline Pipeline"),
+        layout: Some(outline_layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_outline",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_outline",
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_STENCIL_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::Always,
+            stencil: stencil(
+                wgpu::CompareFunction::NotEqual,
+                wgpu::StencilOperation::Keep,
+                0,
+            ),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample,
+        multiview: None,
+    });
+    (silhouette, visible, outline)
+}
@@ -0,0 +1,91 @@
+// The outline around the selected meshes, see outline.rs
+
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+// See clip.wgsl
+@group(0) @binding(5)
+var<uniform> clip_planes: ClipPlanes;
+
+// See outline::OutlineUniform
+struct Outline {
+    color: vec4<f32>,
+    hidden_color: vec4<f32>,
+    // In pixels
+    width: f32,
+}
+@group(0) @binding(0)
+var<uniform> outline: Outline;
+// Red where a selected mesh is visible, green wherever one is, covered or not
+@group(0) @binding(1)
+var mask: texture_2d<f32>;
+
+// Must match outline::MAX_WIDTH
+const MAX_WIDTH: i32 = 8;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+}
+
+struct MaskOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+}
+
+@vertex
+fn vs_mask(model: VertexInput) -> MaskOutput {
+    var out: MaskOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(model.position, 1.0);
+    out.world_position = model.position;
+    return out;
+}
+
+// The pipelines only write the channel they are for
+@fragment
+fn fs_mask(in: MaskOutput) -> @location(0) vec4<f32> {
+    if clipped(clip_planes, in.world_position) {
+        discard;
+    }
+    return vec4<f32>(1.0, 1.0, 0.0, 0.0);
+}
+
+// One triangle that covers the whole screen
+@vertex
+fn vs_outline(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+// Only runs outside the selected meshes, the stencil test rejects the pixels they cover. Looks for
+// the closest selected pixel within the outline width.
+@fragment
+fn fs_outline(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let size = vec2<i32>(textureDimensions(mask));
+    let center = vec2<i32>(position.xy);
+    let radius = min(i32(ceil(outline.width)), MAX_WIDTH);
+    var visible = 0.0;
+    var selected = 0.0;
+    for (var y = -radius; y <= radius; y++) {
+        for (var x = -radius; x <= radius; x++) {
+            let texel = center + vec2<i32>(x, y);
+            if any(texel < vec2<i32>(0)) || any(texel >= size) {
+                continue;
+            }
+            // Soft by half a pixel at the outer edge
+            let weight = clamp(outline.width + 0.5 - length(vec2<f32>(f32(x), f32(y))), 0.0, 1.0);
+            let coverage = textureLoad(mask, texel, 0).rg * weight;
+            visible = max(visible, coverage.r);
+            selected = max(selected, coverage.g);
+        }
+    }
+    if selected <= 0.0 {
+        discard;
+    }
+    // Dimmer where the closest part of the selection is covered by other geometry
+    let color = mix(outline.hidden_color, outline.color, visible / selected);
+    return vec4<f32>(color.rgb, color.a * selected);
+}
@@ -0,0 +1,784 @@
+use wgpu::util::DeviceExt;
+
+/// The format the scene is rendered in before the post-processing maps it to the output.
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+/// The most downsampled levels the bloom is spread over, each half the size of the previous.
+pub const BLOOM_LEVELS: u32 = 6;
+
+/// The size of the identity LUT used until another one is loaded.
+const DEFAULT_LUT_SIZE: u32 = 16;
+
+/// Maps the exposed HDR colors to the displayable range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tonemapper {
+    /// Clips everything above 1, like the viewer did before it rendered in HDR.
+    None = 0,
+    /// A fit of the ACES filmic curve, contrasty and saturated.
+    Aces = 1,
+    /// AgX, which desaturates bright colors towards white instead of skewing their hue.
+    Agx = 2,
+}
+
+impl Tonemapper {
+    /// The next tonemapper, wrapping around to [Tonemapper::None].
+    pub fn next(self) -> Self {
+        match self {
+            Self::None => Self::Aces,
+            Self::Aces => Self::Agx,
+            Self::Agx => Self::None,
+        }
+    }
+}
+
+impl std::str::FromStr for Tonemapper {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "none" => Ok(Self::None),
+            "aces" => Ok(Self::Aces),
+            "agx" => Ok(Self::Agx),
+            _ => anyhow::bail!("unknown tonemapper {:?}, expected none, aces or agx", s),
+        }
+    }
+}
+
+/// Controls the passes of the [PostProcessing] chain.
+#[derive(Debug, Clone, Copy)]
+pub struct PostSettings {
+    /// Exposure compensation in stops, on top of the automatic exposure if that is enabled.
+    pub exposure: f32,
+    /// Adapts the exposure so the average luminance of the frame becomes middle grey.
+    pub auto_exposure: bool,
+    /// How fast the automatic exposure follows changes, per second. Infinity adapts at once.
+    pub adaptation_speed: f32,
+    pub tonemapper: Tonemapper,
+    /// Adds a glow around everything brighter than `bloom_threshold` after the exposure.
+    pub bloom: bool,
+    pub bloom_threshold: f32,
+    pub bloom_intensity: f32,
+    /// Smooths edges that MSAA misses, such as the ones inside textures.
+    pub fxaa: bool,
+    /// Maps the tonemapped colors through the color grading LUT.
+    pub color_grading: bool,
+}
+
+impl Default for PostSettings {
+    fn default() -> Self {
+        Self {
+            exposure: 0.0,
+            auto_exposure: false,
+            adaptation_speed: 1.5,
+            tonemapper: Tonemapper::Aces,
+            bloom: true,
+            bloom_threshold: 1.0,
+            bloom_intensity: 0.1,
+            fxaa: false,
+            color_grading: false,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PostUniform {
+    // 2 to the power of PostSettings::exposure
+    exposure: f32,
+    auto_exposure: u32,
+    // How far the measured exposure moves towards the target this frame, from 0 to 1
+    adaptation: f32,
+    tonemapper: u32,
+    bloom: u32,
+    bloom_threshold: f32,
+    bloom_intensity: f32,
+    color_grading: u32,
+    lut_size: f32,
+    _padding: [f32; 3],
+}
+
+/// Turns the HDR frame into the final image.
+///
+/// The scene is drawn into [PostProcessing::hdr_view] and [PostProcessing::render] runs these
+/// passes in order, skipping the disabled ones:
+///
+/// 1. Auto exposure: a compute pass measures the average luminance and adapts the exposure.
+/// 2. Bloom: the bright parts are downsampled into a chain of smaller textures and blurred
+///    back up.
+/// 3. Tonemapping: applies the exposure, adds the bloom, maps the colors with the
+///    [Tonemapper] and the color grading LUT.
+/// 4. FXAA on the tonemapped image.
+pub struct PostProcessing {
+    pub settings: PostSettings,
+    format: wgpu::TextureFormat,
+    width: u32,
+    height: u32,
+    buffer: wgpu::Buffer,
+    // The exposure measured by the auto exposure, kept on the GPU between frames
+    exposure_buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+    lut: wgpu::TextureView,
+    lut_size: u32,
+    last_update: Option<instant::Instant>,
+    /// Keeps the measured exposure, for example while parts of the view are rendered as tiles.
+    pub exposure_locked: bool,
+    /// Passes the HDR colors through unchanged, so debug views keep their exact colors.
+    pub passthrough: bool,
+    pass_bind_group_layout: wgpu::BindGroupLayout,
+    composite_bind_group_layout: wgpu::BindGroupLayout,
+    exposure_pipeline: wgpu::ComputePipeline,
+    prefilter_pipeline: wgpu::RenderPipeline,
+    downsample_pipeline: wgpu::RenderPipeline,
+    upsample_pipeline: wgpu::RenderPipeline,
+    tonemap_pipeline: wgpu::RenderPipeline,
+    fxaa_pipeline: wgpu::RenderPipeline,
+    targets: Targets,
+}
+
+/// Everything that depends on the size of the frame.
+struct Targets {
+    hdr: wgpu::TextureView,
+    // Half the size of the frame and smaller
+    bloom: Vec<wgpu::TextureView>,
+    // The tonemapped image when FXAA runs after it
+    ldr: wgpu::TextureView,
+    exposure_bind_group: wgpu::BindGroup,
+    prefilter_bind_group: wgpu::BindGroup,
+    // The source of every bloom level is the level above it
+    bloom_bind_groups: Vec<wgpu::BindGroup>,
+    tonemap_bind_group: wgpu::BindGroup,
+    fxaa_bind_group: wgpu::BindGroup,
+}
+
+impl PostProcessing {
+    /// Arguments:
+    ///
+    /// * `format`: The format of the views [PostProcessing::render] writes to.
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let settings = PostSettings::default();
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Post Buffer"),
+            contents: bytemuck::cast_slice(&[PostUniform::new(&settings, 1.0, DEFAULT_LUT_SIZE)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let exposure_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Exposure Buffer"),
+            contents: bytemuck::cast_slice(&[1.0f32]),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let lut = create_lut(
+            device,
+            queue,
+            DEFAULT_LUT_SIZE,
+            &identity_lut(DEFAULT_LUT_SIZE),
+        );
+
+        let texture_entry = |binding, view_dimension| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let pass_entries = [
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+            texture_entry(2, wgpu::TextureViewDimension::D2),
+            wgpu::BindGroupLayoutEntry {
+                binding: 3,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ];
+        let pass_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &pass_entries,
+                label: Some("post_pass_bind_group_layout"),
+            });
+        // The tonemapping pass also reads the bloom and the LUT. They can't be in the layout of
+        // the bloom passes, which render into the first bloom level.
+        let composite_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    pass_entries[0],
+                    pass_entries[1],
+                    pass_entries[2],
+                    pass_entries[3],
+                    texture_entry(4, wgpu::TextureViewDimension::D2),
+                    texture_entry(5, wgpu::TextureViewDimension::D3),
+                ],
+                label: Some("post_composite_bind_group_layout"),
+            });
+
+        let exposure_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Exposure Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("exposure.wgsl").into()),
+        });
+        // The layout is derived from the bindings the shader uses
+        let exposure_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Exposure Pipeline"),
+            layout: None,
+            module: &exposure_shader,
+            entry_point: "measure_exposure",
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Post Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("post.wgsl").into()),
+        });
+        let pass_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Post Pipeline Layout"),
+            bind_group_layouts: &[&pass_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let composite_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Post Composite Pipeline Layout"),
+                bind_group_layouts: &[&composite_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let pipeline = |layout, entry_point, format, blend| {
+            create_pipeline(device, layout, &shader, entry_point, format, blend)
+        };
+        let prefilter_pipeline = pipeline(
+            &pass_pipeline_layout,
+            "fs_bloom_prefilter",
+            HDR_FORMAT,
+            None,
+        );
+        let downsample_pipeline = pipeline(
+            &pass_pipeline_layout,
+            "fs_bloom_downsample",
+            HDR_FORMAT,
+            None,
+        );
+        // Every level is added onto the level above it
+        let additive = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::One,
+            dst_factor: wgpu::BlendFactor::One,
+            operation: wgpu::BlendOperation::Add,
+        };
+        let upsample_pipeline = pipeline(
+            &pass_pipeline_layout,
+            "fs_bloom_upsample",
+            HDR_FORMAT,
+            Some(wgpu::BlendState {
+                color: additive,
+                alpha: additive,
+            }),
+        );
+        let tonemap_pipeline = pipeline(&composite_pipeline_layout, "fs_tonemap", format, None);
+        let fxaa_pipeline = pipeline(&pass_pipeline_layout, "fs_fxaa", format, None);
+
+        let targets = Targets::new(
+            device,
+            &pass_bind_group_layout,
+            &composite_bind_group_layout,
+            &exposure_pipeline,
+            Resources {
+                buffer: &buffer,
+                exposure_buffer: &exposure_buffer,
+                sampler: &sampler,
+                lut: &lut,
+            },
+            format,
+            width,
+            height,
+        );
+
+        Self {
+            settings,
+            format,
+            width,
+            height,
+            buffer,
+            exposure_buffer,
+            sampler,
+            lut,
+            lut_size: DEFAULT_LUT_SIZE,
+            last_update: None,
+            exposure_locked: false,
+            passthrough: false,
+            pass_bind_group_layout,
+            composite_bind_group_layout,
+            exposure_pipeline,
+            prefilter_pipeline,
+            downsample_pipeline,
+            upsample_pipeline,
+            tonemap_pipeline,
+            fxaa_pipeline,
+            targets,
+        }
+    }
+
+    /// How many pixels away the bloom and FXAA of a pixel read from.
+    pub fn reach(&self) -> u32 {
+        // Every bloom level reads 2 texels of the larger level on the way down and 1 texel of the
+        // smaller level on the way up, which adds up to about 4 texels of the smallest level
+        let bloom = if self.settings.bloom {
+            4 << BLOOM_LEVELS
+        } else {
+            0
+        };
+        // Half of FXAA_SPAN_MAX in post.wgsl
+        let fxaa = if self.settings.fxaa { 4 } else { 0 };
+        bloom + fxaa
+    }
+
+    /// Recreates the render targets for a frame of `width` x `height` pixels.
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        self.create_targets(device);
+    }
+
+    /// Replaces the color grading LUT.
+    ///
+    /// Arguments:
+    ///
+    /// * `size`: The number of entries along each axis.
+    /// * `entries`: `size`³ display encoded RGB colors, red changing fastest and blue slowest.
+    pub fn set_lut(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        size: u32,
+        entries: &[[f32; 3]],
+    ) {
+        self.lut = create_lut(device, queue, size, entries);
+        self.lut_size = size;
+        self.create_targets(device);
+    }
+
+    /// The view the scene has to be rendered into before [PostProcessing::render].
+    pub fn hdr_view(&self) -> &wgpu::TextureView {
+        &self.targets.hdr
+    }
+
+    /// Uploads the settings. The automatic exposure adapts by the time since the last call.
+    pub fn update(&mut self, queue: &wgpu::Queue) {
+        let now = instant::Instant::now();
+        let adaptation = match self.last_update {
+            // The first frame has nothing to adapt from
+            None => 1.0,
+            Some(_) if self.settings.adaptation_speed.is_infinite() => 1.0,
+            Some(last) => {
+                let dt = (now - last).as_secs_f32();
+                1.0 - (-dt * self.settings.adaptation_speed.max(0.0)).exp()
+            }
+        };
+        self.last_update = Some(now);
+
+        let uniform = PostUniform::new(&self.active_settings(), adaptation, self.lut_size);
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    /// Runs the enabled passes on [PostProcessing::hdr_view] and writes the result to `view`,
+    /// which must have the format the [PostProcessing] was created with.
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        let settings = self.active_settings();
+        if settings.auto_exposure && !self.exposure_locked {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Exposure Pass"),
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(&self.exposure_pipeline);
+            pass.set_bind_group(0, &self.targets.exposure_bind_group, &[]);
+            pass.dispatch_workgroups(1, 1, 1);
+        }
+
+        if settings.bloom {
+            let targets = &self.targets;
+            fullscreen_pass(
+                encoder,
+                "Bloom Prefilter Pass",
+                &targets.bloom[0],
+                wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                &self.prefilter_pipeline,
+                &targets.prefilter_bind_group,
+            );
+            for level in 1..targets.bloom.len() {
+                fullscreen_pass(
+                    encoder,
+                    "Bloom Downsample Pass",
+                    &targets.bloom[level],
+                    wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    &self.downsample_pipeline,
+                    &targets.bloom_bind_groups[level - 1],
+                );
+            }
+            for level in (1..targets.bloom.len()).rev() {
+                fullscreen_pass(
+                    encoder,
+                    "Bloom Upsample Pass",
+                    &targets.bloom[level - 1],
+                    wgpu::LoadOp::Load,
+                    &self.upsample_pipeline,
+                    &targets.bloom_bind_groups[level],
+                );
+            }
+        }
+
+        let tonemap_target = if settings.fxaa {
+            &self.targets.ldr
+        } else {
+            view
+        };
+        fullscreen_pass(
+            encoder,
+            "Tonemap Pass",
+            tonemap_target,
+            wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+            &self.tonemap_pipeline,
+            &self.targets.tonemap_bind_group,
+        );
+
+        if settings.fxaa {
+            fullscreen_pass(
+                encoder,
+                "FXAA Pass",
+                view,
+                wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                &self.fxaa_pipeline,
+                &self.targets.fxaa_bind_group,
+            );
+        }
+    }
+
+    /// The settings with everything turned off if [PostProcessing::passthrough] is set.
+    fn active_settings(&self) -> PostSettings {
+        if !self.passthrough {
+            return self.settings;
+        }
+        PostSettings {
+            exposure: 0.0,
+            auto_exposure: false,
+            tonemapper: Tonemapper::None,
+            bloom: false,
+            fxaa: false,
+            color_grading: false,
+            ..self.settings
+        }
+    }
+
+    fn create_targets(&mut self, device: &wgpu::Device) {
+        self.targets = Targets::new(
+            device,
+            &self.pass_bind_group_layout,
+            &self.composite_bind_group_layout,
+            &self.exposure_pipeline,
+            Resources {
+                buffer: &self.buffer,
+                exposure_buffer: &self.exposure_buffer,
+                sampler: &self.sampler,
+                lut: &self.lut,
+            },
+            self.format,
+            self.width,
+            self.height,
+        );
+    }
+}
+
+impl PostUniform {
+    fn new(settings: &PostSettings, adaptation: f32, lut_size: u32) -> Self {
+        Self {
+            exposure: settings.exposure.exp2(),
+            auto_exposure: settings.auto_exposure as u32,
+            adaptation,
+            tonemapper: settings.tonemapper as u32,
+            bloom: settings.bloom as u32,
+            bloom_threshold: settings.bloom_threshold,
+            bloom_intensity: settings.bloom_intensity,
+            color_grading: settings.color_grading as u32,
+            lut_size: lut_size as f32,
+            _padding: [0.0; 3],
+        }
+    }
+}
+
+/// The size independent resources the bind groups of the [Targets] refer to.
+struct Resources<'a> {
+    buffer: &'a wgpu::Buffer,
+    exposure_buffer: &'a wgpu::Buffer,
+    sampler: &'a wgpu::Sampler,
+    lut: &'a wgpu::TextureView,
+}
+
+impl Targets {
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        device: &wgpu::Device,
+        pass_layout: &wgpu::BindGroupLayout,
+        composite_layout: &wgpu::BindGroupLayout,
+        exposure_pipeline: &wgpu::ComputePipeline,
+        resources: Resources,
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let hdr = create_target(device, "hdr_texture", HDR_FORMAT, width, height);
+        let ldr = create_target(device, "ldr_texture", format, width, height);
+        // Stop before the levels get smaller than a few pixels
+        let levels = BLOOM_LEVELS.min(width.min(height).max(1).ilog2().saturating_sub(1).max(1));
+        let bloom = (0..levels)
+            .map(|level| {
+                create_target(
+                    device,
+                    "bloom_texture",
+                    HDR_FORMAT,
+                    (width >> (level + 1)).max(1),
+                    (height >> (level + 1)).max(1),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let common_entries = |source| {
+            [
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: resources.buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(resources.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(source),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: resources.exposure_buffer.as_entire_binding(),
+                },
+            ]
+        };
+        let pass_bind_group = |source, label| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: pass_layout,
+                entries: &common_entries(source),
+                label: Some(label),
+            })
+        };
+
+        let exposure_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &exposure_pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: resources.buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&hdr),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: resources.exposure_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("exposure_bind_group"),
+        });
+        let prefilter_bind_group = pass_bind_group(&hdr, "bloom_prefilter_bind_group");
+        let bloom_bind_groups = bloom
+            .iter()
+            .map(|level| pass_bind_group(level, "bloom_bind_group"))
+            .collect();
+        let [uniform, sampler, source, exposure] = common_entries(&hdr);
+        let tonemap_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: composite_layout,
+            entries: &[
+                uniform,
+                sampler,
+                source,
+                exposure,
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&bloom[0]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(resources.lut),
+                },
+            ],
+            label: Some("tonemap_bind_group"),
+        });
+        let fxaa_bind_group = pass_bind_group(&ldr, "fxaa_bind_group");
+
+        Self {
+            hdr,
+            bloom,
+            ldr,
+            exposure_bind_group,
+            prefilter_bind_group,
+            bloom_bind_groups,
+            tonemap_bind_group,
+            fxaa_bind_group,
+        }
+    }
+}
+
+/// `size`³ entries that leave the colors unchanged.
+fn identity_lut(size: u32) -> Vec<[f32; 3]> {
+    let step = 1.0 / (size - 1) as f32;
+    let mut entries = Vec::with_capacity((size * size * size) as usize);
+    for b in 0..size {
+        for g in 0..size {
+            for r in 0..size {
+                entries.push([r as f32 * step, g as f32 * step, b as f32 * step]);
+            }
+        }
+    }
+    entries
+}
+
+fn create_lut(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    size: u32,
+    entries: &[[f32; 3]],
+) -> wgpu::TextureView {
+    let texels = entries
+        .iter()
+        .flat_map(|&[r, g, b]| [r, g, b, 1.0].map(half::f16::from_f32))
+        .collect::<Vec<_>>();
+    let texture = device.create_texture_with_data(
+        queue,
+        &wgpu::TextureDescriptor {
+            label: Some("color_grading_lut"),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: size,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D3,
+            format: wgpu::TextureFormat::Rgba16Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        },
+        bytemuck::cast_slice(&texels),
+    );
+    texture.create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+fn create_target(
+    device: &wgpu::Device,
+    label: &str,
+    format: wgpu::TextureFormat,
+    width: u32,
+    height: u32,
+) -> wgpu::TextureView {
+    device
+        .create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        })
+        .create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+fn fullscreen_pass(
+    encoder: &mut wgpu::CommandEncoder,
+    label: &str,
+    target: &wgpu::TextureView,
+    load: wgpu::LoadOp<wgpu::Color>,
+    pipeline: &wgpu::RenderPipeline,
+    bind_group: &wgpu::BindGroup,
+) {
+    let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some(label),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view: target,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load,
+                store: wgpu::StoreOp::Store,
+            },
+        })],
+        depth_stencil_attachment: None,
+        occlusion_query_set: None,
+        timestamp_writes: None,
+    });
+    pass.set_pipeline(pipeline);
+    pass.set_bind_group(0, bind_group, &[]);
+    pass.draw(0..3, 0..1);
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    entry_point: &str,
+    format: wgpu::TextureFormat,
+    blend: Option<wgpu::BlendState>,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Post Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point,
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
@@ -0,0 +1,253 @@
+// The post-processing chain from the HDR frame to the swapchain, see post.rs
+
+// See post::PostUniform
+struct Post {
+    // 2 to the power of the exposure compensation
+    exposure: f32,
+    auto_exposure: u32,
+    adaptation: f32,
+    tonemapper: u32,
+    bloom: u32,
+    bloom_threshold: f32,
+    bloom_intensity: f32,
+    color_grading: u32,
+    lut_size: f32,
+}
+@group(0) @binding(0)
+var<uniform> post: Post;
+@group(0) @binding(1)
+var linear_sampler: sampler;
+// The input of the pass: the HDR frame, a bloom level or the tonemapped frame for FXAA
+@group(0) @binding(2)
+var source: texture_2d<f32>;
+// Written by exposure.wgsl
+struct Exposure {
+    value: f32,
+}
+@group(0) @binding(3)
+var<storage, read> exposure: Exposure;
+// Only bound in the tonemapping pass
+@group(0) @binding(4)
+var bloom_texture: texture_2d<f32>;
+@group(0) @binding(5)
+var lut: texture_3d<f32>;
+
+// Values of Post.tonemapper, see post::Tonemapper
+const TONEMAP_NONE: u32 = 0u;
+const TONEMAP_ACES: u32 = 1u;
+const TONEMAP_AGX: u32 = 2u;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+// One triangle that covers the whole screen
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+fn current_exposure() -> f32 {
+    if post.auto_exposure != 0u {
+        return post.exposure * exposure.value;
+    }
+    return post.exposure;
+}
+
+fn sample_source(uv: vec2<f32>) -> vec3<f32> {
+    return textureSampleLevel(source, linear_sampler, uv, 0.0).rgb;
+}
+
+// -- BLOOM -- //
+
+// The 13 tap downsample from "Next Generation Post Processing in Call of Duty: Advanced Warfare",
+// it doesn't flicker as much as a plain box filter
+fn downsample(uv: vec2<f32>) -> vec3<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(source));
+    let a = sample_source(uv + texel * vec2<f32>(-2.0, -2.0));
+    let b = sample_source(uv + texel * vec2<f32>(0.0, -2.0));
+    let c = sample_source(uv + texel * vec2<f32>(2.0, -2.0));
+    let d = sample_source(uv + texel * vec2<f32>(-2.0, 0.0));
+    let e = sample_source(uv);
+    let f = sample_source(uv + texel * vec2<f32>(2.0, 0.0));
+    let g = sample_source(uv + texel * vec2<f32>(-2.0, 2.0));
+    let h = sample_source(uv + texel * vec2<f32>(0.0, 2.0));
+    let i = sample_source(uv + texel * vec2<f32>(2.0, 2.0));
+    let j = sample_source(uv + texel * vec2<f32>(-1.0, -1.0));
+    let k = sample_source(uv + texel * vec2<f32>(1.0, -1.0));
+    let l = sample_source(uv + texel * vec2<f32>(-1.0, 1.0));
+    let m = sample_source(uv + texel * vec2<f32>(1.0, 1.0));
+    return e * 0.125 + (a + c + g + i) * 0.03125 + (b + d + f + h) * 0.0625 + (j + k + l + m) * 0.125;
+}
+
+// The first level only keeps what is brighter than the threshold, with a soft knee
+@fragment
+fn fs_bloom_prefilter(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Clamped so single very bright pixels don't turn into flickering blobs
+    let color = min(downsample(in.uv) * current_exposure(), vec3<f32>(64.0));
+    let brightness = max(color.r, max(color.g, color.b));
+    let knee = post.bloom_threshold * 0.5;
+    var soft = clamp(brightness - post.bloom_threshold + knee, 0.0, 2.0 * knee);
+    soft = soft * soft / (4.0 * knee + 1e-5);
+    let contribution = max(soft, brightness - post.bloom_threshold) / max(brightness, 1e-5);
+    return vec4<f32>(color * contribution, 1.0);
+}
+
+@fragment
+fn fs_bloom_downsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(downsample(in.uv), 1.0);
+}
+
+// A 3x3 tent filter, added onto the next larger level
+@fragment
+fn fs_bloom_upsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(source));
+    var color = sample_source(in.uv) * 4.0;
+    color += (sample_source(in.uv + texel * vec2<f32>(-1.0, 0.0)) + sample_source(in.uv + texel * vec2<f32>(1.0, 0.0))
+        + sample_source(in.uv + texel * vec2<f32>(0.0, -1.0)) + sample_source(in.uv + texel * vec2<f32>(0.0, 1.0))) * 2.0;
+    color += sample_source(in.uv + texel * vec2<f32>(-1.0, -1.0)) + sample_source(in.uv + texel * vec2<f32>(1.0, -1.0))
+        + sample_source(in.uv + texel * vec2<f32>(-1.0, 1.0)) + sample_source(in.uv + texel * vec2<f32>(1.0, 1.0));
+    return vec4<f32>(color / 16.0, 1.0);
+}
+
+// -- TONEMAPPING -- //
+
+// Stephen Hill's fit of the ACES reference rendering and output transforms
+fn tonemap_aces(color: vec3<f32>) -> vec3<f32> {
+    // sRGB => XYZ => D65_2_D60 => AP1 => RRT_SAT
+    let input = mat3x3<f32>(
+        vec3<f32>(0.59719, 0.07600, 0.02840),
+        vec3<f32>(0.35458, 0.90834, 0.13383),
+        vec3<f32>(0.04823, 0.01566, 0.83777),
+    );
+    // ODT_SAT => XYZ => D60_2_D65 => sRGB
+    let output = mat3x3<f32>(
+        vec3<f32>(1.60475, -0.10208, -0.00327),
+        vec3<f32>(-0.53108, 1.10813, -0.07276),
+        vec3<f32>(-0.07367, -0.00605, 1.07602),
+    );
+    let v = input * color;
+    let a = v * (v + 0.0245786) - 0.000090537;
+    let b = v * (0.983729 * v + 0.4329510) + 0.238081;
+    return clamp(output * (a / b), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+// A polynomial fit of the AgX base contrast curve
+fn agx_contrast(x: vec3<f32>) -> vec3<f32> {
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    return 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232;
+}
+
+// Troy Sobotka's AgX, after Benjamin Wrensch's minimal version
+fn tonemap_agx(color: vec3<f32>) -> vec3<f32> {
+    let inset = mat3x3<f32>(
+        vec3<f32>(0.842479062253094, 0.0423282422610123, 0.0423756549057051),
+        vec3<f32>(0.0784335999999992, 0.878468636469772, 0.0784336),
+        vec3<f32>(0.0792237451477643, 0.0791661274605434, 0.879142973793104),
+    );
+    let outset = mat3x3<f32>(
+        vec3<f32>(1.19687900512017, -0.0528968517574562, -0.0529716355144438),
+        vec3<f32>(-0.0980208811401368, 1.15190312990417, -0.0980434501171241),
+        vec3<f32>(-0.0990297440797205, -0.0989611768448433, 1.15107367264116),
+    );
+    let min_ev = -12.47393;
+    let max_ev = 4.026069;
+    var v = inset * max(color, vec3<f32>(1e-10));
+    v = clamp((log2(v) - min_ev) / (max_ev - min_ev), vec3<f32>(0.0), vec3<f32>(1.0));
+    v = outset * agx_contrast(v);
+    // The curve produces display encoded values, the render target encodes them again
+    return pow(clamp(v, vec3<f32>(0.0), vec3<f32>(1.0)), vec3<f32>(2.2));
+}
+
+fn tonemap(color: vec3<f32>) -> vec3<f32> {
+    switch post.tonemapper {
+        case TONEMAP_ACES: {
+            return tonemap_aces(color);
+        }
+        case TONEMAP_AGX: {
+            return tonemap_agx(color);
+        }
+        default: {
+            return clamp(color, vec3<f32>(0.0), vec3<f32>(1.0));
+        }
+    }
+}
+
+fn linear_to_srgb(color: vec3<f32>) -> vec3<f32> {
+    let low = color * 12.92;
+    let high = 1.055 * pow(color, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, color <= vec3<f32>(0.0031308));
+}
+
+fn srgb_to_linear(color: vec3<f32>) -> vec3<f32> {
+    let low = color / 12.92;
+    let high = pow((color + 0.055) / 1.055, vec3<f32>(2.4));
+    return select(high, low, color <= vec3<f32>(0.04045));
+}
+
+// Color grading LUTs map display encoded colors, like the .cube files they come from
+fn grade(color: vec3<f32>) -> vec3<f32> {
+    let encoded = linear_to_srgb(clamp(color, vec3<f32>(0.0), vec3<f32>(1.0)));
+    // Hit the centers of the first and last texels
+    let uvw = encoded * (post.lut_size - 1.0) / post.lut_size + 0.5 / post.lut_size;
+    return srgb_to_linear(textureSampleLevel(lut, linear_sampler, uvw, 0.0).rgb);
+}
+
+@fragment
+fn fs_tonemap(in: VertexOutput) -> @location(0) vec4<f32> {
+    let hdr = textureLoad(source, vec2<i32>(in.clip_position.xy), 0);
+    var color = hdr.rgb * current_exposure();
+    if post.bloom != 0u {
+        color += textureSampleLevel(bloom_texture, linear_sampler, in.uv, 0.0).rgb * post.bloom_intensity;
+    }
+    color = tonemap(color);
+    if post.color_grading != 0u {
+        color = grade(color);
+    }
+    return vec4<f32>(color, clamp(hdr.a, 0.0, 1.0));
+}
+
+// -- FXAA -- //
+
+const FXAA_REDUCE_MIN: f32 = 0.0078125;
+const FXAA_REDUCE_MUL: f32 = 0.125;
+const FXAA_SPAN_MAX: f32 = 8.0;
+
+// The source is sRGB, so it is sampled as linear. The square root is close enough to the
+// perceptual brightness FXAA expects.
+fn fxaa_luma(color: vec3<f32>) -> f32 {
+    return dot(sqrt(max(color, vec3<f32>(0.0))), vec3<f32>(0.299, 0.587, 0.114));
+}
+
+// Blurs along the edges found from the luma of the corners, after Timothy Lottes' FXAA
+@fragment
+fn fs_fxaa(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(source));
+    let center = textureSampleLevel(source, linear_sampler, in.uv, 0.0);
+    let luma_nw = fxaa_luma(sample_source(in.uv + texel * vec2<f32>(-1.0, -1.0)));
+    let luma_ne = fxaa_luma(sample_source(in.uv + texel * vec2<f32>(1.0, -1.0)));
+    let luma_sw = fxaa_luma(sample_source(in.uv + texel * vec2<f32>(-1.0, 1.0)));
+    let luma_se = fxaa_luma(sample_source(in.uv + texel * vec2<f32>(1.0, 1.0)));
+    let luma_m = fxaa_luma(center.rgb);
+    let luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    let luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    var direction = vec2<f32>(-((luma_nw + luma_ne) - (luma_sw + luma_se)), (luma_nw + luma_sw) - (luma_ne + luma_se));
+    let direction_reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * FXAA_REDUCE_MUL, FXAA_REDUCE_MIN);
+    let inverse_min = 1.0 / (min(abs(direction.x), abs(direction.y)) + direction_reduce);
+    direction = clamp(direction * inverse_min, vec2<f32>(-FXAA_SPAN_MAX), vec2<f32>(FXAA_SPAN_MAX)) * texel;
+
+    let color_a = 0.5 * (sample_source(in.uv + direction * (1.0 / 3.0 - 0.5)) + sample_source(in.uv + direction * (2.0 / 3.0 - 0.5)));
+    let color_b = color_a * 0.5 + 0.25 * (sample_source(in.uv - direction * 0.5) + sample_source(in.uv + direction * 0.5));
+    let luma_b = fxaa_luma(color_b);
+    if luma_b < luma_min || luma_b > luma_max {
+        return vec4<f32>(color_a, center.a);
+    }
+    return vec4<f32>(color_b, center.a);
+}
@@ -0,0 +1,1429 @@
+use anyhow::Context;
+use cgmath::{Deg, InnerSpace, Vector3};
+use wgpu::util::DeviceExt;
+
+use crate::background::Background;
+use crate::camera::{self, Camera};
+use crate::clipping::{self, Clipping};
+use crate::clusters::Clusters;
+use crate::culling::{CullingStats, Frustum};
+use crate::debug_draw::{DebugDraw, DebugStyle};
+use crate::environment::Environment;
+use crate::grid::Grid;
+use crate::hud::Hud;
+use crate::model::{self, Vertex};
+use crate::occlusion::OcclusionCulling;
+use crate::orbit_camera::OrbitCamera;
+use crate::outline::Outline;
+use crate::post::{PostProcessing, HDR_FORMAT};
+use crate::scene::SceneConfig;
+use crate::shading::{self, ShadingModel, ShadingUniform, ToonSettings};
+use crate::shadow::{self, ShadowMap, ShadowSettings};
+use crate::ssao::Ssao;
+use crate::transparency::{self, Transparency};
+use crate::vectors::VertexVectors;
+use crate::visualization::{Visualization, VisualizationMode};
+use crate::wireframe::{Wireframe, WireframeMode};
+use crate::{lights, resources, texture};
+
+/// The MSAA sample count used unless another one is chosen.
+pub const DEFAULT_SAMPLE_COUNT: u32 = 4;
+
+/// How fast [Renderer::orbit_lights] moves the lights around the model, per second.
+pub const LIGHT_ORBIT_SPEED: Deg<f32> = Deg(60.0);
+
+/// Everything needed to draw the scene, independent of where the frame ends up.
+///
+/// The [Renderer] does not know about windows or surfaces. It draws into any texture view
+/// of its `format`, which is either a swapchain image or an offscreen texture. The scene itself
+/// is rendered in [HDR_FORMAT] and mapped to the `format` by the [PostProcessing].
+pub struct Renderer {
+    pub device: wgpu::Device,
+    pub command_queue: wgpu::Queue,
+    pub format: wgpu::TextureFormat,
+    pub width: u32,
+    pub height: u32,
+    render_pipeline_layout: wgpu::PipelineLayout,
+    render_pipeline: wgpu::RenderPipeline,
+    pub camera: OrbitCamera,
+    camera_uniform: camera::CameraUniform,
+    camera_buffer: wgpu::Buffer,
+    // Also holds the shading settings, the ambient occlusion, which depends on the size, the
+    // matcap and the clip planes
+    camera_bind_group_layout: wgpu::BindGroupLayout,
+    camera_bind_group: wgpu::BindGroup,
+    pub shading_model: ShadingModel,
+    /// Scales the image based lighting from `environment`.
+    pub environment_intensity: f32,
+    /// The bands and rim light of [ShadingModel::Toon].
+    pub toon: ToonSettings,
+    shading_buffer: wgpu::Buffer,
+    /// The sphere image of [ShadingModel::Matcap], see [Renderer::load_matcap].
+    matcap: texture::Texture,
+    pub obj_model: model::Model,
+    /// Skips the meshes outside the view of the camera, see [model::Model::cull].
+    pub frustum_culling: bool,
+    /// Skips the meshes hidden behind others.
+    pub occlusion: OcclusionCulling,
+    /// The meshes drawn and culled by the last [Renderer::update].
+    pub culling: CullingStats,
+    depth_texture: texture::Texture,
+    /// The number of MSAA samples per pixel, one of `supported_sample_counts`.
+    pub sample_count: u32,
+    pub supported_sample_counts: Vec<u32>,
+    // Only used when sample_count > 1, it is resolved into the view passed to render()
+    msaa_texture: Option<texture::Texture>,
+    /// The lights of the scene, uploaded on every [Renderer::update]. The first one casts the shadows.
+    pub lights: Vec<lights::Light>,
+    // Holds exactly one LightRaw per light, the shader takes the count from its size
+    light_buffer: wgpu::Buffer,
+    clusters: Clusters,
+    /// Shows how many lights reach each cluster instead of the shading.
+    pub cluster_heatmap: bool,
+    light_bind_group_layout: wgpu::BindGroupLayout,
+    light_bind_group: wgpu::BindGroup,
+    // A sphere drawn once per light by light_pipeline
+    light_gizmo: model::Model,
+    light_pipeline_layout: wgpu::PipelineLayout,
+    light_pipeline: wgpu::RenderPipeline,
+    pub show_light_gizmos: bool,
+    /// The light that is being edited, its gizmo is drawn larger.
+    pub selected_light: Option<usize>,
+    /// The indices of the selected meshes of [Renderer::obj_model], drawn with an [Outline].
+    pub selection: Vec<usize>,
+    pub environment: Environment,
+    pub background: Background,
+    pub grid: Grid,
+    pub shadow_map: ShadowMap,
+    pub ssao: Ssao,
+    pub post: PostProcessing,
+    pub wireframe: Wireframe,
+    pub vectors: VertexVectors,
+    pub visualization: Visualization,
+    pub debug_draw: DebugDraw,
+    pub outline: Outline,
+    pub clipping: Clipping,
+    pub transparency: Transparency,
+    /// Drawn by [Renderer::render], but not into captures.
+    pub hud: Hud,
+    pub subdivision_level: u32,
+}
+
+/// Requests a device and queue from the adapter with the features the [Renderer] can make use of.
+pub async fn request_device(
+    adapter: &wgpu::Adapter,
+) -> anyhow::Result<(wgpu::Device, wgpu::Queue)> {
+    // The adapter specific format features unlock MSAA sample counts other than 1 and 4
+    let features = adapter.features() & wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES;
+    adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                label: Some("main device"),
+                features,
+                limits: wgpu::Limits {
+                    max_push_constant_size: 8,
+                    ..Default::default()
+                },
+            },
+            None,
+        )
+        .await
+        .context("failed to create the device")
+}
+
+/// The MSAA sample counts that can be used for both the color `format` and the depth buffer.
+pub fn supported_sample_counts(
+    adapter: &wgpu::Adapter,
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+) -> Vec<u32> {
+    // Without adapter specific format features only 1 and 4 samples are guaranteed to work
+    if !device
+        .features()
+        .contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES)
+    {
+        return vec![1, 4];
+    }
+
+    let color = adapter.get_texture_format_features(format).flags;
+    let depth = adapter
+        .get_texture_format_features(texture::Texture::DEPTH_STENCIL_FORMAT)
+        .flags;
+    let revealage = adapter
+        .get_texture_format_features(transparency::REVEALAGE_FORMAT)
+        .flags;
+    [1, 2, 4, 8]
+        .into_iter()
+        .filter(|&count| {
+            count == 1
+                || (color.sample_count_supported(count)
+                    && color.contains(wgpu::TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE)
+                    && depth.sample_count_supported(count)
+                    && revealage.sample_count_supported(count)
+                    && revealage.contains(wgpu::TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE))
+        })
+        .collect()
+}
+
+impl Renderer {
+    /// Creates a new [Renderer] and loads the model.
+    ///
+    /// Arguments:
+    ///
+    /// * `adapter`: The adapter the `device` was requested from.
+    /// * `format`: The format of the color targets that will be passed to [Renderer::render].
+    /// * `width`, `height`: The size of the color targets in pixels.
+    /// * `sample_count`: The requested MSAA sample count, see [Renderer::set_sample_count].
+    /// * `model_name`: The OBJ file to load from the `res` folder.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn new(
+        adapter: &wgpu::Adapter,
+        device: wgpu::Device,
+        command_queue: wgpu::Queue,
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+        sample_count: u32,
+        model_name: &str,
+    ) -> anyhow::Result<Renderer> {
+        let supported_sample_counts = supported_sample_counts(adapter, &device, HDR_FORMAT);
+        let sample_count = clamp_sample_count(&supported_sample_counts, sample_count);
+
+        // --CAMERA-- //
+        let mut camera = OrbitCamera::new(
+            2.0,
+            0.0,
+            0.0,
+            Vector3::new(0.0, 0.0, 0.0),
+            width as f32 / height as f32,
+        );
+        camera.bounds.min_distance = Some(1.1);
+        let mut camera_uniform = camera::CameraUniform::default();
+        camera_uniform.update_view_proj(&camera);
+
+        // this is a uniform buffer for the camera
+        let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Camera Buffer"),
+            contents: bytemuck::cast_slice(&[camera_uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        // the shading settings change as rarely as the camera, so they share its bind group
+        let shading_model = ShadingModel::Pbr;
+        let shading_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Shading Buffer"),
+            contents: bytemuck::cast_slice(&[ShadingUniform::new(
+                shading_model,
+                1.0,
+                false,
+                None,
+                false,
+                &ToonSettings::default(),
+                None,
+            )]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        // now lets create a bind group with the buffer, we need a layout for this
+        let camera_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false, //means that the location of the data in the buffer wont change
+                            min_binding_size: None, //smallest size the buffer can be (dont need to specify -> https://docs.rs/wgpu/latest/wgpu/enum.BindingType.html#variant.Buffer.field.min_binding_size)
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // Read with textureLoad at the pixel position, so no sampler
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+                label: Some("camera_bind_group_layout"),
+            });
+
+        // --AMBIENT OCCLUSION-- //
+        let ssao = Ssao::new(&device, width, height);
+
+        // --MATCAP-- //
+        let matcap = texture::Texture::from_image(
+            &device,
+            &command_queue,
+            &image::DynamicImage::ImageRgba8(shading::default_matcap(256)),
+            "default_matcap",
+            true,
+        );
+
+        // --CLIPPING-- //
+        let clipping = Clipping::new(&device, HDR_FORMAT, sample_count, &camera_bind_group_layout);
+
+        // create the bind group
+        let camera_bind_group = create_camera_bind_group(
+            &device,
+            &camera_bind_group_layout,
+            &camera_buffer,
+            &shading_buffer,
+            &ssao,
+            &matcap,
+            clipping.buffer(),
+        );
+
+        // --DEPTH-- //
+        let depth_texture = texture::Texture::create_depth_stencil_texture(
+            &device,
+            width,
+            height,
+            sample_count,
+            "depth_texture",
+        );
+        let msaa_texture = create_msaa_texture(&device, width, height, HDR_FORMAT, sample_count);
+
+        // --POST-PROCESSING-- //
+        let post = PostProcessing::new(&device, &command_queue, format, width, height);
+
+        // --LIGHTS-- //
+        let lights = SceneConfig::default().lights;
+        let light_buffer = create_light_buffer(&device, &lights);
+        let clusters = Clusters::new(&device, &light_buffer);
+
+        // --ENVIRONMENT-- //
+        // The image based lighting shares the light bind group, all four groups are taken
+        let environment = Environment::procedural_sky(&device, &command_queue);
+
+        // now create a bind group (with of course the layout as per usual)
+        let environment_entry = |binding, view_dimension| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let cluster_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let light_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    environment_entry(1, wgpu::TextureViewDimension::Cube),
+                    environment_entry(2, wgpu::TextureViewDimension::Cube),
+                    environment_entry(3, wgpu::TextureViewDimension::D2),
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                    // The light lists of the clusters, also in this group for lack of another
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    cluster_entry(6),
+                    cluster_entry(7),
+                ],
+                label: None,
+            });
+
+        let light_bind_group = create_light_bind_group(
+            &device,
+            &light_bind_group_layout,
+            &light_buffer,
+            &environment,
+            &clusters,
+        );
+
+        // --BACKGROUND-- //
+        let background = Background::new(&device, HDR_FORMAT, sample_count, &environment);
+
+        // --GRID-- //
+        let grid = Grid::new(&device, HDR_FORMAT, sample_count);
+
+        // --WIREFRAME-- //
+        let wireframe =
+            Wireframe::new(&device, HDR_FORMAT, sample_count, &camera_bind_group_layout);
+
+        // --VECTORS-- //
+        let vectors =
+            VertexVectors::new(&device, HDR_FORMAT, sample_count, &camera_bind_group_layout);
+
+        // --DEBUG DRAW-- //
+        let debug_draw =
+            DebugDraw::new(&device, HDR_FORMAT, sample_count, &camera_bind_group_layout);
+
+        // --OUTLINE-- //
+        let outline = Outline::new(
+            &device,
+            HDR_FORMAT,
+            sample_count,
+            &camera_bind_group_layout,
+            width,
+            height,
+        );
+
+        // --VISUALIZATION-- //
+        let visualization = Visualization::new(
+            &device,
+            HDR_FORMAT,
+            sample_count,
+            &camera_bind_group_layout,
+            width,
+            height,
+        );
+        let hud = Hud::new(&device, &command_queue, format);
+
+        // --SHADOWS-- //
+        let shadow_map = ShadowMap::new(&device, ShadowSettings::default());
+
+        // --MATERIALS-- //
+        let material_bind_group_layout = model::Material::create_bind_group_layout(&device);
+
+        // RENDER PIPELINES
+
+        let render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Render Pipeline Layout"),
+                bind_group_layouts: &[
+                    &camera_bind_group_layout,
+                    &light_bind_group_layout,
+                    &shadow_map.bind_group_layout,
+                    &material_bind_group_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+
+        let render_pipeline =
+            create_shaded_pipeline(&device, &render_pipeline_layout, HDR_FORMAT, sample_count);
+
+        // --OCCLUSION CULLING-- //
+        let occlusion = OcclusionCulling::new(&device, sample_count, &camera_bind_group_layout);
+
+        // --TRANSPARENCY-- //
+        let transparency = Transparency::new(
+            &device,
+            HDR_FORMAT,
+            sample_count,
+            &[
+                &camera_bind_group_layout,
+                &light_bind_group_layout,
+                &shadow_map.bind_group_layout,
+                &material_bind_group_layout,
+            ],
+            width,
+            height,
+        );
+
+        // The gizmos only need the camera and the lights
+        let light_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Light Pipeline Layout"),
+                bind_group_layouts: &[&camera_bind_group_layout, &light_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let light_pipeline =
+            create_light_pipeline(&device, &light_pipeline_layout, HDR_FORMAT, sample_count);
+        let light_gizmo = lights::create_gizmo(&device);
+
+        // --MODELS-- //
+
+        let obj_model = resources::load_model(
+            model_name,
+            &device,
+            &command_queue,
+            &material_bind_group_layout,
+        )
+        .await?;
+
+        Ok(Renderer {
+            device,
+            command_queue,
+            format,
+            width,
+            height,
+            render_pipeline_layout,
+            render_pipeline,
+            camera,
+            camera_uniform,
+            camera_buffer,
+            camera_bind_group_layout,
+            camera_bind_group,
+            shading_model,
+            environment_intensity: 1.0,
+            toon: ToonSettings::default(),
+            shading_buffer,
+            matcap,
+            obj_model,
+            frustum_culling: true,
+            occlusion,
+            culling: CullingStats::default(),
+            depth_texture,
+            sample_count,
+            supported_sample_counts,
+            msaa_texture,
+            lights,
+            light_buffer,
+            clusters,
+            cluster_heatmap: false,
+            light_bind_group_layout,
+            light_bind_group,
+            light_gizmo,
+            light_pipeline_layout,
+            light_pipeline,
+            show_light_gizmos: false,
+            selected_light: None,
+            selection: Vec::new(),
+            environment,
+            background,
+            grid,
+            shadow_map,
+            ssao,
+            post,
+            wireframe,
+            vectors,
+            visualization,
+            debug_draw,
+            outline,
+            clipping,
+            transparency,
+            hud,
+            subdivision_level: 0,
+        })
+    }
+
+    /// Resizes the render targets owned by the [Renderer] and the camera projection.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        if width == 0 || height == 0 {
+            return;
+        }
+        self.width = width;
+        self.height = height;
+
+        self.camera.resize_projection(width, height);
+
+        self.create_render_targets();
+    }
+
+    /// Switches to the highest supported MSAA sample count that is not above `sample_count`.
+    ///
+    /// The pipelines and render targets are recreated to match. Returns the count now in use.
+    pub fn set_sample_count(&mut self, sample_count: u32) -> u32 {
+        let sample_count = clamp_sample_count(&self.supported_sample_counts, sample_count);
+        if sample_count != self.sample_count {
+            self.sample_count = sample_count;
+            self.render_pipeline = create_shaded_pipeline(
+                &self.device,
+                &self.render_pipeline_layout,
+                HDR_FORMAT,
+                sample_count,
+            );
+            self.light_pipeline = create_light_pipeline(
+                &self.device,
+                &self.light_pipeline_layout,
+                HDR_FORMAT,
+                sample_count,
+            );
+            self.background.set_sample_count(&self.device, sample_count);
+            self.grid.set_sample_count(&self.device, sample_count);
+            self.wireframe.set_sample_count(&self.device, sample_count);
+            self.vectors.set_sample_count(&self.device, sample_count);
+            self.debug_draw.set_sample_count(&self.device, sample_count);
+            self.outline.set_sample_count(&self.device, sample_count);
+            self.clipping.set_sample_count(&self.device, sample_count);
+            self.transparency
+                .set_sample_count(&self.device, sample_count);
+            self.occlusion.set_sample_count(&self.device, sample_count);
+            self.visualization
+                .set_sample_count(&self.device, sample_count);
+            self.create_render_targets();
+        }
+        sample_count
+    }
+
+    /// The next supported sample count after the current one, wrapping around to the lowest.
+    pub fn next_sample_count(&self) -> u32 {
+        self.supported_sample_counts
+            .iter()
+            .copied()
+            .find(|&count| count > self.sample_count)
+            .unwrap_or(self.supported_sample_counts[0])
+    }
+
+    /// (Re)creates the depth, MSAA, ambient occlusion, post-processing, overdraw, outline and
+    /// transparency targets for the current size and sample count.
+    fn create_render_targets(&mut self) {
+        self.ssao.resize(&self.device, self.width, self.height);
+        self.post.resize(&self.device, self.width, self.height);
+        self.visualization
+            .resize(&self.device, self.width, self.height);
+        self.outline.resize(&self.device, self.width, self.height);
+        self.transparency
+            .resize(&self.device, self.width, self.height);
+        self.camera_bind_group = create_camera_bind_group(
+            &self.device,
+            &self.camera_bind_group_layout,
+            &self.camera_buffer,
+            &self.shading_buffer,
+            &self.ssao,
+            &self.matcap,
+            self.clipping.buffer(),
+        );
+        self.depth_texture = texture::Texture::create_depth_stencil_texture(
+            &self.device,
+            self.width,
+            self.height,
+            self.sample_count,
+            "depth_texture",
+        );
+        self.msaa_texture = create_msaa_texture(
+            &self.device,
+            self.width,
+            self.height,
+            HDR_FORMAT,
+            self.sample_count,
+        );
+    }
+
+    /// Rebuilds the model buffers at the given subdivision level.
+    pub fn set_subdivision_level(&mut self, level: u32) {
+        self.subdivision_level = level;
+        self.obj_model.set_subdivision_level(&self.device, level);
+        self.vectors.invalidate();
+        self.wireframe.invalidate();
+    }
+
+    /// Adds the bounds of the model, the lights and the frustum of the shadow map to the
+    /// [DebugDraw] for one frame. The selected light is drawn on top of the model.
+    pub fn draw_helpers(&mut self) {
+        let (min, max) = self.obj_model.bounds();
+        let center = (min + max) / 2.0;
+        let size = (max - min).magnitude();
+        self.debug_draw
+            .aabb(min, max, DebugStyle::color([1.0, 0.8, 0.1]));
+
+        for (index, light) in self.lights.iter().enumerate() {
+            let style = DebugStyle {
+                color: light.color,
+                depth_test: self.selected_light != Some(index),
+                ..Default::default()
+            };
+            let position = Vector3::from(light.position);
+            let direction = Vector3::from(light.direction).normalize();
+            match light.kind {
+                lights::LightKind::Point => {
+                    self.debug_draw.cross(position, size * 0.05, style);
+                    if light.range > 0.0 {
+                        self.debug_draw.sphere(position, light.range, style);
+                    }
+                }
+                lights::LightKind::Spot => {
+                    let length = size * 0.25;
+                    self.debug_draw.cross(position, size * 0.05, style);
+                    self.debug_draw
+                        .arrow(position, position + direction * length, style);
+                    // four lines along the outer cone
+                    let helper = if direction.y.abs() < 0.9 {
+                        Vector3::unit_y()
+                    } else {
+                        Vector3::unit_x()
+                    };
+                    let side = direction.cross(helper).normalize();
+                    let up = direction.cross(side);
+                    let radius = light.outer_cone_angle.to_radians().tan() * length;
+                    for offset in [side, -side, up, -up] {
+                        let end = position + direction * length + offset * radius;
+                        self.debug_draw.line(position, end, style);
+                    }
+                }
+                // pointing at the model from outside its bounds
+                lights::LightKind::Directional => self.debug_draw.arrow(
+                    center - direction * size,
+                    center - direction * size * 0.5,
+                    style,
+                ),
+            }
+        }
+
+        if let Some(light) = self.lights.first() {
+            let view_proj = shadow::light_view_projection(light, (min, max));
+            self.debug_draw
+                .frustum(view_proj, DebugStyle::color([0.6, 0.6, 0.6]));
+        }
+    }
+
+    /// Adds the bounds of the meshes the last [Renderer::update] skipped as occluded to the
+    /// [DebugDraw] for one frame, in red on top of the model. Only while `show_occluded` is set.
+    pub fn draw_occluded(&mut self) {
+        if !self.occlusion.settings.show_occluded {
+            return;
+        }
+        let style = DebugStyle {
+            color: [1.0, 0.1, 0.1],
+            depth_test: false,
+            ..Default::default()
+        };
+        for mesh in self.obj_model.meshes.iter().filter(|mesh| mesh.occluded) {
+            self.debug_draw.aabb(mesh.bounds.0, mesh.bounds.1, style);
+        }
+    }
+
+    /// Draws the outline of every clip plane across the model, with the handle that drags it and
+    /// an arrow towards the side that is cut away. The selected plane is drawn on top.
+    pub fn draw_clip_planes(&mut self) {
+        let (min, max) = self.obj_model.bounds();
+        let center = (min + max) / 2.0;
+        let size = (max - min).magnitude();
+        for (index, plane) in self.clipping.planes.iter().enumerate() {
+            let selected = self.clipping.selected == Some(index);
+            let style = DebugStyle {
+                color: if selected {
+                    [1.0, 1.0, 0.0]
+                } else {
+                    [0.3, 0.7, 1.0]
+                },
+                depth_test: !selected,
+                ..Default::default()
+            };
+            let handle = plane.project(center);
+            let helper = if plane.normal.y.abs() < 0.9 {
+                Vector3::unit_y()
+            } else {
+                Vector3::unit_x()
+            };
+            let side = plane.normal.cross(helper).normalize() * size * 0.5;
+            let up = plane.normal.cross(side);
+            let corners = [side + up, side - up, -side - up, -side + up];
+            for (corner, next) in corners.iter().zip(corners.iter().cycle().skip(1)) {
+                self.debug_draw.line(handle + corner, handle + next, style);
+            }
+            self.debug_draw
+                .sphere(handle, size * clipping::HANDLE_SIZE, style);
+            self.debug_draw
+                .arrow(handle, handle + plane.normal * size * 0.15, style);
+        }
+    }
+
+    /// The kind of the shadow casting light, see [lights::LightKind].
+    pub fn light_kind(&self) -> lights::LightKind {
+        self.lights
+            .first()
+            .map_or(lights::LightKind::Point, |light| light.kind)
+    }
+
+    /// Changes how the shadow casting light emits light and aims it at the model.
+    pub fn set_light_kind(&mut self, kind: lights::LightKind) {
+        let (min, max) = self.obj_model.bounds();
+        if let Some(light) = self.lights.first_mut() {
+            light.set_kind(kind, (min + max) / 2.0);
+        }
+    }
+
+    /// Loads a scene configuration from the `res` folder and replaces the lights with its own.
+    pub async fn load_scene(&mut self, file_name: &str) -> anyhow::Result<()> {
+        let scene = resources::load_scene(file_name).await?;
+        self.lights = scene.lights;
+        self.selected_light = None;
+        Ok(())
+    }
+
+    /// Moves all lights around the vertical axis through the model by [LIGHT_ORBIT_SPEED].
+    ///
+    /// Arguments:
+    ///
+    /// * `dt`: The time since the last frame.
+    pub fn orbit_lights(&mut self, dt: std::time::Duration) {
+        let (min, max) = self.obj_model.bounds();
+        let angle = LIGHT_ORBIT_SPEED * dt.as_secs_f32();
+        for light in &mut self.lights {
+            light.orbit(angle.into(), (min + max) / 2.0);
+        }
+    }
+
+    /// Loads an equirectangular `.hdr` or `.exr` file from the `res` folder and lights the
+    /// scene with it.
+    pub async fn load_environment(&mut self, file_name: &str) -> anyhow::Result<()> {
+        let environment =
+            resources::load_environment(file_name, &self.device, &self.command_queue).await?;
+        self.set_environment(environment);
+        Ok(())
+    }
+
+    /// Replaces the image based lighting, see [resources::load_environment].
+    pub fn set_environment(&mut self, environment: Environment) {
+        self.light_bind_group = create_light_bind_group(
+            &self.device,
+            &self.light_bind_group_layout,
+            &self.light_buffer,
+            &environment,
+            &self.clusters,
+        );
+        self.background.set_environment(&self.device, &environment);
+        self.environment = environment;
+    }
+
+    /// Loads a `.cube` file from the `res` folder as the color grading LUT of the [PostProcessing].
+    pub async fn load_color_grading_lut(&mut self, file_name: &str) -> anyhow::Result<()> {
+        let (size, entries) = resources::load_cube_lut(file_name).await?;
+        self.post
+            .set_lut(&self.device, &self.command_queue, size, &entries);
+        Ok(())
+    }
+
+    /// Loads an image of a shaded sphere from the `res` folder as the matcap of
+    /// [ShadingModel::Matcap].
+    pub async fn load_matcap(&mut self, file_name: &str) -> anyhow::Result<()> {
+        self.matcap =
+            resources::load_texture(file_name, &self.device, &self.command_queue, true).await?;
+        self.camera_bind_group = create_camera_bind_group(
+            &self.device,
+            &self.camera_bind_group_layout,
+            &self.camera_buffer,
+            &self.shading_buffer,
+            &self.ssao,
+            &self.matcap,
+            self.clipping.buffer(),
+        );
+        Ok(())
+    }
+
+    /// How many pixels away the screen space effects of a pixel read from, in a render of one
+    /// tile of a `tiles` x `tiles` grid.
+    pub fn screen_space_reach(&self, tiles: u32) -> u32 {
+        // The model is largest on screen at its point nearest to the eye
+        let (min, max) = self.obj_model.bounds();
+        let eye = self.camera.eye;
+        let nearest = Vector3::new(
+            eye.x.clamp(min.x, max.x),
+            eye.y.clamp(min.y, max.y),
+            eye.z.clamp(min.z, max.z),
+        );
+        let distance = (eye - nearest).magnitude().max(self.camera.znear);
+        let pixels_per_unit =
+            self.height as f32 * tiles as f32 / (2.0 * distance * (self.camera.fovy.0 / 2.0).tan());
+        // The post-processing reads the shading that the ambient occlusion went into
+        self.ssao.reach(pixels_per_unit) + self.post.reach()
+    }
+
+    /// Recreates the shadow map with `resolution` x `resolution` texels.
+    pub fn set_shadow_resolution(&mut self, resolution: u32) {
+        self.shadow_map.set_resolution(&self.device, resolution);
+    }
+
+    /// Culls the meshes and uploads the uniforms for the next frame. Returns whether the frame
+    /// after it is needed as well, while the occlusion culling catches up.
+    pub fn update(&mut self) -> bool {
+        let frustum = Frustum::from_view_projection(self.camera.build_view_projection_matrix());
+        // delivers the occlusion query results, without waiting for the GPU
+        self.device.poll(wgpu::Maintain::Poll);
+        let occlusion_changing = self.occlusion.update(&self.camera, &self.obj_model);
+        // the see-through wireframe is there to show the hidden edges
+        let hidden = self
+            .occlusion
+            .hidden()
+            .filter(|_| self.wireframe.settings.mode != WireframeMode::SeeThrough);
+        self.culling = self
+            .obj_model
+            .cull(self.frustum_culling.then_some(&frustum), hidden);
+
+        self.camera_uniform.update_view_proj(&self.camera);
+        self.command_queue.write_buffer(
+            &self.camera_buffer,
+            0,
+            bytemuck::cast_slice(&[self.camera_uniform]),
+        );
+        self.command_queue.write_buffer(
+            &self.shading_buffer,
+            0,
+            bytemuck::cast_slice(&[ShadingUniform::new(
+                self.shading_model,
+                self.environment_intensity,
+                self.cluster_heatmap,
+                self.selected_light,
+                self.ssao.settings.enabled,
+                &self.toon,
+                self.transparency.xray_opacity(),
+            )]),
+        );
+        self.clusters
+            .update(&self.command_queue, &self.camera, self.width, self.height);
+        self.ssao.update(
+            &self.command_queue,
+            &self.camera,
+            self.width,
+            self.height,
+            &self.clipping.planes,
+        );
+        self.background.update(
+            &self.command_queue,
+            &self.camera,
+            self.environment_intensity,
+        );
+        // The debug views show their colors as they are
+        self.post.passthrough = self.visualization.mode != VisualizationMode::Shaded;
+        self.post.update(&self.command_queue);
+        self.grid.update(&self.command_queue, &self.camera);
+        self.wireframe.update(&self.command_queue);
+        self.vectors
+            .update(&self.command_queue, self.obj_model.bounds());
+        self.visualization
+            .update(&self.command_queue, &self.camera, self.obj_model.bounds());
+        self.debug_draw.update(&self.device, &self.command_queue);
+        self.outline.update(&self.command_queue);
+        self.clipping.update(&self.command_queue, &self.camera);
+        self.upload_lights();
+        if let Some(light) = self.lights.first() {
+            self.shadow_map
+                .update(&self.command_queue, light, self.obj_model.bounds());
+        }
+        occlusion_changing
+    }
+
+    /// Writes `lights` into the storage buffer, which is recreated when the count changed.
+    fn upload_lights(&mut self) {
+        let raw = raw_lights(&self.lights);
+        let size = std::mem::size_of_val(raw.as_slice()) as wgpu::BufferAddress;
+        if size != self.light_buffer.size() {
+            self.light_buffer = create_light_buffer(&self.device, &self.lights);
+            self.clusters
+                .set_light_buffer(&self.device, &self.light_buffer);
+            self.light_bind_group = create_light_bind_group(
+                &self.device,
+                &self.light_bind_group_layout,
+                &self.light_buffer,
+                &self.environment,
+                &self.clusters,
+            );
+        } else {
+            self.command_queue
+                .write_buffer(&self.light_buffer, 0, bytemuck::cast_slice(&raw));
+        }
+    }
+
+    // ===================================================================== //
+    // ============================= RENDER ================================ //
+    // ===================================================================== //
+
+    /// Draws the scene into `view`, which must have the [Renderer]s' `format` and size.
+    pub fn render(&mut self, view: &wgpu::TextureView) {
+        self.render_frame(view, false);
+    }
+
+    /// Like [Renderer::render], but leaves the background transparent if
+    /// [BackgroundSettings::transparent_screenshots](crate::background::BackgroundSettings) is set.
+    pub fn render_capture(&mut self, view: &wgpu::TextureView) {
+        self.render_frame(view, true);
+    }
+
+    fn render_frame(&mut self, view: &wgpu::TextureView, capture: bool) {
+        let transparent = capture && self.background.settings.transparent_screenshots;
+        // we need a command buffer to send instructions to the gpu. This encoder does that
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Render Encoder"),
+            });
+
+        // the shadow map has to be complete before the main pass samples it
+        self.shadow_map.render(&mut encoder, &self.obj_model);
+        // and so do the light lists
+        self.clusters.dispatch(&mut encoder);
+        // and the ambient occlusion
+        self.ssao
+            .render(&mut encoder, &self.obj_model, &self.transparency);
+        self.vectors
+            .prepare(&self.device, &mut encoder, &self.obj_model);
+        self.wireframe
+            .prepare(&self.device, &self.obj_model, self.subdivision_level);
+        // and the overdraw counts if they are shown
+        self.visualization
+            .render_overdraw(&mut encoder, &self.obj_model, &self.camera_bind_group);
+
+        // now use the encoder to create a render pass, which has all the methods for actual drawing
+        let hdr_view = self.post.hdr_view();
+        // the debug views and the plain wireframe surfaces draw every mesh opaque
+        let shaded = self.wireframe.settings.mode.shades_model()
+            && self.visualization.mode == VisualizationMode::Shaded;
+
+        //we need the nesting because begin_render_pass BORROWS encoder mutably (&mut self) so we can't
+        // call encoder.finish() until we release this mutable borrow
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Render Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    // With MSAA we draw into the multisampled texture and resolve into the HDR target
+                    view: self.msaa_texture.as_ref().map_or(hdr_view, |t| &t.view),
+                    resolve_target: self.msaa_texture.as_ref().map(|_| hdr_view),
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(self.background.clear_color(transparent)),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    //attach depth texture to stencil attatchement of render pass
+                    view: &self.depth_texture.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+
+            if !self.wireframe.settings.mode.shades_model() {
+                self.wireframe.draw_surfaces(
+                    &mut render_pass,
+                    &self.obj_model,
+                    &self.camera_bind_group,
+                );
+            } else if self.visualization.mode != VisualizationMode::Shaded {
+                self.visualization
+                    .draw(&mut render_pass, &self.obj_model, &self.camera_bind_group);
+            } else {
+                use model::DrawModel;
+                render_pass.set_pipeline(&self.render_pipeline);
+                render_pass.set_bind_group(2, &self.shadow_map.bind_group, &[]);
+                // the see-through meshes are left to the transparency
+                for mesh in self.obj_model.meshes.iter().filter(|mesh| !mesh.culled) {
+                    let material = &self.obj_model.materials[mesh.material];
+                    if !self.transparency.is_see_through(material) {
+                        render_pass.draw_mesh(
+                            mesh,
+                            material,
+                            &self.camera_bind_group,
+                            &self.light_bind_group,
+                        );
+                    }
+                }
+            }
+
+            if self.show_light_gizmos {
+                use model::DrawLight;
+                render_pass.set_pipeline(&self.light_pipeline);
+                render_pass.draw_light_model_instanced(
+                    &self.light_gizmo,
+                    0..self.lights.len() as u32,
+                    &self.camera_bind_group,
+                    &self.light_bind_group,
+                );
+            }
+        }
+
+        // the bounds of the meshes against the depth of the opaque ones, for the next frame
+        self.occlusion.render(
+            &self.device,
+            &mut encoder,
+            &self.obj_model,
+            &self.depth_texture.view,
+            &self.camera_bind_group,
+        );
+
+        // the caps close the cross-sections before anything is drawn around the model
+        self.clipping.render(
+            &mut encoder,
+            &self.obj_model,
+            self.msaa_texture.as_ref().map_or(hdr_view, |t| &t.view),
+            self.msaa_texture.as_ref().map(|_| hdr_view),
+            &self.depth_texture.view,
+            &self.camera_bind_group,
+        );
+
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Overlay Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: self.msaa_texture.as_ref().map_or(hdr_view, |t| &t.view),
+                    resolve_target: self.msaa_texture.as_ref().map(|_| hdr_view),
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.depth_texture.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+
+            // after the model, so the depth test rejects everything it covers
+            if !transparent {
+                self.background.draw(&mut render_pass);
+            }
+
+            // the grid and the vectors don't write depth, so they have to come after the background
+            self.grid.draw(&mut render_pass);
+            self.vectors
+                .draw(&mut render_pass, &self.obj_model, &self.camera_bind_group);
+
+            // blended over everything, the see-through lines also over the background
+            self.wireframe
+                .draw_lines(&mut render_pass, &self.obj_model, &self.camera_bind_group);
+        }
+
+        // could do drop(render_pass) here if we dont want braces nesting
+
+        // over the background, so it shows through the see-through meshes
+        if shaded {
+            self.transparency.render(
+                &mut encoder,
+                &self.obj_model,
+                self.msaa_texture.as_ref().map_or(hdr_view, |t| &t.view),
+                self.msaa_texture.as_ref().map(|_| hdr_view),
+                &self.depth_texture.view,
+                &self.camera_bind_group,
+                &self.light_bind_group,
+                &self.shadow_map.bind_group,
+            );
+        }
+
+        // the outline goes around the selected meshes without changing how they are shaded
+        self.outline.render(
+            &mut encoder,
+            &self.obj_model,
+            &self.selection,
+            self.msaa_texture.as_ref().map_or(hdr_view, |t| &t.view),
+            self.msaa_texture.as_ref().map(|_| hdr_view),
+            &self.depth_texture.view,
+            &self.camera_bind_group,
+        );
+
+        // the helpers go over everything, in a pass of their own
+        self.debug_draw.render(
+            &mut encoder,
+            self.msaa_texture.as_ref().map_or(hdr_view, |t| &t.view),
+            self.msaa_texture.as_ref().map(|_| hdr_view),
+            &self.depth_texture.view,
+            &self.camera_bind_group,
+        );
+
+        // exposure, bloom, tonemapping and FXAA into the actual target
+        self.post.render(&mut encoder, view);
+        if !capture {
+            // laid out here, so the text can report on the update before
+            self.hud
+                .update(&self.device, &self.command_queue, self.width, self.height);
+            self.hud.render(&mut encoder, view);
+        }
+
+        // submit will accept anything that implements IntoIter
+        self.command_queue.submit(std::iter::once(encoder.finish()));
+        self.occlusion.read_back();
+    }
+}
+
+/// The lights as they are stored on the GPU. Buffers can't be empty, so no lights are
+/// represented by a single black one.
+fn raw_lights(lights: &[lights::Light]) -> Vec<lights::LightRaw> {
+    if lights.is_empty() {
+        return vec![bytemuck::Zeroable::zeroed()];
+    }
+    lights.iter().map(|light| light.to_raw()).collect()
+}
+
+fn create_light_buffer(device: &wgpu::Device, lights: &[lights::Light]) -> wgpu::Buffer {
+    // We'll want to update the lights, so we use COPY_DST
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Light Buffer"),
+        contents: bytemuck::cast_slice(&raw_lights(lights)),
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+    })
+}
+
+fn create_camera_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    camera_buffer: &wgpu::Buffer,
+    shading_buffer: &wgpu::Buffer,
+    ssao: &Ssao,
+    matcap: &texture::Texture,
+    clip_buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: camera_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: shading_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(ssao.view()),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::TextureView(&matcap.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::Sampler(&matcap.sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: clip_buffer.as_entire_binding(),
+            },
+        ],
+        label: Some("camera_bind_group"),
+    })
+}
+
+fn create_light_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    light_buffer: &wgpu::Buffer,
+    environment: &Environment,
+    clusters: &Clusters,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: light_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(&environment.irradiance_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(&environment.prefiltered_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::TextureView(&environment.brdf_lut_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::Sampler(&environment.sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: clusters.uniform_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 6,
+                resource: clusters.light_counts.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 7,
+                resource: clusters.light_indices.as_entire_binding(),
+            },
+        ],
+        label: None,
+    })
+}
+
+fn clamp_sample_count(supported: &[u32], requested: u32) -> u32 {
+    supported
+        .iter()
+        .copied()
+        .filter(|&count| count <= requested)
+        .max()
+        .unwrap_or(1)
+}
+
+fn create_msaa_texture(
+    device: &wgpu::Device,
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+) -> Option<texture::Texture> {
+    (sample_count > 1).then(|| {
+        texture::Texture::create_multisampled_texture(
+            device,
+            width,
+            height,
+            format,
+            sample_count,
+            "msaa_texture",
+        )
+    })
+}
+
+fn create_shaded_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+) -> wgpu::RenderPipeline {
+    let shader = wgpu::ShaderModuleDescriptor {
+        label: Some("Normal Shader"),
+        source: clipping::shader_source(include_str!("shader.wgsl")),
+    };
+    create_render_pipeline(
+        device,
+        layout,
+        format,
+        Some(texture::Texture::DEPTH_STENCIL_FORMAT),
+        &[model::ModelVertex::desc()],
+        shader,
+        wgpu::PolygonMode::Fill,
+        sample_count,
+    )
+}
+
+fn create_light_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+) -> wgpu::RenderPipeline {
+    let shader = wgpu::ShaderModuleDescriptor {
+        label: Some("Light Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("light.wgsl").into()),
+    };
+    create_render_pipeline(
+        device,
+        layout,
+        format,
+        Some(texture::Texture::DEPTH_STENCIL_FORMAT),
+        &[model::ModelVertex::desc()],
+        shader,
+        wgpu::PolygonMode::Fill,
+        sample_count,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn create_render_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    depth_format: Option<wgpu::TextureFormat>,
+    vertex_layouts: &[wgpu::VertexBufferLayout],
+    shader: wgpu::ShaderModuleDescriptor,
+    poly_mode: wgpu::PolygonMode,
+    sample_count: u32,
+) -> wgpu::RenderPipeline {
+    let shader = device.create_shader_module(shader);
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Render Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: vertex_layouts,
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format: color_format,
+                blend: Some(wgpu::BlendState {
+                    alpha: wgpu::BlendComponent::REPLACE,
+                    color: wgpu::BlendComponent::REPLACE,
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
+            polygon_mode: poly_mode,
+            // Requires Features::DEPTH_CLIP_CONTROL
+            unclipped_depth: false,
+            // Requires Features::CONSERVATIVE_RASTERIZATION
+            conservative: false,
+        },
+        depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
+            format,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
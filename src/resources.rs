@@ -1,8 +1,9 @@
-use crate::model;
+use crate::{environment, model, scene, subdivision, texture};
 use std::io::{BufReader, Cursor};
 
+use anyhow::Context;
+
 use cfg_if::cfg_if;
-use wgpu::util::DeviceExt;
 
 pub async fn load_string(file_name: &str) -> anyhow::Result<String> {
     cfg_if! {
@@ -23,35 +24,195 @@ pub async fn load_string(file_name: &str) -> anyhow::Result<String> {
     Ok(txt)
 }
 
-// pub async fn load_binary(file_name: &str) -> anyhow::Result<Vec<u8>> {
-//     cfg_if! {
-//         if #[cfg(target_arch = "wasm32")] {
-//             let url = format_url(file_name);
-//             let data = reqwest::get(url)
-//                 .await?
-//                 .bytes()
-//                 .await?
-//                 .to_vec();
-//         } else {
-//             let path = std::path::Path::new(env!("OUT_DIR"))
-//                 .join("res")
-//                 .join(file_name);
-//             let data = std::fs::read(path)?;
-//         }
-//     }
-
-//     Ok(data)
-// }
-
-pub async fn load_model(file_name: &str, device: &wgpu::Device) -> anyhow::Result<model::Model> {
+pub async fn load_binary(file_name: &str) -> anyhow::Result<Vec<u8>> {
+    cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            let url = format_url(file_name);
+            let data = reqwest::get(url)
+                .await?
+                .bytes()
+                .await?
+                .to_vec();
+        } else {
+            let path = std::path::Path::new(env!("OUT_DIR"))
+                .join("res")
+                .join(file_name);
+            let data = std::fs::read(path)?;
+        }
+    }
+
+    Ok(data)
+}
+
+pub async fn load_texture(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    srgb: bool,
+) -> anyhow::Result<texture::Texture> {
+    let data = load_binary(file_name).await?;
+    texture::Texture::from_bytes(device, queue, &data, file_name, srgb)
+        .with_context(|| format!("failed to decode {}", file_name))
+}
+
+/// Loads a scene configuration, see [scene::SceneConfig].
+pub async fn load_scene(file_name: &str) -> anyhow::Result<scene::SceneConfig> {
+    let text = load_string(file_name)
+        .await
+        .with_context(|| format!("failed to read {}", file_name))?;
+    toml::from_str(&text).with_context(|| format!("failed to parse {}", file_name))
+}
+
+/// Loads a 3D color grading LUT in the Adobe/Resolve `.cube` format.
+///
+/// Returns the number of entries along each axis and the entries, red changing fastest.
+pub async fn load_cube_lut(file_name: &str) -> anyhow::Result<(u32, Vec<[f32; 3]>)> {
+    let text = load_string(file_name)
+        .await
+        .with_context(|| format!("failed to read {}", file_name))?;
+    parse_cube_lut(&text).with_context(|| format!("failed to parse {}", file_name))
+}
+
+fn parse_cube_lut(text: &str) -> anyhow::Result<(u32, Vec<[f32; 3]>)> {
+    let mut size = None;
+    let mut entries = Vec::new();
+    for line in text.lines().map(str::trim) {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            None => {}
+            Some(word) if word.starts_with('#') => {}
+            Some("LUT_3D_SIZE") => {
+                size = Some(
+                    words
+                        .next()
+                        .context("LUT_3D_SIZE without a value")?
+                        .parse::<u32>()?,
+                )
+            }
+            // The texture is sampled with display encoded colors from 0 to 1
+            Some("DOMAIN_MIN") => {
+                anyhow::ensure!(parse_triple(words)? == [0.0; 3], "DOMAIN_MIN must be 0 0 0")
+            }
+            Some("DOMAIN_MAX") => {
+                anyhow::ensure!(parse_triple(words)? == [1.0; 3], "DOMAIN_MAX must be 1 1 1")
+            }
+            Some("LUT_1D_SIZE") => anyhow::bail!("1D LUTs are not supported"),
+            // TITLE and other keywords don't change the table
+            Some(word) if word.starts_with(|c: char| c.is_ascii_alphabetic()) => {}
+            Some(_) => entries.push(parse_triple(line.split_whitespace())?),
+        }
+    }
+
+    let size = size.context("missing LUT_3D_SIZE")?;
+    anyhow::ensure!(size >= 2, "LUT_3D_SIZE must be at least 2");
+    anyhow::ensure!(
+        entries.len() == (size * size * size) as usize,
+        "expected {} entries but found {}",
+        size * size * size,
+        entries.len()
+    );
+    Ok((size, entries))
+}
+
+fn parse_triple<'a>(mut words: impl Iterator<Item = &'a str>) -> anyhow::Result<[f32; 3]> {
+    let mut triple = [0.0; 3];
+    for value in &mut triple {
+        *value = words.next().context("expected three values")?.parse()?;
+    }
+    Ok(triple)
+}
+
+/// Loads an environment from an equirectangular image, or from six cubemap faces when the
+/// file name contains `{}`, which is replaced by `px`, `nx`, `py`, `ny`, `pz` and `nz`.
+pub async fn load_environment(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> anyhow::Result<environment::Environment> {
+    let max_size = device.limits().max_texture_dimension_2d;
+    if file_name.contains("{}") {
+        let mut faces = Vec::with_capacity(6);
+        for face in ["px", "nx", "py", "ny", "pz", "nz"] {
+            let face_name = file_name.replace("{}", face);
+            let img = load_linear_image(&face_name).await?;
+            anyhow::ensure!(
+                img.width() == img.height() && img.width() <= max_size,
+                "{} must be square and at most {} pixels wide",
+                face_name,
+                max_size
+            );
+            faces.push(img);
+        }
+        let size = faces[0].width();
+        anyhow::ensure!(
+            faces.iter().all(|face| face.width() == size),
+            "the faces of {} differ in size",
+            file_name
+        );
+        return Ok(environment::Environment::from_cube_faces(
+            device,
+            queue,
+            size,
+            std::array::from_fn(|i| faces[i].as_raw().as_slice()),
+        ));
+    }
+
+    let img = load_linear_image(file_name).await?;
+    anyhow::ensure!(
+        img.width() <= max_size && img.height() <= max_size,
+        "{} is larger than the maximum texture size of {}",
+        file_name,
+        max_size
+    );
+    Ok(environment::Environment::from_equirect(
+        device,
+        queue,
+        img.width(),
+        img.height(),
+        img.as_raw(),
+    ))
+}
+
+/// Decodes an image to linear RGBA floats. HDR and EXR images are linear already, every
+/// other format is assumed to be sRGB encoded.
+async fn load_linear_image(file_name: &str) -> anyhow::Result<image::Rgba32FImage> {
+    let data = load_binary(file_name).await?;
+    let img = image::load_from_memory(&data)
+        .with_context(|| format!("failed to decode {}", file_name))?;
+    let is_float = matches!(
+        img,
+        image::DynamicImage::ImageRgb32F(_) | image::DynamicImage::ImageRgba32F(_)
+    );
+    let mut img = img.to_rgba32f();
+    if !is_float {
+        for pixel in img.pixels_mut() {
+            for c in &mut pixel.0[..3] {
+                *c = if *c <= 0.04045 {
+                    *c / 12.92
+                } else {
+                    ((*c + 0.055) / 1.055).powf(2.4)
+                };
+            }
+        }
+    }
+    Ok(img)
+}
+
+pub async fn load_model(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    material_layout: &wgpu::BindGroupLayout,
+) -> anyhow::Result<model::Model> {
     let obj_text = load_string(file_name).await?;
     let obj_cursor = Cursor::new(obj_text);
     let mut obj_reader = BufReader::new(obj_cursor);
 
-    let (models, _) = tobj::load_obj_buf_async(
+    let (models, obj_materials) = tobj::load_obj_buf_async(
         &mut obj_reader,
         &tobj::LoadOptions {
-            triangulate: true,
+            // Keep quads and n-gons so they can be subdivided with Catmull-Clark
+            triangulate: false,
             single_index: true,
             ..Default::default()
         },
@@ -62,30 +223,45 @@ pub async fn load_model(file_name: &str, device: &wgpu::Device) -> anyhow::Resul
     )
     .await?;
 
-    let materials = Vec::new();
-    // for m in obj_materials? {
-    //     // let diffuse_texture = load_texture(&m.diffuse_texture, device, queue).await?;
-    //     let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-    //         layout,
-    //         entries: &[
-    //             wgpu::BindGroupEntry {
-    //                 binding: 0,
-    //                 resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-    //             },
-    //             wgpu::BindGroupEntry {
-    //                 binding: 1,
-    //                 resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-    //             },
-    //         ],
-    //         label: None,
-    //     });
-
-    //     materials.push(model::Material {
-    //         name: m.name,
-    //         // diffuse_texture,
-    //         bind_group,
-    //     })
-    // }
+    let mut materials = Vec::new();
+    for m in obj_materials? {
+        let uniform = material_uniform(&m);
+        let mut textures = model::MaterialTextures::white(device, queue);
+        // map_Pm/map_Pr are the PBR extension to MTL, map_Ka is commonly used for baked occlusion
+        let slots = [
+            (m.diffuse_texture.as_ref(), &mut textures.base_color, true),
+            (m.unknown_param.get("map_Pm"), &mut textures.metallic, false),
+            (
+                m.unknown_param.get("map_Pr"),
+                &mut textures.roughness,
+                false,
+            ),
+            (m.ambient_texture.as_ref(), &mut textures.occlusion, false),
+            (m.unknown_param.get("map_Ke"), &mut textures.emissive, true),
+        ];
+        for (file, slot, srgb) in slots {
+            if let Some(file) = file {
+                *slot = load_texture(file, device, queue, srgb).await?;
+            }
+        }
+
+        materials.push(model::Material::new(
+            device,
+            &m.name,
+            uniform,
+            textures,
+            material_layout,
+        ));
+    }
+    if materials.is_empty() {
+        materials.push(model::Material::new(
+            device,
+            "default",
+            model::MaterialUniform::default(),
+            model::MaterialTextures::white(device, queue),
+            material_layout,
+        ));
+    }
 
     let meshes = models
         .into_iter()
@@ -97,32 +273,75 @@ pub async fn load_model(file_name: &str, device: &wgpu::Device) -> anyhow::Resul
                         m.mesh.positions[i * 3 + 1],
                         m.mesh.positions[i * 3 + 2],
                     ],
-                    normal: [
-                        m.mesh.normals[i * 3],
-                        m.mesh.normals[i * 3 + 1],
-                        m.mesh.normals[i * 3 + 2],
-                    ],
+                    normal: if m.mesh.normals.is_empty() {
+                        [0.0; 3]
+                    } else {
+                        [
+                            m.mesh.normals[i * 3],
+                            m.mesh.normals[i * 3 + 1],
+                            m.mesh.normals[i * 3 + 2],
+                        ]
+                    },
+                    // OBJ puts v = 0 at the bottom of the image, textures start at the top
+                    tex_coords: if m.mesh.texcoords.is_empty() {
+                        [0.0; 2]
+                    } else {
+                        [m.mesh.texcoords[i * 2], 1.0 - m.mesh.texcoords[i * 2 + 1]]
+                    },
                 })
                 .collect::<Vec<_>>();
 
-            let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Vertex Buffer", file_name)),
-                contents: bytemuck::cast_slice(&vertices),
-                usage: wgpu::BufferUsages::VERTEX,
-            });
-            let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Index Buffer", file_name)),
-                contents: bytemuck::cast_slice(&m.mesh.indices),
-                usage: wgpu::BufferUsages::INDEX,
-            });
-
-            model::Mesh {
-                name: file_name.to_string(),
-                vertex_buffer,
-                index_buffer,
-                num_elements: m.mesh.indices.len() as u32,
-                material: m.mesh.material_id.unwrap_or(0),
-            }
+            // face_arities is left empty by tobj when every face is a triangle
+            let faces = if m.mesh.face_arities.is_empty() {
+                m.mesh
+                    .indices
+                    .chunks(3)
+                    .map(|f| f.to_vec())
+                    .collect::<Vec<_>>()
+            } else {
+                let mut start = 0;
+                m.mesh
+                    .face_arities
+                    .iter()
+                    .map(|&arity| {
+                        let face = m.mesh.indices[start..start + arity as usize].to_vec();
+                        start += arity as usize;
+                        face
+                    })
+                    .collect::<Vec<_>>()
+            };
+
+            // Files without normals get smooth ones computed from the polygons
+            let vertices = if m.mesh.normals.is_empty() {
+                let smooth = subdivision::PolyMesh {
+                    positions: vertices.iter().map(|v| v.position.into()).collect(),
+                    faces: faces.clone(),
+                    face_uvs: Vec::new(),
+                }
+                .to_triangles()
+                .0;
+                vertices
+                    .into_iter()
+                    .zip(smooth)
+                    .map(|(vertex, smooth)| model::ModelVertex {
+                        normal: smooth.normal,
+                        ..vertex
+                    })
+                    .collect()
+            } else {
+                vertices
+            };
+
+            model::Mesh::new(
+                device,
+                file_name,
+                vertices,
+                faces,
+                m.mesh
+                    .material_id
+                    .filter(|&id| id < materials.len())
+                    .unwrap_or(0),
+            )
         })
         .collect::<Vec<_>>();
 
@@ -130,3 +349,102 @@ pub async fn load_model(file_name: &str, device: &wgpu::Device) -> anyhow::Resul
 
     Ok(model::Model { meshes, materials })
 }
+
+/// Maps the Phong style MTL parameters to metallic-roughness.
+///
+/// The PBR extension (`Pm`, `Pr`, `Ke`) is used when present. Otherwise the roughness is
+/// derived from the specular exponent `Ns`.
+fn material_uniform(m: &tobj::Material) -> model::MaterialUniform {
+    let defaults = model::MaterialUniform::default();
+    let param = |key: &str| {
+        m.unknown_param
+            .get(key)
+            .and_then(|v| v.trim().parse::<f32>().ok())
+    };
+
+    let base_color = m.diffuse.map_or(defaults.base_color, |[r, g, b]| {
+        [r, g, b, m.dissolve.unwrap_or(1.0)]
+    });
+    let emissive = m
+        .unknown_param
+        .get("Ke")
+        .and_then(|v| {
+            let values = v
+                .split_whitespace()
+                .map(|c| c.parse::<f32>().ok())
+                .collect::<Option<Vec<_>>>()?;
+            <[f32; 3]>::try_from(values).ok()
+        })
+        .unwrap_or(defaults.emissive);
+    // Blinn-Phong exponent to GGX alpha is roughly alpha = sqrt(2 / (Ns + 2)), and the
+    // perceptual roughness is the square root of alpha
+    let roughness = param("Pr").unwrap_or_else(|| {
+        m.shininess.map_or(defaults.roughness, |ns| {
+            (2.0 / (ns.max(0.0) + 2.0)).sqrt().sqrt()
+        })
+    });
+
+    model::MaterialUniform {
+        base_color,
+        emissive,
+        metallic: param("Pm").unwrap_or(defaults.metallic),
+        roughness,
+        ..defaults
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IDENTITY_2: &str = "TITLE \"identity\"
+# red changes fastest
+LUT_3D_SIZE 2
+DOMAIN_MIN 0 0 0
+DOMAIN_MAX 1 1 1
+
+0 0 0
+1 0 0
+0 1 0
+1 1 0
+0 0 1
+1 0 1
+0 1 1
+1 1 1
+";
+
+    #[test]
+    fn parses_a_3d_lut() {
+        let (size, entries) = parse_cube_lut(IDENTITY_2).unwrap();
+        assert_eq!(size, 2);
+        assert_eq!(entries.len(), 8);
+        assert_eq!(entries[1], [1.0, 0.0, 0.0]);
+        assert_eq!(entries[6], [0.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn rejects_a_missing_size() {
+        let text = IDENTITY_2.replace("LUT_3D_SIZE 2\n", "");
+        let error = parse_cube_lut(&text).unwrap_err();
+        assert!(error.to_string().contains("LUT_3D_SIZE"), "{}", error);
+    }
+
+    #[test]
+    fn rejects_a_wrong_entry_count() {
+        let text = IDENTITY_2.strip_suffix("1 1 1\n").unwrap();
+        let error = parse_cube_lut(text).unwrap_err();
+        assert!(
+            error.to_string().contains("expected 8 entries"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn rejects_another_domain() {
+        let text = IDENTITY_2.replace("DOMAIN_MIN 0 0 0", "DOMAIN_MIN -1 -1 -1");
+        assert!(parse_cube_lut(&text).is_err());
+        let text = IDENTITY_2.replace("DOMAIN_MAX 1 1 1", "DOMAIN_MAX 2 2 2");
+        assert!(parse_cube_lut(&text).is_err());
+    }
+}
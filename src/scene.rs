@@ -0,0 +1,32 @@
+use crate::lights::Light;
+
+/// The contents of a scene configuration file, a TOML file in the `res` folder:
+///
+/// ```toml
+/// [[lights]]
+/// kind = "spot"
+/// position = [0.0, 3.0, 0.0]
+/// direction = [0.0, -1.0, 0.0]
+/// color = [1.0, 0.9, 0.8]
+/// intensity = 60.0
+/// ```
+///
+/// Every field of a light is optional, see [Light::default] for the values used otherwise.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct SceneConfig {
+    /// The first light also casts the shadows.
+    #[serde(default = "default_lights")]
+    pub lights: Vec<Light>,
+}
+
+impl Default for SceneConfig {
+    fn default() -> Self {
+        Self {
+            lights: default_lights(),
+        }
+    }
+}
+
+fn default_lights() -> Vec<Light> {
+    vec![Light::default()]
+}
@@ -5,23 +5,147 @@ struct Camera {
 @group(0) @binding(0)
 var<uniform> camera: Camera;
 
+struct Shading {
+    model: u32,
+    environment_intensity: f32,
+    cluster_heatmap: u32,
+    selected_light: u32,
+    ambient_occlusion: u32,
+    toon_bands: u32,
+    rim_intensity: f32,
+    rim_width: f32,
+    xray: u32,
+    xray_opacity: f32,
+}
+@group(0) @binding(1)
+var<uniform> shading: Shading;
+// Written by ssao.wgsl, only valid if shading.ambient_occlusion is set
+@group(0) @binding(2)
+var ambient_occlusion_texture: texture_2d<f32>;
+// A shaded sphere, only used by SHADING_MATCAP
+@group(0) @binding(3)
+var matcap_texture: texture_2d<f32>;
+@group(0) @binding(4)
+var matcap_sampler: sampler;
+
+// See clip.wgsl
+@group(0) @binding(5)
+var<uniform> clip_planes: ClipPlanes;
+
+// Values of Shading.model, see shading::ShadingModel
+const SHADING_PBR: u32 = 0u;
+const SHADING_PHONG: u32 = 1u;
+const SHADING_MATCAP: u32 = 2u;
+const SHADING_TOON: u32 = 3u;
+const SHADING_GOOCH: u32 = 4u;
+const SHADING_HATCHING: u32 = 5u;
+
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) normal: vec3<f32>,
+    @location(2) tex_coords: vec2<f32>,
 }
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) world_normal: vec3<f32>,
     @location(1) world_position: vec3<f32>,
+    @location(2) tex_coords: vec2<f32>,
 }
 
+// See lights::LightRaw
 struct Light {
     position: vec3<f32>,
+    kind: u32,
+    direction: vec3<f32>,
+    range: f32,
     color: vec3<f32>,
+    intensity: f32,
+    inner_cone_cos: f32,
+    outer_cone_cos: f32,
 }
 @group(1) @binding(0)
-var<uniform> light: Light;
+var<storage, read> lights: array<Light>;
+
+// Image based lighting, see environment.rs
+@group(1) @binding(1)
+var irradiance_map: texture_cube<f32>;
+@group(1) @binding(2)
+var prefiltered_map: texture_cube<f32>;
+@group(1) @binding(3)
+var brdf_lut: texture_2d<f32>;
+@group(1) @binding(4)
+var environment_sampler: sampler;
+
+// The lights that reach each cluster, written by clusters.wgsl
+struct Clusters {
+    inv_projection: mat4x4<f32>,
+    view: mat4x4<f32>,
+    screen_size: vec2<f32>,
+    near: f32,
+    far: f32,
+}
+@group(1) @binding(5)
+var<uniform> clusters: Clusters;
+@group(1) @binding(6)
+var<storage, read> cluster_light_counts: array<u32>;
+@group(1) @binding(7)
+var<storage, read> cluster_light_indices: array<u32>;
+
+// Must match the constants in clusters.rs
+const CLUSTERS_X: u32 = 16u;
+const CLUSTERS_Y: u32 = 9u;
+const CLUSTERS_Z: u32 = 24u;
+const MAX_LIGHTS_PER_CLUSTER: u32 = 128u;
+
+// Must match environment::PREFILTERED_MIP_LEVELS
+const PREFILTERED_MIP_LEVELS: f32 = 5.0;
+
+// Values of Light.kind, see lights::LightKind
+const LIGHT_POINT: u32 = 0u;
+const LIGHT_SPOT: u32 = 1u;
+const LIGHT_DIRECTIONAL: u32 = 2u;
+
+struct Shadow {
+    light_view_proj: mat4x4<f32>,
+    depth_bias: f32,
+    normal_bias: f32,
+    pcf_radius: f32,
+    texel_size: f32,
+}
+@group(2) @binding(0)
+var<uniform> shadow: Shadow;
+@group(2) @binding(1)
+var shadow_map: texture_depth_2d;
+@group(2) @binding(2)
+var shadow_sampler: sampler_comparison;
+
+struct Material {
+    base_color: vec4<f32>,
+    emissive: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+    occlusion_strength: f32,
+}
+@group(3) @binding(0)
+var<uniform> material: Material;
+@group(3) @binding(1)
+var base_color_texture: texture_2d<f32>;
+@group(3) @binding(2)
+var metallic_texture: texture_2d<f32>;
+@group(3) @binding(3)
+var roughness_texture: texture_2d<f32>;
+@group(3) @binding(4)
+var occlusion_texture: texture_2d<f32>;
+@group(3) @binding(5)
+var emissive_texture: texture_2d<f32>;
+@group(3) @binding(6)
+var material_sampler: sampler;
+
+const PI: f32 = 3.14159265;
+
+// The distance between the lines of the hatching in pixels
+const HATCH_SPACING: f32 = 6.0;
 
 @vertex
 fn vs_main(
@@ -55,35 +179,219 @@ fn vs_main(
     var world_position: vec4<f32> = vec4<f32>(model.position, 1.0); //TODO!(Investigate) Will need to correct before allowing users to rotate the models
 
     out.world_position = world_position.xyz;
+    out.tex_coords = model.tex_coords;
     out.clip_position = camera.view_proj * world_position;
     return out;
 }
 
 
-// Fragment shader
-@fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    let object_color = vec4<f32>(0.9,0.8,0.8, 1.0);
-    // let object_color = vec4<f32>(in.world_normal, 1.0);
+// 1.0 where the light reaches the fragment, 0.0 where it is in shadow, averaged over the PCF kernel
+fn shadow_factor(world_position: vec3<f32>, world_normal: vec3<f32>) -> f32 {
+    let biased_position = world_position + world_normal * shadow.normal_bias;
+    let light_clip = shadow.light_view_proj * vec4<f32>(biased_position, 1.0);
+    if light_clip.w <= 0.0 {
+        return 1.0;
+    }
+    let light_ndc = light_clip.xyz / light_clip.w;
+    // Texture coordinates have y pointing down
+    let uv = vec2<f32>(light_ndc.x * 0.5 + 0.5, -light_ndc.y * 0.5 + 0.5);
+    if any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) || light_ndc.z > 1.0 {
+        return 1.0;
+    }
 
-    let light_dir = normalize(light.position - in.world_position);
-    let view_dir = normalize(camera.view_pos.xyz - in.world_position);
+    let depth = light_ndc.z - shadow.depth_bias;
+    let radius = i32(shadow.pcf_radius);
+    var lit = 0.0;
+    for (var y = -radius; y <= radius; y++) {
+        for (var x = -radius; x <= radius; x++) {
+            let offset = vec2<f32>(f32(x), f32(y)) * shadow.texel_size;
+            // The Level variant because this is not in uniform control flow
+            lit += textureSampleCompareLevel(shadow_map, shadow_sampler, uv + offset, depth);
+        }
+    }
+    let samples = f32((2 * radius + 1) * (2 * radius + 1));
+    return lit / samples;
+}
+
+// Direction towards a light and the radiance that arrives, including the shadow
+struct LightSample {
+    direction: vec3<f32>,
+    radiance: vec3<f32>,
+}
+
+fn sample_light(light: Light, world_position: vec3<f32>) -> LightSample {
+    var out: LightSample;
+    if light.kind == LIGHT_DIRECTIONAL {
+        out.direction = -light.direction;
+        out.radiance = light.color * light.intensity;
+        return out;
+    }
+
+    let to_light = light.position - world_position;
+    let distance2 = max(dot(to_light, to_light), 1e-4);
+    out.direction = to_light * inverseSqrt(distance2);
+    // Inverse square falloff, windowed so it reaches exactly zero at the range
+    var attenuation = 1.0 / distance2;
+    if light.range > 0.0 {
+        let ratio2 = distance2 / (light.range * light.range);
+        let window = clamp(1.0 - ratio2 * ratio2, 0.0, 1.0);
+        attenuation *= window * window;
+    }
+    if light.kind == LIGHT_SPOT {
+        let spot_cos = dot(-out.direction, light.direction);
+        attenuation *= smoothstep(light.outer_cone_cos, light.inner_cone_cos, spot_cos);
+    }
+    out.radiance = light.color * light.intensity * attenuation;
+    return out;
+}
+
+// The lights of the scene, only the first one casts shadows
+fn scene_light(index: u32, world_position: vec3<f32>, world_normal: vec3<f32>) -> LightSample {
+    var light_sample = sample_light(lights[index], world_position);
+    if index == 0u {
+        light_sample.radiance *= shadow_factor(world_position, world_normal);
+    }
+    return light_sample;
+}
+
+// The cluster of the fragment at `frag_coord`, from its framebuffer position and view depth
+fn cluster_index(frag_coord: vec2<f32>, world_position: vec3<f32>) -> u32 {
+    let tile = vec2<u32>(clamp(frag_coord / clusters.screen_size, vec2<f32>(0.0), vec2<f32>(0.9999)) * vec2<f32>(f32(CLUSTERS_X), f32(CLUSTERS_Y)));
+    let depth = -(clusters.view * vec4<f32>(world_position, 1.0)).z;
+    let slice = log(max(depth, clusters.near) / clusters.near) / log(clusters.far / clusters.near);
+    let z = min(u32(max(slice, 0.0) * f32(CLUSTERS_Z)), CLUSTERS_Z - 1u);
+    return tile.x + tile.y * CLUSTERS_X + z * CLUSTERS_X * CLUSTERS_Y;
+}
+
+// The index into `lights` of the `n`th light in `cluster`
+fn cluster_light(cluster: u32, n: u32) -> u32 {
+    return cluster_light_indices[cluster * MAX_LIGHTS_PER_CLUSTER + n];
+}
+
+// Dark blue for no lights, then cyan over green and yellow to red at 32 or more
+fn heatmap(count: u32) -> vec3<f32> {
+    if count == 0u {
+        return vec3<f32>(0.0, 0.0, 0.3);
+    }
+    let t = clamp(f32(count) / 32.0, 0.0, 1.0);
+    return clamp(vec3<f32>(2.0 * t, 2.0 - 2.0 * t, 1.0 - 4.0 * t), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+// The material inputs after the textures have been applied
+struct Surface {
+    base_color: vec4<f32>,
+    metallic: f32,
+    roughness: f32,
+    occlusion: f32,
+    emissive: vec3<f32>,
+}
+
+fn sample_surface(tex_coords: vec2<f32>) -> Surface {
+    var out: Surface;
+    out.base_color = material.base_color * textureSample(base_color_texture, material_sampler, tex_coords);
+    out.metallic = material.metallic * textureSample(metallic_texture, material_sampler, tex_coords).r;
+    out.roughness = material.roughness * textureSample(roughness_texture, material_sampler, tex_coords).r;
+    let occlusion = textureSample(occlusion_texture, material_sampler, tex_coords).r;
+    out.occlusion = mix(1.0, occlusion, material.occlusion_strength);
+    out.emissive = material.emissive * textureSample(emissive_texture, material_sampler, tex_coords).rgb;
+    return out;
+}
+
+// -- BRDF -- //
+
+// GGX / Trowbridge-Reitz normal distribution
+fn distribution_ggx(n_dot_h: f32, alpha: f32) -> f32 {
+    let alpha2 = alpha * alpha;
+    let d = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+    return alpha2 / (PI * d * d);
+}
+
+// Height correlated Smith masking-shadowing, already divided by 4 n.l n.v
+fn visibility_smith_ggx(n_dot_v: f32, n_dot_l: f32, alpha: f32) -> f32 {
+    let alpha2 = alpha * alpha;
+    let ggx_v = n_dot_l * sqrt(n_dot_v * n_dot_v * (1.0 - alpha2) + alpha2);
+    let ggx_l = n_dot_v * sqrt(n_dot_l * n_dot_l * (1.0 - alpha2) + alpha2);
+    return 0.5 / max(ggx_v + ggx_l, 1e-5);
+}
+
+fn fresnel_schlick(cos_theta: f32, f0: vec3<f32>) -> vec3<f32> {
+    return f0 + (vec3<f32>(1.0) - f0) * pow(1.0 - cos_theta, 5.0);
+}
+
+// The light of one LightSample reflected towards the viewer
+fn direct_pbr(diffuse_color: vec3<f32>, f0: vec3<f32>, alpha: f32, normal: vec3<f32>, view_dir: vec3<f32>, light_sample: LightSample) -> vec3<f32> {
+    let light_dir = light_sample.direction;
     let half_dir = normalize(view_dir + light_dir);
-    
-    // -- AMBIENT -- //
+    let n_dot_l = max(dot(normal, light_dir), 0.0);
+    let n_dot_v = max(dot(normal, view_dir), 1e-4);
+    let n_dot_h = max(dot(normal, half_dir), 0.0);
+    let v_dot_h = max(dot(view_dir, half_dir), 0.0);
+
+    let fresnel = fresnel_schlick(v_dot_h, f0);
+    let specular = distribution_ggx(n_dot_h, alpha) * visibility_smith_ggx(n_dot_v, n_dot_l, alpha) * fresnel;
+    let diffuse = (vec3<f32>(1.0) - fresnel) * diffuse_color / PI;
+
+    return (diffuse + specular) * light_sample.radiance * n_dot_l;
+}
+
+fn shade_pbr(surface: Surface, world_position: vec3<f32>, normal: vec3<f32>, view_dir: vec3<f32>, cluster: u32, ambient_occlusion: f32) -> vec3<f32> {
+    // Very low roughness makes the highlight vanish between pixels
+    let roughness = clamp(surface.roughness, 0.045, 1.0);
+    let alpha = roughness * roughness;
+    let diffuse_color = surface.base_color.rgb * (1.0 - surface.metallic);
+    // Dielectrics reflect about 4% at normal incidence, metals tint the reflection
+    let f0 = mix(vec3<f32>(0.04), surface.base_color.rgb, surface.metallic);
+
+    var direct = vec3<f32>(0.0);
+    for (var n = 0u; n < cluster_light_counts[cluster]; n++) {
+        let light_sample = scene_light(cluster_light(cluster, n), world_position, normal);
+        direct += direct_pbr(diffuse_color, f0, alpha, normal, view_dir, light_sample);
+    }
+    let n_dot_v = max(dot(normal, view_dir), 1e-4);
+
+    // -- IMAGE BASED LIGHTING -- //
+    // Split sum approximation: prefiltered radiance times the integrated BRDF
+    let reflect_dir = reflect(-view_dir, normal);
+    let prefiltered = textureSampleLevel(prefiltered_map, environment_sampler, reflect_dir, roughness * (PREFILTERED_MIP_LEVELS - 1.0)).rgb;
+    let brdf = textureSampleLevel(brdf_lut, environment_sampler, vec2<f32>(n_dot_v, roughness), 0.0).rg;
+    let specular_ibl = prefiltered * (f0 * brdf.x + brdf.y);
+    let irradiance = textureSampleLevel(irradiance_map, environment_sampler, normal, 0.0).rgb;
+    // Light that is reflected specularly can't also be diffused
+    let diffuse_ibl = irradiance * diffuse_color * (vec3<f32>(1.0) - (f0 * brdf.x + brdf.y));
+    let ambient = (diffuse_ibl + specular_ibl) * surface.occlusion * ambient_occlusion * shading.environment_intensity;
+
+    return direct + ambient + surface.emissive;
+}
+
+// The original shading of the viewer, kept as the legacy mode
+fn shade_phong(in: VertexOutput, object_color: vec4<f32>, cluster: u32, ambient_occlusion: f32) -> vec4<f32> {
+    let view_dir = normalize(camera.view_pos.xyz - in.world_position);
     let ambient_strength = 0.2;
-    let ambient_color = light.color * ambient_strength;
 
-    // -- DIFFUSE -- //
-    let diffuse_strength = max(dot(in.world_normal, light_dir), 0.0);
-    let diffuse_color = light.color * diffuse_strength;
+    // -- AMBIENT -- //
+    // Once from the first light, the main light of the scene, however many lights there are
+    var light_color = lights[0].color * ambient_strength * ambient_occlusion;
+    for (var n = 0u; n < cluster_light_counts[cluster]; n++) {
+        let i = cluster_light(cluster, n);
+        let light_sample = scene_light(i, in.world_position, in.world_normal);
+        let light_dir = light_sample.direction;
+        let half_dir = normalize(view_dir + light_dir);
+        // The Lambertian BRDF divides by PI, Phong doesn't
+        let radiance = light_sample.radiance / PI;
+
+        // -- DIFFUSE -- //
+        let diffuse_strength = max(dot(in.world_normal, light_dir), 0.0);
+        let diffuse_color = radiance * diffuse_strength;
 
-    // -- SPECULAR -- //
-    let specular_strength = pow(max(dot(in.world_normal, half_dir), 0.0), 32.0);
-    let specular_color = specular_strength * light.color;
+        // -- SPECULAR -- //
+        let specular_strength = pow(max(dot(in.world_normal, half_dir), 0.0), 32.0);
+        let specular_color = specular_strength * radiance;
+
+        light_color += diffuse_color + specular_color;
+    }
 
     // -- RESULT -- //
-    let result = (ambient_color + diffuse_color + specular_color) * object_color.xyz;
+    let result = light_color * object_color.xyz;
 
     // return vec4<f32>(result, object_color.a);
 
@@ -96,6 +404,196 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     // Compute the Fresnel factor using the dot product between the view direction and the normal
     let fresnelFactor = abs(dot(viewDirection, in.world_normal));
 
-    return vec4<f32>(result, object_color.a) * mix(0.5,1.0,fresnel);
+    // Only darken the color, the alpha has to stay intact for transparent screenshots
+    return vec4<f32>(result * mix(0.5,1.0,fresnel), object_color.a);
     // return vec4<f32>(result, object_color.a);
-}
\ No newline at end of file
+}
+
+// -- NON-PHOTOREALISTIC -- //
+
+fn luminance(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+// The light from the environment, the same for every non-photorealistic model
+fn ambient_light(normal: vec3<f32>, occlusion: f32) -> vec3<f32> {
+    let irradiance = textureSampleLevel(irradiance_map, environment_sampler, normal, 0.0).rgb;
+    return irradiance * occlusion * shading.environment_intensity;
+}
+
+// Looks the color up in the matcap by the normal as seen from the camera. The view direction
+// turns the lookup so the sphere doesn't slide sideways towards the edges of the screen.
+fn shade_matcap(surface: Surface, normal: vec3<f32>, view_dir: vec3<f32>) -> vec3<f32> {
+    let view_normal = (clusters.view * vec4<f32>(normal, 0.0)).xyz;
+    let view_eye = (clusters.view * vec4<f32>(view_dir, 0.0)).xyz;
+    let x = normalize(vec3<f32>(view_eye.z, 0.0, -view_eye.x));
+    let y = cross(view_eye, x);
+    // Texture coordinates have y pointing down, and stay off the very edge of the sphere
+    let uv = vec2<f32>(dot(x, view_normal), -dot(y, view_normal)) * 0.495 + 0.5;
+    let matcap = textureSampleLevel(matcap_texture, matcap_sampler, uv, 0.0).rgb;
+    return matcap * surface.base_color.rgb + surface.emissive;
+}
+
+// Cel shading: the cosine of every light is rounded up to one of shading.toon_bands flat bands,
+// with a hard highlight and a rim of light along the silhouette
+fn shade_toon(surface: Surface, world_position: vec3<f32>, normal: vec3<f32>, view_dir: vec3<f32>, cluster: u32, ambient_occlusion: f32) -> vec3<f32> {
+    let bands = f32(shading.toon_bands);
+    var diffuse = vec3<f32>(0.0);
+    var specular = vec3<f32>(0.0);
+    // What a surface facing all the lights would get, the rim light is a fraction of it
+    var full = vec3<f32>(0.0);
+    for (var n = 0u; n < cluster_light_counts[cluster]; n++) {
+        let light_sample = scene_light(cluster_light(cluster, n), world_position, normal);
+        let radiance = light_sample.radiance / PI;
+        let n_dot_l = max(dot(normal, light_sample.direction), 0.0);
+        diffuse += radiance * ceil(n_dot_l * bands) / bands;
+        let half_dir = normalize(view_dir + light_sample.direction);
+        let highlight = step(0.96, dot(normal, half_dir)) * step(1e-4, n_dot_l);
+        specular += radiance * highlight * (1.0 - surface.roughness);
+        full += radiance;
+    }
+
+    let n_dot_v = max(dot(normal, view_dir), 0.0);
+    let rim_edge = 1.0 - shading.rim_width;
+    let rim = smoothstep(rim_edge - 0.02, rim_edge + 0.02, 1.0 - n_dot_v) * shading.rim_intensity;
+
+    let ambient = ambient_light(normal, surface.occlusion * ambient_occlusion);
+    return surface.base_color.rgb * (ambient + diffuse + full * rim) + specular + surface.emissive;
+}
+
+// Gooch shading: blends from a cool blue facing away from the lights to a warm yellow facing
+// them, so the shape stays readable in the shadows. Lights count by how bright they are.
+fn shade_gooch(surface: Surface, world_position: vec3<f32>, normal: vec3<f32>, view_dir: vec3<f32>, cluster: u32) -> vec3<f32> {
+    let cool = vec3<f32>(0.0, 0.0, 0.55) + 0.25 * surface.base_color.rgb;
+    let warm = vec3<f32>(0.3, 0.3, 0.0) + 0.5 * surface.base_color.rgb;
+    var color = vec3<f32>(0.0);
+    var highlight = 0.0;
+    var weight = 0.0;
+    for (var n = 0u; n < cluster_light_counts[cluster]; n++) {
+        let light_sample = scene_light(cluster_light(cluster, n), world_position, normal);
+        let light_weight = luminance(light_sample.radiance);
+        let t = (1.0 + dot(normal, light_sample.direction)) * 0.5;
+        color += mix(cool, warm, t) * light_weight;
+        let half_dir = normalize(view_dir + light_sample.direction);
+        highlight += pow(max(dot(normal, half_dir), 0.0), 32.0) * light_weight;
+        weight += light_weight;
+    }
+    if weight <= 0.0 {
+        return cool + surface.emissive;
+    }
+    return mix(color / weight, vec3<f32>(1.0), highlight / weight) + surface.emissive;
+}
+
+// Coverage of lines across `direction`, HATCH_SPACING pixels apart, that get wider the further
+// `tone` falls below `threshold`
+fn hatch(frag_coord: vec2<f32>, direction: vec2<f32>, tone: f32, threshold: f32) -> f32 {
+    let darkness = clamp((threshold - tone) / 0.25, 0.0, 1.0);
+    let distance = abs(fract(dot(frag_coord, normalize(direction)) / HATCH_SPACING) - 0.5) * HATCH_SPACING;
+    let half_width = darkness * HATCH_SPACING * 0.3;
+    // Anti-aliased over one pixel, and gone entirely above the threshold
+    return clamp(half_width + 0.5 - distance, 0.0, 1.0) * step(1e-4, darkness);
+}
+
+// Pen and ink: layers of lines at different angles in screen space, each one drawn where the
+// light is darker than its threshold
+fn shade_hatching(surface: Surface, world_position: vec3<f32>, normal: vec3<f32>, cluster: u32, frag_coord: vec2<f32>, ambient_occlusion: f32) -> vec3<f32> {
+    var light = ambient_light(normal, surface.occlusion * ambient_occlusion);
+    for (var n = 0u; n < cluster_light_counts[cluster]; n++) {
+        let light_sample = scene_light(cluster_light(cluster, n), world_position, normal);
+        light += light_sample.radiance / PI * max(dot(normal, light_sample.direction), 0.0);
+    }
+    let tone = clamp(luminance(light), 0.0, 1.0);
+
+    var ink = hatch(frag_coord, vec2<f32>(1.0, 1.0), tone, 0.9);
+    ink = max(ink, hatch(frag_coord, vec2<f32>(1.0, -1.0), tone, 0.6));
+    ink = max(ink, hatch(frag_coord, vec2<f32>(0.0, 1.0), tone, 0.35));
+    ink = max(ink, hatch(frag_coord, vec2<f32>(1.0, 0.0), tone, 0.15));
+    // The base color is the paper, the ink doesn't go fully black
+    return surface.base_color.rgb * (1.0 - 0.85 * ink) + surface.emissive;
+}
+
+// The color of a fragment in the chosen shading model, with the alpha of its surface
+fn shade_fragment(in: VertexOutput, ambient_occlusion: f32) -> vec4<f32> {
+    // Sampled before any branching, textureSample needs uniform control flow
+    let surface = sample_surface(in.tex_coords);
+    if clipped(clip_planes, in.world_position) {
+        discard;
+    }
+    let cluster = cluster_index(in.clip_position.xy, in.world_position);
+
+    if shading.cluster_heatmap != 0u {
+        return vec4<f32>(heatmap(cluster_light_counts[cluster]), 1.0);
+    }
+
+    if shading.model == SHADING_PHONG {
+        return shade_phong(in, surface.base_color, cluster, ambient_occlusion);
+    }
+
+    let normal = normalize(in.world_normal);
+    let view_dir = normalize(camera.view_pos.xyz - in.world_position);
+    var color: vec3<f32>;
+    switch shading.model {
+        case SHADING_MATCAP: {
+            color = shade_matcap(surface, normal, view_dir);
+        }
+        case SHADING_TOON: {
+            color = shade_toon(surface, in.world_position, normal, view_dir, cluster, ambient_occlusion);
+        }
+        case SHADING_GOOCH: {
+            color = shade_gooch(surface, in.world_position, normal, view_dir, cluster);
+        }
+        case SHADING_HATCHING: {
+            color = shade_hatching(surface, in.world_position, normal, cluster, in.clip_position.xy, ambient_occlusion);
+        }
+        default: {
+            color = shade_pbr(surface, in.world_position, normal, view_dir, cluster, ambient_occlusion);
+        }
+    }
+    return vec4<f32>(color, surface.base_color.a);
+}
+
+// Fragment shader
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    var ambient_occlusion = 1.0;
+    if shading.ambient_occlusion != 0u {
+        ambient_occlusion = textureLoad(ambient_occlusion_texture, vec2<i32>(in.clip_position.xy), 0).r;
+    }
+    return shade_fragment(in, ambient_occlusion);
+}
+
+// -- TRANSPARENCY -- //
+
+// Summed up by the weighted blended transparency, see transparency.rs
+struct AccumulationOutput {
+    // Premultiplied color and alpha, scaled by the weight
+    @location(0) accumulation: vec4<f32>,
+    // Multiplies the revealage, how much of the background is still visible
+    @location(1) revealage: f32,
+}
+
+// The see-through surfaces, in any order. Back faces are drawn too, with the normal flipped.
+@fragment
+fn fs_transparent(in: VertexOutput, @builtin(front_facing) front_facing: bool) -> AccumulationOutput {
+    var surface = in;
+    surface.world_normal = select(-in.world_normal, in.world_normal, front_facing);
+    // The ambient occlusion belongs to the closest surface, which need not be this one
+    let color = shade_fragment(surface, 1.0);
+
+    var alpha = color.a;
+    if shading.xray != 0u {
+        // More opaque towards the silhouette, so the shape of each part stays readable
+        let view_dir = normalize(camera.view_pos.xyz - in.world_position);
+        let facing = abs(dot(normalize(in.world_normal), view_dir));
+        alpha *= mix(shading.xray_opacity, 1.0, pow(1.0 - facing, 4.0));
+    }
+
+    // Closer surfaces weigh more (McGuire and Bavoil 2013, equation 7)
+    let z = distance(camera.view_pos.xyz, in.world_position);
+    let weight = alpha * clamp(10.0 / (1e-5 + pow(z / 5.0, 2.0) + pow(z / 200.0, 6.0)), 1e-2, 3e3);
+
+    var out: AccumulationOutput;
+    out.accumulation = vec4<f32>(color.rgb * alpha, alpha) * weight;
+    out.revealage = alpha;
+    return out;
+}
@@ -0,0 +1,145 @@
+use cgmath::{InnerSpace, Vector3};
+
+/// Which lighting model the main shader evaluates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShadingModel {
+    /// GGX/Smith/Schlick metallic-roughness BRDF, as used by glTF.
+    Pbr = 0,
+    /// The original Blinn-Phong shading with the fresnel darkening, kept for comparison.
+    Phong = 1,
+    /// Looks the color up in a sphere image by the view space normal, ignoring the lights.
+    Matcap = 2,
+    /// Cel shading with a few flat bands of light, a hard highlight and a rim light.
+    Toon = 3,
+    /// Gooch's technical illustration shading, from a cool color facing away from the light
+    /// to a warm color facing it.
+    Gooch = 4,
+    /// Pen strokes in screen space that get denser in the darker areas.
+    Hatching = 5,
+}
+
+impl ShadingModel {
+    /// The next model, wrapping around to [ShadingModel::Pbr].
+    pub fn next(self) -> Self {
+        match self {
+            Self::Pbr => Self::Phong,
+            Self::Phong => Self::Matcap,
+            Self::Matcap => Self::Toon,
+            Self::Toon => Self::Gooch,
+            Self::Gooch => Self::Hatching,
+            Self::Hatching => Self::Pbr,
+        }
+    }
+}
+
+impl std::str::FromStr for ShadingModel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "pbr" => Ok(Self::Pbr),
+            "phong" => Ok(Self::Phong),
+            "matcap" => Ok(Self::Matcap),
+            "toon" => Ok(Self::Toon),
+            "gooch" => Ok(Self::Gooch),
+            "hatching" => Ok(Self::Hatching),
+            _ => anyhow::bail!(
+                "unknown shading model {:?}, expected pbr, phong, matcap, toon, gooch or hatching",
+                s
+            ),
+        }
+    }
+}
+
+/// Settings of [ShadingModel::Toon].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ToonSettings {
+    /// The number of flat bands the diffuse light is split into, at least 1.
+    pub bands: u32,
+    /// The brightness of the rim light along the silhouette, 0 turns it off.
+    pub rim_intensity: f32,
+    /// How far the rim light reaches in from the silhouette, from 0 to 1.
+    pub rim_width: f32,
+}
+
+impl Default for ToonSettings {
+    fn default() -> Self {
+        Self {
+            bands: 3,
+            rim_intensity: 0.5,
+            rim_width: 0.3,
+        }
+    }
+}
+
+/// A matcap that is used until one is loaded: a grey clay sphere lit from the top left.
+pub fn default_matcap(size: u32) -> image::RgbaImage {
+    let light = Vector3::new(-0.4f32, 0.6, 0.7).normalize();
+    image::RgbaImage::from_fn(size, size, |x, y| {
+        // The sphere normal at the pixel, with y up
+        let u = (x as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+        let v = 1.0 - (y as f32 + 0.5) / size as f32 * 2.0;
+        let z = (1.0 - u * u - v * v).max(0.0).sqrt();
+        let normal = Vector3::new(u, v, z).normalize();
+        let diffuse = normal.dot(light).max(0.0);
+        let half = (light + Vector3::unit_z()).normalize();
+        let specular = normal.dot(half).max(0.0).powf(40.0);
+        // Some light bouncing back from below keeps the shadow side readable
+        let bounce = (-normal.y).max(0.0) * 0.15;
+        let value = (0.12 + 0.7 * diffuse + 0.35 * specular + bounce).min(1.0);
+        let value = (value * 255.0) as u8;
+        image::Rgba([value, value, value, 255])
+    })
+}
+
+/// Settings for the main shader that apply to the whole frame.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ShadingUniform {
+    /// A [ShadingModel].
+    pub model: u32,
+    /// Scales the image based lighting.
+    pub environment_intensity: f32,
+    /// 1 replaces the shading with the number of lights in the cluster of each fragment.
+    pub cluster_heatmap: u32,
+    /// The index of the light whose gizmo is highlighted, `u32::MAX` for none.
+    pub selected_light: u32,
+    /// 1 multiplies the ambient light with the screen space ambient occlusion.
+    pub ambient_occlusion: u32,
+    /// See [ToonSettings].
+    pub toon_bands: u32,
+    pub rim_intensity: f32,
+    pub rim_width: f32,
+    /// 1 makes every surface see-through, see
+    /// [TransparencySettings::xray](crate::transparency::TransparencySettings).
+    pub xray: u32,
+    /// The opacity of the surfaces facing the camera in the X-ray mode.
+    pub xray_opacity: f32,
+    pub _padding: [u32; 2],
+}
+
+impl ShadingUniform {
+    pub fn new(
+        model: ShadingModel,
+        environment_intensity: f32,
+        cluster_heatmap: bool,
+        selected_light: Option<usize>,
+        ambient_occlusion: bool,
+        toon: &ToonSettings,
+        xray_opacity: Option<f32>,
+    ) -> Self {
+        Self {
+            model: model as u32,
+            environment_intensity,
+            cluster_heatmap: cluster_heatmap as u32,
+            selected_light: selected_light.map_or(u32::MAX, |index| index as u32),
+            ambient_occlusion: ambient_occlusion as u32,
+            toon_bands: toon.bands.max(1),
+            rim_intensity: toon.rim_intensity,
+            rim_width: toon.rim_width,
+            xray: xray_opacity.is_some() as u32,
+            xray_opacity: xray_opacity.unwrap_or(1.0),
+            _padding: [0; 2],
+        }
+    }
+}
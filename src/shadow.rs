@@ -0,0 +1,317 @@
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Point3, Rad, SquareMatrix, Vector3};
+use wgpu::util::DeviceExt;
+
+use crate::lights::{Light, LightKind};
+use crate::model::{self, Vertex};
+use crate::orbit_camera::OPENGL_TO_WGPU_MATRIX;
+use crate::texture;
+
+/// The shadow map sizes that can be cycled through.
+pub const RESOLUTIONS: [u32; 4] = [512, 1024, 2048, 4096];
+
+/// Controls the quality of the shadows and how much they are pushed away from the surfaces
+/// casting them.
+#[derive(Debug, Clone, Copy)]
+pub struct ShadowSettings {
+    /// The width and height of the shadow map in texels.
+    pub resolution: u32,
+    /// Subtracted from the depth of a fragment before it is compared with the shadow map.
+    pub depth_bias: f32,
+    /// How far a fragment is moved along its normal before the lookup, in world units.
+    pub normal_bias: f32,
+    /// The PCF kernel covers (2 * `pcf_radius` + 1)² texels, 0 gives hard shadows.
+    pub pcf_radius: u32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            resolution: 2048,
+            depth_bias: 0.0005,
+            normal_bias: 0.01,
+            pcf_radius: 1,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShadowUniform {
+    light_view_proj: [[f32; 4]; 4],
+    depth_bias: f32,
+    normal_bias: f32,
+    pcf_radius: f32,
+    texel_size: f32,
+}
+
+/// A depth map of the model as seen from the first light of the scene.
+///
+/// Point and spot lights get a perspective projection from the light position, directional lights
+/// an orthographic one. Both are fitted around the bounds of the model. The main shader samples
+/// it through `bind_group`, which goes into group 2.
+pub struct ShadowMap {
+    pub settings: ShadowSettings,
+    texture: texture::Texture,
+    buffer: wgpu::Buffer,
+    pipeline: wgpu::RenderPipeline,
+    // Only the uniform, the map can't be bound while it is being rendered
+    pass_bind_group: wgpu::BindGroup,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl ShadowMap {
+    pub fn new(device: &wgpu::Device, settings: ShadowSettings) -> Self {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Shadow Buffer"),
+            contents: bytemuck::cast_slice(&[ShadowUniform::from_settings(&settings)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let uniform_entry = |visibility| wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let pass_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[uniform_entry(wgpu::ShaderStages::VERTEX)],
+                label: Some("shadow_pass_bind_group_layout"),
+            });
+        let pass_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &pass_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("shadow_pass_bind_group"),
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                uniform_entry(wgpu::ShaderStages::FRAGMENT),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Depth,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                    count: None,
+                },
+            ],
+            label: Some("shadow_bind_group_layout"),
+        });
+
+        let pipeline = create_shadow_pipeline(device, &pass_bind_group_layout);
+        let texture = create_shadow_texture(device, settings.resolution);
+        let bind_group = create_bind_group(device, &bind_group_layout, &buffer, &texture);
+
+        Self {
+            settings,
+            texture,
+            buffer,
+            pipeline,
+            pass_bind_group,
+            bind_group_layout,
+            bind_group,
+        }
+    }
+
+    /// Recreates the shadow map with `resolution` x `resolution` texels.
+    pub fn set_resolution(&mut self, device: &wgpu::Device, resolution: u32) {
+        let resolution = resolution.min(device.limits().max_texture_dimension_2d);
+        if resolution == self.settings.resolution {
+            return;
+        }
+        self.settings.resolution = resolution;
+        self.texture = create_shadow_texture(device, resolution);
+        self.bind_group =
+            create_bind_group(device, &self.bind_group_layout, &self.buffer, &self.texture);
+    }
+
+    /// The next entry of [RESOLUTIONS] after the current resolution, wrapping around.
+    pub fn next_resolution(&self) -> u32 {
+        RESOLUTIONS
+            .into_iter()
+            .find(|&resolution| resolution > self.settings.resolution)
+            .unwrap_or(RESOLUTIONS[0])
+    }
+
+    /// Fits the light projection around `bounds` and uploads it together with the settings.
+    pub fn update(&self, queue: &wgpu::Queue, light: &Light, bounds: (Vector3<f32>, Vector3<f32>)) {
+        let mut uniform = ShadowUniform::from_settings(&self.settings);
+        uniform.light_view_proj = light_view_projection(light, bounds).into();
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    /// Renders the depth of `model` into the shadow map.
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, model: &model::Model) {
+        let mut shadow_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Shadow Pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+
+        use model::DrawGeometry;
+        shadow_pass.set_pipeline(&self.pipeline);
+        shadow_pass.set_bind_group(0, &self.pass_bind_group, &[]);
+        // the culling is for the camera, meshes outside its view still cast shadows into it
+        for mesh in &model.meshes {
+            shadow_pass.draw_mesh_geometry(mesh);
+        }
+    }
+}
+
+impl ShadowUniform {
+    fn from_settings(settings: &ShadowSettings) -> Self {
+        Self {
+            light_view_proj: Matrix4::identity().into(),
+            depth_bias: settings.depth_bias,
+            normal_bias: settings.normal_bias,
+            pcf_radius: settings.pcf_radius as f32,
+            texel_size: 1.0 / settings.resolution as f32,
+        }
+    }
+}
+
+/// The view projection matrix of the light, fitted around the bounding sphere of `bounds`.
+pub fn light_view_projection(light: &Light, bounds: (Vector3<f32>, Vector3<f32>)) -> Matrix4<f32> {
+    let (min, max) = bounds;
+    let center = (min + max) / 2.0;
+    // Keep a minimum size so a flat or empty model still gets a valid projection
+    let radius = ((max - min).magnitude() / 2.0).max(0.01);
+    let position = Vector3::from(light.position);
+
+    let (eye, projection) = if light.kind == LightKind::Directional {
+        // Placed outside the bounds, looking along the light direction
+        let direction = Vector3::from(light.to_raw().direction);
+        let eye = center - direction * radius * 2.0;
+        let projection = cgmath::ortho(-radius, radius, -radius, radius, radius, radius * 3.0);
+        (eye, projection)
+    } else {
+        let distance = (center - position).magnitude();
+        let projection = if distance > radius * 1.01 {
+            // The narrowest cone that still contains the whole bounding sphere
+            let fovy = Rad(2.0 * (radius / distance).asin());
+            cgmath::perspective(fovy, 1.0, distance - radius, distance + radius)
+        } else {
+            // The light is inside the model, so only part of it can be covered
+            cgmath::perspective(cgmath::Deg(120.0), 1.0, 0.01, distance + radius)
+        };
+        (position, projection)
+    };
+
+    let forward = center - eye;
+    let up = if forward.cross(Vector3::unit_y()).magnitude2() < 1e-6 * forward.magnitude2() {
+        Vector3::unit_z()
+    } else {
+        Vector3::unit_y()
+    };
+    let view = Matrix4::look_at_rh(Point3::from_vec(eye), Point3::from_vec(center), up);
+
+    OPENGL_TO_WGPU_MATRIX * projection * view
+}
+
+fn create_shadow_texture(device: &wgpu::Device, resolution: u32) -> texture::Texture {
+    texture::Texture::create_depth_texture(device, resolution, resolution, 1, "shadow_map")
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    buffer: &wgpu::Buffer,
+    texture: &texture::Texture,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(&texture.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Sampler(&texture.sampler),
+            },
+        ],
+        label: Some("shadow_bind_group"),
+    })
+}
+
+fn create_shadow_pipeline(
+    device: &wgpu::Device,
+    bind_group_layout: &wgpu::BindGroupLayout,
+) -> wgpu::RenderPipeline {
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Shadow Pipeline Layout"),
+        bind_group_layouts: &[bind_group_layout],
+        push_constant_ranges: &[],
+    });
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Shadow Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("shadow.wgsl").into()),
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Shadow Pipeline"),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[model::ModelVertex::desc()],
+        },
+        fragment: None,
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            // Both sides cast shadows, models aren't always closed
+            cull_mode: None,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            // Slope scaled bias for surfaces at grazing angles to the light, the constant
+            // part is applied in the shader so it can be changed without a new pipeline
+            bias: wgpu::DepthBiasState {
+                constant: 0,
+                slope_scale: 1.5,
+                clamp: 0.0,
+            },
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
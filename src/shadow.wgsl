@@ -0,0 +1,16 @@
+// Depth only pass that renders the model from the point of view of the scene light
+
+struct Shadow {
+    light_view_proj: mat4x4<f32>,
+    depth_bias: f32,
+    normal_bias: f32,
+    pcf_radius: f32,
+    texel_size: f32,
+}
+@group(0) @binding(0)
+var<uniform> shadow: Shadow;
+
+@vertex
+fn vs_main(@location(0) position: vec3<f32>) -> @builtin(position) vec4<f32> {
+    return shadow.light_view_proj * vec4<f32>(position, 1.0);
+}
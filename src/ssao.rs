@@ -0,0 +1,561 @@
+use bytemuck::Zeroable;
+use cgmath::{Matrix4, SquareMatrix};
+use wgpu::util::DeviceExt;
+
+use crate::clipping::{self, ClipPlane, ClipPlanesUniform};
+use crate::model::{self, Vertex};
+use crate::orbit_camera::OrbitCamera;
+use crate::texture;
+use crate::transparency::Transparency;
+
+/// The largest number of kernel samples per pixel. Must match `MAX_SAMPLES` in ssao.wgsl.
+pub const MAX_SAMPLES: u32 = 64;
+
+/// The sample counts that can be cycled through.
+pub const SAMPLE_COUNTS: [u32; 4] = [8, 16, 32, 64];
+
+// Must match BLUR_RADIUS in ssao.wgsl
+const BLUR_RADIUS: u32 = 4;
+
+// The view space normal and depth, the depth needs full precision
+const GEOMETRY_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Float;
+const OCCLUSION_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+
+/// Controls how far the ambient occlusion reaches and how dark it gets.
+#[derive(Debug, Clone, Copy)]
+pub struct SsaoSettings {
+    pub enabled: bool,
+    /// The radius of the sampled hemisphere in world units.
+    pub radius: f32,
+    /// The occlusion is raised to this power, 0 turns it off and higher values darken it.
+    pub intensity: f32,
+    /// The number of kernel samples per pixel, at most [MAX_SAMPLES].
+    pub sample_count: u32,
+    /// Geometry must be this much in front of a sample to occlude it, in world units. Avoids
+    /// self occlusion on flat surfaces.
+    pub bias: f32,
+}
+
+impl Default for SsaoSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            radius: 0.2,
+            intensity: 1.0,
+            sample_count: 32,
+            bias: 0.01,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SsaoUniform {
+    view_proj: [[f32; 4]; 4],
+    view: [[f32; 4]; 4],
+    projection: [[f32; 4]; 4],
+    inv_projection: [[f32; 4]; 4],
+    // Offsets in the hemisphere around +z, only xyz is used
+    kernel: [[f32; 4]; MAX_SAMPLES as usize],
+    screen_size: [f32; 2],
+    radius: f32,
+    intensity: f32,
+    sample_count: u32,
+    bias: f32,
+    _padding: [f32; 2],
+    // The prepass leaves out what the main pass cuts away
+    clip_planes: ClipPlanesUniform,
+}
+
+/// Screen space ambient occlusion, multiplied into the ambient light of the main shader.
+///
+/// A prepass renders the view space normals and depth of the model. For every pixel a
+/// hemisphere kernel around the normal is compared against the rendered depth, and the result is
+/// smoothed by a separable blur that doesn't cross depth discontinuities. The main shader reads
+/// the blurred occlusion through `view`.
+pub struct Ssao {
+    pub settings: SsaoSettings,
+    buffer: wgpu::Buffer,
+    // Regenerated when the sample count changes, the kernel is spread over all of its samples
+    kernel: Vec<[f32; 4]>,
+    pass_bind_group: wgpu::BindGroup,
+    prepass_pipeline: wgpu::RenderPipeline,
+    occlusion_pipeline: wgpu::RenderPipeline,
+    blur_horizontal_pipeline: wgpu::RenderPipeline,
+    blur_vertical_pipeline: wgpu::RenderPipeline,
+    screen_bind_group_layout: wgpu::BindGroupLayout,
+    targets: Targets,
+}
+
+/// Everything that depends on the size of the frame.
+struct Targets {
+    geometry: wgpu::TextureView,
+    // Only for the depth test of the prepass
+    depth: texture::Texture,
+    occlusion: wgpu::TextureView,
+    // The result of the horizontal blur
+    blurred_horizontal: wgpu::TextureView,
+    blurred: wgpu::TextureView,
+    occlusion_bind_group: wgpu::BindGroup,
+    blur_horizontal_bind_group: wgpu::BindGroup,
+    blur_vertical_bind_group: wgpu::BindGroup,
+}
+
+impl Ssao {
+    pub fn new(device: &wgpu::Device, width: u32, height: u32) -> Self {
+        let settings = SsaoSettings::default();
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("SSAO Buffer"),
+            contents: bytemuck::cast_slice(&[SsaoUniform::zeroed()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let uniform_entry = wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let pass_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[uniform_entry],
+                label: Some("ssao_pass_bind_group_layout"),
+            });
+        let pass_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &pass_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("ssao_pass_bind_group"),
+        });
+
+        // The fullscreen passes read the geometry and the occlusion with textureLoad
+        let screen_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    uniform_entry,
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        },
+                        count: None,
+                    },
+                ],
+                label: Some("ssao_screen_bind_group_layout"),
+            });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("SSAO Shader"),
+            source: clipping::shader_source(include_str!("ssao.wgsl")),
+        });
+        let prepass_pipeline = create_prepass_pipeline(device, &pass_bind_group_layout, &shader);
+        let screen_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("SSAO Screen Pipeline Layout"),
+                bind_group_layouts: &[&screen_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let screen_pipeline = |entry_point| {
+            create_screen_pipeline(device, &screen_pipeline_layout, &shader, entry_point)
+        };
+        let occlusion_pipeline = screen_pipeline("fs_occlusion");
+        let blur_horizontal_pipeline = screen_pipeline("fs_blur_horizontal");
+        let blur_vertical_pipeline = screen_pipeline("fs_blur_vertical");
+
+        let targets = Targets::new(device, &screen_bind_group_layout, &buffer, width, height);
+
+        Self {
+            kernel: create_kernel(settings.sample_count),
+            settings,
+            buffer,
+            pass_bind_group,
+            prepass_pipeline,
+            occlusion_pipeline,
+            blur_horizontal_pipeline,
+            blur_vertical_pipeline,
+            screen_bind_group_layout,
+            targets,
+        }
+    }
+
+    /// How many pixels away the ambient occlusion of a pixel reads from, 0 if it is disabled.
+    ///
+    /// Arguments:
+    ///
+    /// * `pixels_per_unit`: The largest size of a world unit on screen.
+    pub fn reach(&self, pixels_per_unit: f32) -> u32 {
+        if !self.settings.enabled {
+            return 0;
+        }
+        (self.settings.radius * pixels_per_unit).ceil() as u32 + BLUR_RADIUS
+    }
+
+    /// Recreates the render targets for a frame of `width` x `height` pixels.
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.targets = Targets::new(
+            device,
+            &self.screen_bind_group_layout,
+            &self.buffer,
+            width,
+            height,
+        );
+    }
+
+    /// The blurred occlusion, 1 where nothing is occluded.
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.targets.blurred
+    }
+
+    /// The next entry of [SAMPLE_COUNTS] after the current sample count, wrapping around.
+    pub fn next_sample_count(&self) -> u32 {
+        SAMPLE_COUNTS
+            .into_iter()
+            .find(|&count| count > self.settings.sample_count)
+            .unwrap_or(SAMPLE_COUNTS[0])
+    }
+
+    /// Uploads the settings and the view of `camera` for a frame of `width` x `height` pixels,
+    /// with the model cut by `clip_planes`.
+    pub fn update(
+        &mut self,
+        queue: &wgpu::Queue,
+        camera: &OrbitCamera,
+        width: u32,
+        height: u32,
+        clip_planes: &[ClipPlane],
+    ) {
+        let sample_count = self.settings.sample_count.clamp(1, MAX_SAMPLES);
+        if self.kernel.len() != sample_count as usize {
+            self.kernel = create_kernel(sample_count);
+        }
+        let mut kernel = [[0.0; 4]; MAX_SAMPLES as usize];
+        kernel[..self.kernel.len()].copy_from_slice(&self.kernel);
+
+        let view = camera.build_view_matrix();
+        let projection = camera.build_projection_matrix();
+        let uniform = SsaoUniform {
+            view_proj: (projection * view).into(),
+            view: view.into(),
+            projection: projection.into(),
+            inv_projection: projection.invert().unwrap_or(Matrix4::identity()).into(),
+            kernel,
+            screen_size: [width as f32, height as f32],
+            radius: self.settings.radius,
+            intensity: self.settings.intensity,
+            sample_count,
+            bias: self.settings.bias,
+            _padding: [0.0; 2],
+            clip_planes: ClipPlanesUniform::new(clip_planes),
+        };
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    /// Renders the normals and depth of the opaque meshes of `model` and computes the blurred
+    /// occlusion from them.
+    ///
+    /// Has to run before the main pass. Does nothing if SSAO is disabled, the main shader then
+    /// doesn't read the result.
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        model: &model::Model,
+        transparency: &Transparency,
+    ) {
+        if !self.settings.enabled {
+            return;
+        }
+
+        {
+            let mut prepass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("SSAO Prepass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &self.targets.geometry,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.targets.depth.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+
+            use model::DrawGeometry;
+            prepass.set_pipeline(&self.prepass_pipeline);
+            prepass.set_bind_group(0, &self.pass_bind_group, &[]);
+            // Like the main pass, so the surfaces behind see-through meshes get their own
+            // occlusion
+            prepass.draw_model_geometry(model, |mesh| {
+                !transparency.is_see_through(&model.materials[mesh.material])
+            });
+        }
+
+        let passes = [
+            (
+                "SSAO Pass",
+                &self.occlusion_pipeline,
+                &self.targets.occlusion_bind_group,
+                &self.targets.occlusion,
+            ),
+            (
+                "SSAO Horizontal Blur Pass",
+                &self.blur_horizontal_pipeline,
+                &self.targets.blur_horizontal_bind_group,
+                &self.targets.blurred_horizontal,
+            ),
+            (
+                "SSAO Vertical Blur Pass",
+                &self.blur_vertical_pipeline,
+                &self.targets.blur_vertical_bind_group,
+                &self.targets.blurred,
+            ),
+        ];
+        for (label, pipeline, bind_group, target) in passes {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some(label),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: target,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.draw(0..3, 0..1);
+        }
+    }
+}
+
+impl Targets {
+    fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        buffer: &wgpu::Buffer,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let geometry = create_target(device, "ssao_geometry", GEOMETRY_FORMAT, width, height);
+        let depth = texture::Texture::create_depth_texture(device, width, height, 1, "ssao_depth");
+        let occlusion = create_target(device, "ssao_occlusion", OCCLUSION_FORMAT, width, height);
+        let blurred_horizontal = create_target(
+            device,
+            "ssao_blurred_horizontal",
+            OCCLUSION_FORMAT,
+            width,
+            height,
+        );
+        let blurred = create_target(device, "ssao_blurred", OCCLUSION_FORMAT, width, height);
+
+        let screen_bind_group = |source: &wgpu::TextureView, label| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&geometry),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(source),
+                    },
+                ],
+                label: Some(label),
+            })
+        };
+        // The occlusion pass has no source, the geometry stands in for it
+        let occlusion_bind_group = screen_bind_group(&geometry, "ssao_occlusion_bind_group");
+        let blur_horizontal_bind_group =
+            screen_bind_group(&occlusion, "ssao_blur_horizontal_bind_group");
+        let blur_vertical_bind_group =
+            screen_bind_group(&blurred_horizontal, "ssao_blur_vertical_bind_group");
+
+        Self {
+            geometry,
+            depth,
+            occlusion,
+            blurred_horizontal,
+            blurred,
+            occlusion_bind_group,
+            blur_horizontal_bind_group,
+            blur_vertical_bind_group,
+        }
+    }
+}
+
+/// `sample_count` offsets in the unit hemisphere around +z.
+///
+/// The directions come from a Halton sequence, so the kernel is the same on every run. The
+/// offsets get longer with the index, most samples stay close to the surface where the
+/// occlusion matters most.
+fn create_kernel(sample_count: u32) -> Vec<[f32; 4]> {
+    (0..sample_count)
+        .map(|i| {
+            let phi = 2.0 * std::f32::consts::PI * halton(i + 1, 2);
+            // Cosine weighted, so samples near the horizon count less
+            let cos_theta = (1.0 - halton(i + 1, 3)).sqrt();
+            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+            let t = i as f32 / sample_count as f32;
+            let length = halton(i + 1, 5).max(0.1) * (0.1 + 0.9 * t * t);
+            [
+                sin_theta * phi.cos() * length,
+                sin_theta * phi.sin() * length,
+                cos_theta * length,
+                0.0,
+            ]
+        })
+        .collect()
+}
+
+/// The `index`th element of the Halton sequence in `base`, in [0, 1).
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut result = 0.0;
+    let mut fraction = 1.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+fn create_target(
+    device: &wgpu::Device,
+    label: &str,
+    format: wgpu::TextureFormat,
+    width: u32,
+    height: u32,
+) -> wgpu::TextureView {
+    device
+        .create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        })
+        .create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+fn create_prepass_pipeline(
+    device: &wgpu::Device,
+    bind_group_layout: &wgpu::BindGroupLayout,
+    shader: &wgpu::ShaderModule,
+) -> wgpu::RenderPipeline {
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("SSAO Prepass Pipeline Layout"),
+        bind_group_layouts: &[bind_group_layout],
+        push_constant_ranges: &[],
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("SSAO Prepass Pipeline"),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_prepass",
+            buffers: &[model::ModelVertex::desc()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "fs_prepass",
+            targets: &[Some(wgpu::ColorTargetState {
+                format: GEOMETRY_FORMAT,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        // The same culling as the main pass, so both see the same surfaces
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            polygon_mode: wgpu::PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+fn create_screen_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    entry_point: &str,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("SSAO Screen Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_fullscreen",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point,
+            targets: &[Some(wgpu::ColorTargetState {
+                format: OCCLUSION_FORMAT,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
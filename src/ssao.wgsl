@@ -0,0 +1,177 @@
+// Screen space ambient occlusion, see ssao.rs
+
+// Must match ssao::MAX_SAMPLES
+const MAX_SAMPLES: u32 = 64u;
+// The blur covers 2 * BLUR_RADIUS + 1 pixels in each direction
+const BLUR_RADIUS: i32 = 4;
+
+struct Ssao {
+    view_proj: mat4x4<f32>,
+    view: mat4x4<f32>,
+    projection: mat4x4<f32>,
+    inv_projection: mat4x4<f32>,
+    kernel: array<vec4<f32>, MAX_SAMPLES>,
+    screen_size: vec2<f32>,
+    radius: f32,
+    intensity: f32,
+    sample_count: u32,
+    bias: f32,
+    clip_planes: ClipPlanes,
+}
+@group(0) @binding(0)
+var<uniform> ssao: Ssao;
+
+// Only bound in the fullscreen passes. The view space normals in xyz and the view space depth
+// in w, which is 0 where there is no model. Depth textures can't be loaded from on GL.
+@group(0) @binding(1)
+var geometry: texture_2d<f32>;
+// The occlusion for the blur passes
+@group(0) @binding(2)
+var source: texture_2d<f32>;
+
+// -- PREPASS -- //
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+}
+
+struct PrepassOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) view_normal: vec3<f32>,
+    @location(1) view_z: f32,
+    @location(2) world_position: vec3<f32>,
+}
+
+@vertex
+fn vs_prepass(model: VertexInput) -> PrepassOutput {
+    var out: PrepassOutput;
+    out.clip_position = ssao.view_proj * vec4<f32>(model.position, 1.0);
+    out.view_normal = (ssao.view * vec4<f32>(model.normal, 0.0)).xyz;
+    out.view_z = (ssao.view * vec4<f32>(model.position, 1.0)).z;
+    out.world_position = model.position;
+    return out;
+}
+
+@fragment
+fn fs_prepass(in: PrepassOutput) -> @location(0) vec4<f32> {
+    if clipped(ssao.clip_planes, in.world_position) {
+        discard;
+    }
+    return vec4<f32>(normalize(in.view_normal), in.view_z);
+}
+
+// -- FULLSCREEN PASSES -- //
+
+// One triangle that covers the whole screen
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+// The view space position at `pixel` with the view space depth `z`
+fn view_position(pixel: vec2<f32>, z: f32) -> vec3<f32> {
+    let ndc = vec2<f32>(pixel.x / ssao.screen_size.x * 2.0 - 1.0, 1.0 - pixel.y / ssao.screen_size.y * 2.0);
+    let far = ssao.inv_projection * vec4<f32>(ndc, 1.0, 1.0);
+    let ray = far.xyz / far.w;
+    return ray * (z / ray.z);
+}
+
+// The view space depth of the model at `pixel`, negative because view space looks along -z
+fn view_z(pixel: vec2<i32>) -> f32 {
+    return textureLoad(geometry, pixel, 0).w;
+}
+
+// A different angle for every pixel of a 4x4 block, the blur averages the pattern out
+fn noise_angle(pixel: vec2<u32>) -> f32 {
+    let index = (pixel.x % 4u) + (pixel.y % 4u) * 4u;
+    // Interleaved so that neighbours differ as much as possible
+    var order = array<f32, 16>(0.0, 8.0, 2.0, 10.0, 12.0, 4.0, 14.0, 6.0, 3.0, 11.0, 1.0, 9.0, 15.0, 7.0, 13.0, 5.0);
+    return (order[index] + 0.5) / 16.0 * 6.2831853;
+}
+
+@fragment
+fn fs_occlusion(@builtin(position) frag_coord: vec4<f32>) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(frag_coord.xy);
+    let center = textureLoad(geometry, pixel, 0);
+    // Nothing to occlude on the background
+    if center.w >= 0.0 {
+        return vec4<f32>(1.0);
+    }
+    let position = view_position(frag_coord.xy, center.w);
+    let normal = normalize(center.xyz);
+
+    // A randomly rotated basis around the normal, so the kernel covers other directions per pixel
+    let angle = noise_angle(vec2<u32>(pixel));
+    let random = vec3<f32>(cos(angle), sin(angle), 0.0);
+    var tangent = random - normal * dot(random, normal);
+    if dot(tangent, tangent) < 1e-4 {
+        tangent = cross(normal, vec3<f32>(1.0, 0.0, 0.0));
+    }
+    tangent = normalize(tangent);
+    let tbn = mat3x3<f32>(tangent, cross(normal, tangent), normal);
+
+    var occlusion = 0.0;
+    for (var i = 0u; i < ssao.sample_count; i++) {
+        let sample_position = position + tbn * ssao.kernel[i].xyz * ssao.radius;
+        let clip = ssao.projection * vec4<f32>(sample_position, 1.0);
+        let ndc = clip.xy / clip.w;
+        let sample_pixel = vec2<i32>((ndc * vec2<f32>(0.5, -0.5) + 0.5) * ssao.screen_size);
+        if any(sample_pixel < vec2<i32>(0)) || any(sample_pixel >= vec2<i32>(ssao.screen_size)) {
+            continue;
+        }
+        let scene_z = view_z(sample_pixel);
+        if scene_z >= 0.0 {
+            continue;
+        }
+        // Geometry far in front of the sample belongs to another object and doesn't count
+        let range = smoothstep(0.0, 1.0, ssao.radius / abs(position.z - scene_z));
+        if scene_z >= sample_position.z + ssao.bias {
+            occlusion += range;
+        }
+    }
+
+    let visibility = 1.0 - occlusion / f32(max(ssao.sample_count, 1u));
+    return vec4<f32>(pow(visibility, ssao.intensity));
+}
+
+// A gaussian blur that leaves out pixels at a different depth, so the occlusion of one surface
+// doesn't bleed onto another
+fn bilateral_blur(frag_coord: vec2<f32>, direction: vec2<i32>) -> vec4<f32> {
+    let pixel = vec2<i32>(frag_coord);
+    let center_z = view_z(pixel);
+    if center_z >= 0.0 {
+        return vec4<f32>(1.0);
+    }
+    let max_pixel = vec2<i32>(ssao.screen_size) - 1;
+    // Surfaces further apart than a fraction of the radius are separate
+    let depth_sigma = ssao.radius * 0.25;
+
+    var sum = 0.0;
+    var weight_sum = 0.0;
+    for (var i = -BLUR_RADIUS; i <= BLUR_RADIUS; i++) {
+        let sample_pixel = clamp(pixel + direction * i, vec2<i32>(0), max_pixel);
+        let sample_z = view_z(sample_pixel);
+        if sample_z >= 0.0 {
+            continue;
+        }
+        let dz = sample_z - center_z;
+        let spatial = exp(-f32(i * i) / (2.0 * 2.5 * 2.5));
+        let range = exp(-dz * dz / (2.0 * depth_sigma * depth_sigma));
+        let weight = spatial * range;
+        sum += textureLoad(source, sample_pixel, 0).r * weight;
+        weight_sum += weight;
+    }
+    return vec4<f32>(sum / max(weight_sum, 1e-5));
+}
+
+@fragment
+fn fs_blur_horizontal(@builtin(position) frag_coord: vec4<f32>) -> @location(0) vec4<f32> {
+    return bilateral_blur(frag_coord.xy, vec2<i32>(1, 0));
+}
+
+@fragment
+fn fs_blur_vertical(@builtin(position) frag_coord: vec4<f32>) -> @location(0) vec4<f32> {
+    return bilateral_blur(frag_coord.xy, vec2<i32>(0, 1));
+}
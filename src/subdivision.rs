@@ -0,0 +1,593 @@
+use std::collections::HashMap;
+
+use cgmath::*;
+
+use crate::model::ModelVertex;
+
+/// The highest subdivision level that can be requested.
+pub const MAX_LEVEL: u32 = 4;
+
+/// The subdivision scheme used for a [PolyMesh].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheme {
+    /// Loop subdivision, used when every face is a triangle.
+    Loop,
+    /// Catmull-Clark subdivision, used for quads and mixed polygon meshes.
+    CatmullClark,
+}
+
+/// A polygon mesh with welded vertex positions that keeps the face topology
+/// of the source file (triangles, quads and n-gons).
+#[derive(Debug, Clone)]
+pub struct PolyMesh {
+    pub positions: Vec<Vector3<f32>>,
+    pub faces: Vec<Vec<u32>>,
+    /// The texture coordinates of every face corner, parallel to `faces`. They are
+    /// interpolated linearly so UV seams stay where they are. Empty if the mesh has none.
+    pub face_uvs: Vec<Vec<Vector2<f32>>>,
+}
+
+/// An edge of a [PolyMesh] together with the faces that share it.
+struct Edge {
+    a: u32,
+    b: u32,
+    faces: Vec<u32>,
+}
+
+impl Edge {
+    /// Edges shared by anything other than two faces are treated as creases.
+    fn is_boundary(&self) -> bool {
+        self.faces.len() != 2
+    }
+}
+
+/// Connectivity information derived from a [PolyMesh].
+struct Topology {
+    edges: Vec<Edge>,
+    edge_lookup: HashMap<(u32, u32), usize>,
+    /// The edges incident to every vertex.
+    vertex_edges: Vec<Vec<usize>>,
+    /// The faces incident to every vertex.
+    vertex_faces: Vec<Vec<u32>>,
+}
+
+impl Topology {
+    fn new(mesh: &PolyMesh) -> Self {
+        let mut edges: Vec<Edge> = Vec::new();
+        let mut edge_lookup = HashMap::new();
+        let mut vertex_edges = vec![Vec::new(); mesh.positions.len()];
+        let mut vertex_faces = vec![Vec::new(); mesh.positions.len()];
+
+        for (face_index, face) in mesh.faces.iter().enumerate() {
+            for (i, &a) in face.iter().enumerate() {
+                let b = face[(i + 1) % face.len()];
+                vertex_faces[a as usize].push(face_index as u32);
+
+                let edge_index = *edge_lookup.entry(edge_key(a, b)).or_insert_with(|| {
+                    edges.push(Edge {
+                        a,
+                        b,
+                        faces: Vec::new(),
+                    });
+                    vertex_edges[a as usize].push(edges.len() - 1);
+                    vertex_edges[b as usize].push(edges.len() - 1);
+                    edges.len() - 1
+                });
+                edges[edge_index].faces.push(face_index as u32);
+            }
+        }
+
+        Self {
+            edges,
+            edge_lookup,
+            vertex_edges,
+            vertex_faces,
+        }
+    }
+
+    fn edge(&self, a: u32, b: u32) -> usize {
+        self.edge_lookup[&edge_key(a, b)]
+    }
+
+    /// Applies the boundary rule shared by Loop and Catmull-Clark to a vertex
+    /// that lies on a boundary or crease edge.
+    ///
+    /// Returns `None` if the vertex is not on a boundary.
+    fn boundary_vertex(&self, mesh: &PolyMesh, vertex: usize) -> Option<Vector3<f32>> {
+        let boundary_edges = self.vertex_edges[vertex]
+            .iter()
+            .map(|&e| &self.edges[e])
+            .filter(|e| e.is_boundary())
+            .collect::<Vec<_>>();
+
+        match boundary_edges.len() {
+            0 => None,
+            2 => {
+                let neighbours = boundary_edges
+                    .iter()
+                    .map(|e| mesh.positions[other_end(e, vertex as u32) as usize])
+                    .fold(Vector3::zero(), |sum, p| sum + p);
+                Some(mesh.positions[vertex] * 0.75 + neighbours * 0.125)
+            }
+            // Corners and non-manifold vertices are kept in place.
+            _ => Some(mesh.positions[vertex]),
+        }
+    }
+}
+
+impl PolyMesh {
+    /// Builds a [PolyMesh] from loader output, welding vertices that share a position
+    /// so that seams in the normals or texture coordinates do not tear the surface.
+    ///
+    /// Arguments:
+    ///
+    /// * `vertices`: The vertices as produced by the OBJ loader.
+    /// * `faces`: The polygons, indexing into `vertices`.
+    pub fn welded(vertices: &[ModelVertex], faces: &[Vec<u32>]) -> Self {
+        let mut positions = Vec::new();
+        let mut lookup = HashMap::new();
+        let remap = vertices
+            .iter()
+            .map(|v| {
+                let key = v.position.map(f32::to_bits);
+                *lookup.entry(key).or_insert_with(|| {
+                    positions.push(Vector3::from(v.position));
+                    positions.len() as u32 - 1
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let (faces, face_uvs) = faces
+            .iter()
+            .map(|f| {
+                let mut corners = f
+                    .iter()
+                    .map(|&i| {
+                        let uv = Vector2::from(vertices[i as usize].tex_coords);
+                        (remap[i as usize], uv)
+                    })
+                    .collect::<Vec<_>>();
+                corners.dedup_by_key(|corner| corner.0);
+                if corners.len() > 1 && corners.first().map(|c| c.0) == corners.last().map(|c| c.0)
+                {
+                    corners.pop();
+                }
+                corners
+            })
+            .filter(|corners| corners.len() >= 3)
+            .map(|corners| corners.into_iter().unzip::<_, _, Vec<_>, Vec<_>>())
+            .unzip();
+
+        Self {
+            positions,
+            faces,
+            face_uvs,
+        }
+    }
+
+    /// The scheme that fits this mesh: Loop for pure triangle meshes and Catmull-Clark otherwise.
+    pub fn scheme(&self) -> Scheme {
+        if self.faces.iter().all(|f| f.len() == 3) {
+            Scheme::Loop
+        } else {
+            Scheme::CatmullClark
+        }
+    }
+
+    /// Subdivides the mesh `levels` times with the scheme returned by [PolyMesh::scheme].
+    pub fn subdivide(&self, levels: u32) -> PolyMesh {
+        let scheme = self.scheme();
+        let mut mesh = self.clone();
+        for _ in 0..levels {
+            mesh = match scheme {
+                Scheme::Loop => mesh.loop_step(),
+                Scheme::CatmullClark => mesh.catmull_clark_step(),
+            };
+        }
+        mesh
+    }
+
+    /// A single step of Loop subdivision. Every face must be a triangle.
+    fn loop_step(&self) -> PolyMesh {
+        let topology = Topology::new(self);
+        let vertex_count = self.positions.len();
+
+        let mut positions = (0..vertex_count)
+            .map(|v| {
+                topology.boundary_vertex(self, v).unwrap_or_else(|| {
+                    let n = topology.vertex_edges[v].len();
+                    let beta = if n == 3 {
+                        3.0 / 16.0
+                    } else {
+                        3.0 / (8.0 * n as f32)
+                    };
+                    let neighbours = topology.vertex_edges[v]
+                        .iter()
+                        .map(|&e| self.positions[other_end(&topology.edges[e], v as u32) as usize])
+                        .fold(Vector3::zero(), |sum, p| sum + p);
+                    self.positions[v] * (1.0 - n as f32 * beta) + neighbours * beta
+                })
+            })
+            .collect::<Vec<_>>();
+
+        positions.extend(topology.edges.iter().map(|edge| {
+            let a = self.positions[edge.a as usize];
+            let b = self.positions[edge.b as usize];
+            if edge.is_boundary() {
+                return (a + b) * 0.5;
+            }
+            let opposite = edge
+                .faces
+                .iter()
+                .map(|&f| {
+                    let face = &self.faces[f as usize];
+                    let c = face.iter().find(|&&i| i != edge.a && i != edge.b).unwrap();
+                    self.positions[*c as usize]
+                })
+                .fold(Vector3::zero(), |sum, p| sum + p);
+            (a + b) * 0.375 + opposite * 0.125
+        }));
+
+        let edge_point = |a: u32, b: u32| (vertex_count + topology.edge(a, b)) as u32;
+        let faces = self
+            .faces
+            .iter()
+            .flat_map(|face| {
+                let (a, b, c) = (face[0], face[1], face[2]);
+                let (ab, bc, ca) = (edge_point(a, b), edge_point(b, c), edge_point(c, a));
+                [
+                    vec![a, ab, ca],
+                    vec![ab, b, bc],
+                    vec![ca, bc, c],
+                    vec![ab, bc, ca],
+                ]
+            })
+            .collect();
+
+        let face_uvs = self
+            .face_uvs
+            .iter()
+            .flat_map(|uvs| {
+                let (a, b, c) = (uvs[0], uvs[1], uvs[2]);
+                let (ab, bc, ca) = ((a + b) * 0.5, (b + c) * 0.5, (c + a) * 0.5);
+                [
+                    vec![a, ab, ca],
+                    vec![ab, b, bc],
+                    vec![ca, bc, c],
+                    vec![ab, bc, ca],
+                ]
+            })
+            .collect();
+
+        PolyMesh {
+            positions,
+            faces,
+            face_uvs,
+        }
+    }
+
+    /// A single step of Catmull-Clark subdivision. The result consists of quads only.
+    fn catmull_clark_step(&self) -> PolyMesh {
+        let topology = Topology::new(self);
+        let vertex_count = self.positions.len();
+        let edge_count = topology.edges.len();
+
+        let face_points = self
+            .faces
+            .iter()
+            .map(|face| centroid(face.iter().map(|&i| self.positions[i as usize])))
+            .collect::<Vec<_>>();
+
+        let mut positions = (0..vertex_count)
+            .map(|v| {
+                topology.boundary_vertex(self, v).unwrap_or_else(|| {
+                    let n = topology.vertex_edges[v].len() as f32;
+                    let f = centroid(
+                        topology.vertex_faces[v]
+                            .iter()
+                            .map(|&f| face_points[f as usize]),
+                    );
+                    let r = centroid(topology.vertex_edges[v].iter().map(|&e| {
+                        let edge = &topology.edges[e];
+                        (self.positions[edge.a as usize] + self.positions[edge.b as usize]) * 0.5
+                    }));
+                    (f + r * 2.0 + self.positions[v] * (n - 3.0)) / n
+                })
+            })
+            .collect::<Vec<_>>();
+
+        positions.extend(topology.edges.iter().map(|edge| {
+            let a = self.positions[edge.a as usize];
+            let b = self.positions[edge.b as usize];
+            if edge.is_boundary() {
+                (a + b) * 0.5
+            } else {
+                let f1 = face_points[edge.faces[0] as usize];
+                let f2 = face_points[edge.faces[1] as usize];
+                (a + b + f1 + f2) * 0.25
+            }
+        }));
+        positions.extend(face_points);
+
+        let edge_point = |a: u32, b: u32| (vertex_count + topology.edge(a, b)) as u32;
+        let faces = self
+            .faces
+            .iter()
+            .enumerate()
+            .flat_map(|(face_index, face)| {
+                let face_point = (vertex_count + edge_count + face_index) as u32;
+                let n = face.len();
+                (0..n)
+                    .map(|i| {
+                        let previous = face[(i + n - 1) % n];
+                        let current = face[i];
+                        let next = face[(i + 1) % n];
+                        vec![
+                            current,
+                            edge_point(current, next),
+                            face_point,
+                            edge_point(previous, current),
+                        ]
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        let face_uvs = self
+            .face_uvs
+            .iter()
+            .flat_map(|uvs| {
+                let center = centroid(uvs.iter().copied());
+                let n = uvs.len();
+                (0..n)
+                    .map(|i| {
+                        let previous = uvs[(i + n - 1) % n];
+                        let current = uvs[i];
+                        let next = uvs[(i + 1) % n];
+                        vec![
+                            current,
+                            (current + next) * 0.5,
+                            center,
+                            (previous + current) * 0.5,
+                        ]
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        PolyMesh {
+            positions,
+            faces,
+            face_uvs,
+        }
+    }
+
+    /// Triangulates the polygons as fans and computes smooth, area weighted vertex normals.
+    ///
+    /// Vertex `i` of the result is position `i`. Corners that share a position but not
+    /// their texture coordinates get extra vertices appended after those.
+    pub fn to_triangles(&self) -> (Vec<ModelVertex>, Vec<u32>) {
+        let mut normals = vec![Vector3::zero(); self.positions.len()];
+        for face in &self.faces {
+            let normal = newell_normal(face.iter().map(|&i| self.positions[i as usize]));
+            for &i in face {
+                normals[i as usize] += normal;
+            }
+        }
+
+        let mut vertices = self
+            .positions
+            .iter()
+            .zip(normals)
+            .map(|(position, normal)| ModelVertex {
+                position: (*position).into(),
+                normal: if normal.magnitude2() > 0.0 {
+                    normal.normalize().into()
+                } else {
+                    [0.0, 1.0, 0.0]
+                },
+                tex_coords: [0.0; 2],
+            })
+            .collect::<Vec<_>>();
+
+        if self.face_uvs.is_empty() {
+            return (vertices, fan_triangulate(&self.faces));
+        }
+
+        // Split vertices along UV seams
+        let mut assigned = vec![false; self.positions.len()];
+        let mut seam_vertices = HashMap::new();
+        let mut faces = Vec::with_capacity(self.faces.len());
+        for (face, uvs) in self.faces.iter().zip(&self.face_uvs) {
+            let mut corners = Vec::with_capacity(face.len());
+            for (&i, &uv) in face.iter().zip(uvs) {
+                let uv: [f32; 2] = uv.into();
+                let vertex = &mut vertices[i as usize];
+                if !assigned[i as usize] {
+                    assigned[i as usize] = true;
+                    vertex.tex_coords = uv;
+                    corners.push(i);
+                } else if vertex.tex_coords == uv {
+                    corners.push(i);
+                } else {
+                    let split = ModelVertex {
+                        tex_coords: uv,
+                        ..*vertex
+                    };
+                    let index = *seam_vertices
+                        .entry((i, uv.map(f32::to_bits)))
+                        .or_insert_with(|| {
+                            vertices.push(split);
+                            vertices.len() as u32 - 1
+                        });
+                    corners.push(index);
+                }
+            }
+            faces.push(corners);
+        }
+
+        (vertices, fan_triangulate(&faces))
+    }
+}
+
+/// Splits every polygon into a fan of triangles that keeps the winding order. Degenerate faces
+/// with less than 3 corners are skipped.
+pub fn fan_triangulate(faces: &[Vec<u32>]) -> Vec<u32> {
+    faces
+        .iter()
+        .filter(|face| face.len() >= 3)
+        .flat_map(|face| (1..face.len() - 1).flat_map(move |i| [face[0], face[i], face[i + 1]]))
+        .collect()
+}
+
+/// For every triangle of [fan_triangulate], whether the edge opposite each of its corners is an
+/// edge of the polygon, rather than a diagonal added by the triangulation.
+pub fn fan_polygon_edges(faces: &[Vec<u32>]) -> Vec<[bool; 3]> {
+    faces
+        .iter()
+        .filter(|face| face.len() >= 3)
+        .flat_map(|face| {
+            let last = face.len() - 2;
+            (1..=last).map(move |i| [true, i == last, i == 1])
+        })
+        .collect()
+}
+
+fn edge_key(a: u32, b: u32) -> (u32, u32) {
+    (a.min(b), a.max(b))
+}
+
+fn other_end(edge: &Edge, vertex: u32) -> u32 {
+    if edge.a == vertex {
+        edge.b
+    } else {
+        edge.a
+    }
+}
+
+fn centroid<V: VectorSpace<Scalar = f32>>(points: impl Iterator<Item = V>) -> V {
+    let (sum, count) = points.fold((V::zero(), 0), |(sum, count), p| (sum + p, count + 1));
+    sum / count.max(1) as f32
+}
+
+/// The normal of a (possibly non-planar) polygon, scaled by its area.
+fn newell_normal(points: impl Iterator<Item = Vector3<f32>> + Clone) -> Vector3<f32> {
+    let next = points.clone().cycle().skip(1);
+    points
+        .zip(next)
+        .fold(Vector3::zero(), |sum, (p, q)| sum + p.cross(q))
+        * 0.5
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A cube from -1 to 1, corner `i` has the max x for bit 0, y for bit 1 and z for bit 2.
+    fn cube() -> PolyMesh {
+        let positions = (0..8)
+            .map(|i| {
+                let coordinate = |bit: u32| if i & (1 << bit) != 0 { 1.0 } else { -1.0 };
+                Vector3::new(coordinate(0), coordinate(1), coordinate(2))
+            })
+            .collect();
+        let faces = vec![
+            vec![0, 2, 3, 1],
+            vec![4, 5, 7, 6],
+            vec![0, 4, 6, 2],
+            vec![1, 3, 7, 5],
+            vec![0, 1, 5, 4],
+            vec![2, 6, 7, 3],
+        ];
+        PolyMesh {
+            positions,
+            faces,
+            face_uvs: Vec::new(),
+        }
+    }
+
+    fn tetrahedron() -> PolyMesh {
+        PolyMesh {
+            positions: vec![
+                Vector3::new(1.0, 1.0, 1.0),
+                Vector3::new(1.0, -1.0, -1.0),
+                Vector3::new(-1.0, 1.0, -1.0),
+                Vector3::new(-1.0, -1.0, 1.0),
+            ],
+            faces: vec![vec![0, 1, 2], vec![0, 3, 1], vec![0, 2, 3], vec![1, 3, 2]],
+            face_uvs: Vec::new(),
+        }
+    }
+
+    fn edge_count(mesh: &PolyMesh) -> usize {
+        Topology::new(mesh).edges.len()
+    }
+
+    #[test]
+    fn picks_the_scheme_from_the_faces() {
+        assert_eq!(tetrahedron().scheme(), Scheme::Loop);
+        assert_eq!(cube().scheme(), Scheme::CatmullClark);
+    }
+
+    #[test]
+    fn loop_splits_every_triangle_into_four() {
+        let mesh = tetrahedron().subdivide(1);
+        assert_eq!(mesh.positions.len(), 4 + 6);
+        assert_eq!(mesh.faces.len(), 4 * 4);
+        assert_eq!(edge_count(&mesh), 2 * 6 + 3 * 4);
+        assert!(mesh.faces.iter().all(|face| face.len() == 3));
+
+        let mesh = tetrahedron().subdivide(2);
+        assert_eq!(mesh.positions.len(), 10 + 24);
+        assert_eq!(mesh.faces.len(), 16 * 4);
+    }
+
+    #[test]
+    fn catmull_clark_splits_every_face_into_quads() {
+        let mesh = cube().subdivide(1);
+        assert_eq!(mesh.positions.len(), 8 + 12 + 6);
+        assert_eq!(mesh.faces.len(), 6 * 4);
+        assert_eq!(edge_count(&mesh), 2 * 12 + 4 * 6);
+        assert!(mesh.faces.iter().all(|face| face.len() == 4));
+
+        let mesh = cube().subdivide(2);
+        assert_eq!(mesh.positions.len(), 26 + 48 + 24);
+        assert_eq!(mesh.faces.len(), 24 * 4);
+    }
+
+    #[test]
+    fn catmull_clark_pulls_the_cube_corners_in() {
+        let mesh = cube().subdivide(1);
+        for corner in &mesh.positions[..8] {
+            for axis in 0..3 {
+                assert!((corner[axis].abs() - 5.0 / 9.0).abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn subdivision_level_zero_keeps_the_mesh() {
+        let mesh = cube().subdivide(0);
+        assert_eq!(mesh.positions, cube().positions);
+        assert_eq!(mesh.faces, cube().faces);
+    }
+
+    #[test]
+    fn fan_triangulate_keeps_the_winding() {
+        let faces = vec![vec![0, 1, 2], vec![3, 4, 5, 6], vec![7, 8, 9, 10, 11]];
+        assert_eq!(
+            fan_triangulate(&faces),
+            [0, 1, 2, 3, 4, 5, 3, 5, 6, 7, 8, 9, 7, 9, 10, 7, 10, 11]
+        );
+    }
+
+    #[test]
+    fn fan_triangulate_skips_degenerate_faces() {
+        let faces = vec![vec![], vec![0], vec![0, 1], vec![0, 1, 2, 3]];
+        assert_eq!(fan_triangulate(&faces), [0, 1, 2, 0, 2, 3]);
+        assert_eq!(
+            fan_polygon_edges(&faces),
+            [[true, false, true], [true, true, false]]
+        );
+    }
+}
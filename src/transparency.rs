@@ -0,0 +1,431 @@
+use crate::clipping;
+use crate::model::{self, DrawModel, Vertex};
+use crate::texture;
+
+/// Holds the weighted sum of the premultiplied colors of the see-through surfaces.
+pub const ACCUMULATION_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+/// Holds the product of one minus their alphas, how much of the opaque scene shows through.
+pub const REVEALAGE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+
+/// Controls which surfaces the [Transparency] draws.
+#[derive(Debug, Clone, Copy)]
+pub struct TransparencySettings {
+    /// Draws the whole model see-through to reveal its inner parts.
+    pub xray: bool,
+    /// The opacity of the surfaces facing the camera in the X-ray mode, from 0 to 1. Surfaces
+    /// seen at grazing angles are more opaque.
+    pub xray_opacity: f32,
+}
+
+impl Default for TransparencySettings {
+    fn default() -> Self {
+        Self {
+            xray: false,
+            xray_opacity: 0.25,
+        }
+    }
+}
+
+/// Draws the see-through meshes with weighted blended order-independent transparency (McGuire and
+/// Bavoil 2013), after the opaque scene.
+///
+/// The meshes are drawn in any order against the depth of the opaque pass, without writing it.
+/// Every fragment adds its premultiplied color times a weight that falls off with the distance
+/// into the accumulation target and multiplies its transparency into the revealage target. A
+/// fullscreen pass divides the sum by the summed weights and blends the result over the scene
+/// with the coverage from the revealage. The average is exact for a single layer and a close
+/// approximation for a few similar ones, no sorting is needed.
+///
+/// A mesh is see-through if the alpha of its material is below 1, or always in the X-ray mode.
+pub struct Transparency {
+    pub settings: TransparencySettings,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    accumulate_pipeline_layout: wgpu::PipelineLayout,
+    composite_pipeline_layout: wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+    accumulate_pipeline: wgpu::RenderPipeline,
+    composite_pipeline: wgpu::RenderPipeline,
+    accumulation: wgpu::TextureView,
+    revealage: wgpu::TextureView,
+    // The multisampled targets that are resolved into the ones above, if the main pass uses MSAA
+    msaa_accumulation: Option<wgpu::TextureView>,
+    msaa_revealage: Option<wgpu::TextureView>,
+}
+
+impl Transparency {
+    /// Arguments:
+    ///
+    /// * `bind_group_layouts`: The layouts of the main shader, the camera, light, shadow and
+    ///   material bind groups.
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let target_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[target_entry(0), target_entry(1)],
+            label: Some("transparency_bind_group_layout"),
+        });
+        let (accumulation, msaa_accumulation) =
+            create_target(device, width, height, ACCUMULATION_FORMAT, sample_count);
+        let (revealage, msaa_revealage) =
+            create_target(device, width, height, REVEALAGE_FORMAT, sample_count);
+        let bind_group = create_bind_group(device, &bind_group_layout, &accumulation, &revealage);
+
+        let accumulate_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Transparency Accumulate Pipeline Layout"),
+                bind_group_layouts,
+                push_constant_ranges: &[],
+            });
+        let composite_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Transparency Composite Pipeline Layout"),
+                bind_group_layouts: &[&bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let (accumulate_pipeline, composite_pipeline) = create_pipelines(
+            device,
+            &accumulate_pipeline_layout,
+            &composite_pipeline_layout,
+            format,
+            sample_count,
+        );
+
+        Self {
+            settings: TransparencySettings::default(),
+            bind_group_layout,
+            bind_group,
+            accumulate_pipeline_layout,
+            composite_pipeline_layout,
+            format,
+            sample_count,
+            accumulate_pipeline,
+            composite_pipeline,
+            accumulation,
+            revealage,
+            msaa_accumulation,
+            msaa_revealage,
+        }
+    }
+
+    /// Recreates the pipelines for the MSAA sample count of the render pass. The targets follow
+    /// with the next [Transparency::resize].
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        self.sample_count = sample_count;
+        (self.accumulate_pipeline, self.composite_pipeline) = create_pipelines(
+            device,
+            &self.accumulate_pipeline_layout,
+            &self.composite_pipeline_layout,
+            self.format,
+            sample_count,
+        );
+    }
+
+    /// Recreates the targets for a frame of `width` x `height` pixels.
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        (self.accumulation, self.msaa_accumulation) = create_target(
+            device,
+            width,
+            height,
+            ACCUMULATION_FORMAT,
+            self.sample_count,
+        );
+        (self.revealage, self.msaa_revealage) =
+            create_target(device, width, height, REVEALAGE_FORMAT, self.sample_count);
+        self.bind_group = create_bind_group(
+            device,
+            &self.bind_group_layout,
+            &self.accumulation,
+            &self.revealage,
+        );
+    }
+
+    /// Whether meshes with this material are drawn by the [Transparency] instead of the opaque
+    /// pass.
+    pub fn is_see_through(&self, material: &model::Material) -> bool {
+        self.settings.xray || material.is_transparent()
+    }
+
+    /// The opacity the main shader applies in the X-ray mode, None if it is off.
+    pub fn xray_opacity(&self) -> Option<f32> {
+        self.settings
+            .xray
+            .then_some(self.settings.xray_opacity.clamp(0.0, 1.0))
+    }
+
+    /// Draws the see-through meshes of `model` over the finished opaque scene. Does nothing if
+    /// there are none.
+    ///
+    /// Arguments:
+    ///
+    /// * `view`, `resolve_target`: The color attachment of the main pass, whose contents are
+    ///   kept.
+    /// * `depth_view`: The depth buffer of the main pass, only read.
+    /// * `camera_bind_group`, `light_bind_group`, `shadow_bind_group`: The bind groups of the
+    ///   main shader.
+    #[allow(clippy::too_many_arguments)]
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        model: &model::Model,
+        view: &wgpu::TextureView,
+        resolve_target: Option<&wgpu::TextureView>,
+        depth_view: &wgpu::TextureView,
+        camera_bind_group: &wgpu::BindGroup,
+        light_bind_group: &wgpu::BindGroup,
+        shadow_bind_group: &wgpu::BindGroup,
+    ) {
+        let meshes = model
+            .meshes
+            .iter()
+            .filter(|mesh| !mesh.culled && self.is_see_through(&model.materials[mesh.material]))
+            .collect::<Vec<_>>();
+        if meshes.is_empty() {
+            return;
+        }
+
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Transparency Accumulate Pass"),
+                color_attachments: &[
+                    attachment(
+                        &self.accumulation,
+                        self.msaa_accumulation.as_ref(),
+                        wgpu::Color::TRANSPARENT,
+                    ),
+                    // Nothing covers the scene yet
+                    attachment(
+                        &self.revealage,
+                        self.msaa_revealage.as_ref(),
+                        wgpu::Color::WHITE,
+                    ),
+                ],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: depth_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            render_pass.set_pipeline(&self.accumulate_pipeline);
+            render_pass.set_bind_group(2, shadow_bind_group, &[]);
+            for mesh in meshes {
+                render_pass.draw_mesh(
+                    mesh,
+                    &model.materials[mesh.material],
+                    camera_bind_group,
+                    light_bind_group,
+                );
+            }
+        }
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Transparency Composite Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        render_pass.set_pipeline(&self.composite_pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+/// Creates a target and, with MSAA, the multisampled target that is resolved into it.
+fn create_target(
+    device: &wgpu::Device,
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+) -> (wgpu::TextureView, Option<wgpu::TextureView>) {
+    let target = |sample_count, usage| {
+        device
+            .create_texture(&wgpu::TextureDescriptor {
+                label: Some("Transparency Target"),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | usage,
+                view_formats: &[],
+            })
+            .create_view(&wgpu::TextureViewDescriptor::default())
+    };
+    let resolved = target(1, wgpu::TextureUsages::TEXTURE_BINDING);
+    let multisampled =
+        (sample_count > 1).then(|| target(sample_count, wgpu::TextureUsages::empty()));
+    (resolved, multisampled)
+}
+
+/// Clears `view`, or `msaa_view` which is resolved into it.
+fn attachment<'a>(
+    view: &'a wgpu::TextureView,
+    msaa_view: Option<&'a wgpu::TextureView>,
+    clear: wgpu::Color,
+) -> Option<wgpu::RenderPassColorAttachment<'a>> {
+    Some(wgpu::RenderPassColorAttachment {
+        view: msaa_view.unwrap_or(view),
+        resolve_target: msaa_view.map(|_| view),
+        ops: wgpu::Operations {
+            load: wgpu::LoadOp::Clear(clear),
+            store: wgpu::StoreOp::Store,
+        },
+    })
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    accumulation: &wgpu::TextureView,
+    revealage: &wgpu::TextureView,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(accumulation),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(revealage),
+            },
+        ],
+        label: Some("transparency_bind_group"),
+    })
+}
+
+/// Creates the accumulate and composite pipelines.
+fn create_pipelines(
+    device: &wgpu::Device,
+    accumulate_layout: &wgpu::PipelineLayout,
+    composite_layout: &wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+) -> (wgpu::RenderPipeline, wgpu::RenderPipeline) {
+    let multisample = wgpu::MultisampleState {
+        count: sample_count,
+        mask: !0,
+        alpha_to_coverage_enabled: false,
+    };
+
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Transparency Accumulate Shader"),
+        source: clipping::shader_source(include_str!("shader.wgsl")),
+    });
+    let sum = wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::One,
+        dst_factor: wgpu::BlendFactor::One,
+        operation: wgpu::BlendOperation::Add,
+    };
+    // dst * (1 - alpha)
+    let product = wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::Zero,
+        dst_factor: wgpu::BlendFactor::OneMinusSrc,
+        operation: wgpu::BlendOperation::Add,
+    };
+    let accumulate = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Transparency Accumulate Pipeline"),
+        layout: Some(accumulate_layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[model::ModelVertex::desc()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_transparent",
+            targets: &[
+                Some(wgpu::ColorTargetState {
+                    format: ACCUMULATION_FORMAT,
+                    blend: Some(wgpu::BlendState {
+                        color: sum,
+                        alpha: sum,
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }),
+                Some(wgpu::ColorTargetState {
+                    format: REVEALAGE_FORMAT,
+                    blend: Some(wgpu::BlendState {
+                        color: product,
+                        alpha: product,
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }),
+            ],
+        }),
+        // The back faces show through the front faces
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_STENCIL_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample,
+        multiview: None,
+    });
+
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Transparency Composite Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("transparency.wgsl").into()),
+    });
+    let composite = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Transparency Composite Pipeline"),
+        layout: Some(composite_layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_composite",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_composite",
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample,
+        multiview: None,
+    });
+    (accumulate, composite)
+}
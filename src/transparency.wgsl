@@ -0,0 +1,28 @@
+// Resolves the weighted blended transparency over the opaque scene, see transparency.rs
+
+// Written by fs_transparent in shader.wgsl
+@group(0) @binding(0)
+var accumulation: texture_2d<f32>;
+@group(0) @binding(1)
+var revealage: texture_2d<f32>;
+
+// One triangle that covers the whole screen
+@vertex
+fn vs_composite(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+// The weighted average color of the surfaces in the pixel, blended with the coverage they add up to
+@fragment
+fn fs_composite(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let texel = vec2<i32>(position.xy);
+    let coverage = 1.0 - textureLoad(revealage, texel, 0).r;
+    if coverage <= 0.0 {
+        discard;
+    }
+    let sum = textureLoad(accumulation, texel, 0);
+    // Keeps the average finite when very many surfaces overflow the sum
+    let color = sum.rgb / clamp(sum.a, 1e-4, 5e4);
+    return vec4<f32>(color, coverage);
+}
@@ -0,0 +1,217 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, ensure, Context};
+
+use crate::capture;
+use crate::offscreen;
+use crate::renderer::Renderer;
+
+/// The number of frames used when no count is given.
+pub const DEFAULT_FRAMES: u32 = 120;
+
+/// The playback rate used when none is given.
+pub const DEFAULT_FRAMES_PER_SECOND: u32 = 30;
+
+/// How the frames of a turntable are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TurntableFormat {
+    /// One numbered PNG per frame inside the output directory.
+    PngSequence,
+    /// A single looping animated GIF.
+    Gif,
+    /// A single looping animated PNG.
+    Apng,
+}
+
+impl std::str::FromStr for TurntableFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "png" => Ok(Self::PngSequence),
+            "gif" => Ok(Self::Gif),
+            "apng" => Ok(Self::Apng),
+            _ => bail!(
+                "unknown turntable format {:?}, expected png, gif or apng",
+                s
+            ),
+        }
+    }
+}
+
+impl TurntableFormat {
+    /// The path that is written to if no output was given.
+    pub fn default_output(&self) -> PathBuf {
+        match self {
+            Self::PngSequence => PathBuf::from("turntable"),
+            Self::Gif => PathBuf::from("turntable.gif"),
+            Self::Apng => PathBuf::from("turntable.png"),
+        }
+    }
+}
+
+/// Settings for a 360° turntable export.
+#[derive(Debug, Clone)]
+pub struct TurntableOptions {
+    /// The number of frames for one full revolution.
+    pub frames: u32,
+    pub frames_per_second: u32,
+    pub format: TurntableFormat,
+    pub output: PathBuf,
+}
+
+/// Renders a full revolution of the camera around its target and writes it to disk.
+///
+/// The yaw of the [OrbitCamera](crate::orbit_camera::OrbitCamera) is stepped from its
+/// current value through 360° while pitch and distance stay fixed. The camera is
+/// restored afterwards.
+pub fn export(renderer: &mut Renderer, options: &TurntableOptions) -> anyhow::Result<()> {
+    ensure!(options.frames > 0, "a turntable needs at least one frame");
+    ensure!(
+        options.frames_per_second > 0,
+        "the frame rate must not be zero"
+    );
+    // The APNG frame delay is 1 / frames_per_second in a u16 denominator
+    ensure!(
+        options.frames_per_second <= u16::MAX as u32,
+        "the frame rate must not be above {}",
+        u16::MAX
+    );
+
+    let (width, height) = (renderer.width, renderer.height);
+    let mut sink = FrameSink::new(options, width, height)?;
+
+    let saved_camera = renderer.camera;
+    // Yaw bounds would stop the camera from going all the way around
+    renderer.camera.bounds.min_yaw = None;
+    renderer.camera.bounds.max_yaw = None;
+    // The occlusion lags a frame behind, which is a large step of the camera here
+    let occlusion_culling = std::mem::take(&mut renderer.occlusion.settings.enabled);
+
+    let result = (0..options.frames).try_for_each(|frame| {
+        let angle = std::f32::consts::TAU * frame as f32 / options.frames as f32;
+        renderer.camera.set_yaw(saved_camera.yaw + angle);
+        let mut pixels = capture::capture_frame(renderer);
+        sink.write_frame(frame, &mut pixels)
+    });
+
+    renderer.camera = saved_camera;
+    renderer.occlusion.settings.enabled = occlusion_culling;
+    renderer.update();
+
+    result?;
+    sink.finish()
+}
+
+/// Receives the rendered frames and encodes them in the requested format.
+enum FrameSink {
+    PngSequence {
+        directory: PathBuf,
+        width: u32,
+        height: u32,
+    },
+    Gif {
+        encoder: gif::Encoder<std::io::BufWriter<std::fs::File>>,
+        width: u16,
+        height: u16,
+        // Hundredths of a second, which is what GIF frame delays are measured in
+        delay: u16,
+    },
+    Apng {
+        writer: png::Writer<std::io::BufWriter<std::fs::File>>,
+    },
+}
+
+impl FrameSink {
+    fn new(options: &TurntableOptions, width: u32, height: u32) -> anyhow::Result<Self> {
+        let output = &options.output;
+        match options.format {
+            TurntableFormat::PngSequence => {
+                std::fs::create_dir_all(output)
+                    .with_context(|| format!("failed to create {}", output.display()))?;
+                Ok(Self::PngSequence {
+                    directory: output.clone(),
+                    width,
+                    height,
+                })
+            }
+            TurntableFormat::Gif => {
+                let (width, height) = (
+                    u16::try_from(width).context("GIFs are limited to 65535 pixels")?,
+                    u16::try_from(height).context("GIFs are limited to 65535 pixels")?,
+                );
+                let mut encoder = gif::Encoder::new(create_file(output)?, width, height, &[])?;
+                encoder.set_repeat(gif::Repeat::Infinite)?;
+                // GIF delays are whole centiseconds, and browsers play delays below 2 as 10
+                let fps = options.frames_per_second;
+                let delay = ((100 + fps / 2) / fps).max(2);
+                if fps > 50 {
+                    eprintln!("GIFs play at most at 50 fps, {} fps is slowed down", fps);
+                }
+                Ok(Self::Gif {
+                    encoder,
+                    width,
+                    height,
+                    delay: delay as u16,
+                })
+            }
+            TurntableFormat::Apng => {
+                let mut encoder = png::Encoder::new(create_file(output)?, width, height);
+                encoder.set_color(png::ColorType::Rgba);
+                encoder.set_depth(png::BitDepth::Eight);
+                encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+                // 0 plays means the animation loops forever
+                encoder.set_animated(options.frames, 0)?;
+                encoder.set_frame_delay(1, u16::try_from(options.frames_per_second)?)?;
+                Ok(Self::Apng {
+                    writer: encoder.write_header()?,
+                })
+            }
+        }
+    }
+
+    fn write_frame(&mut self, frame: u32, rgba: &mut [u8]) -> anyhow::Result<()> {
+        match self {
+            Self::PngSequence {
+                directory,
+                width,
+                height,
+            } => {
+                let path = directory.join(format!("frame_{:04}.png", frame));
+                offscreen::save_png(path, *width, *height, rgba)
+            }
+            Self::Gif {
+                encoder,
+                width,
+                height,
+                delay,
+            } => {
+                // Speed 10 is the quantizer default, a good balance of quality and time
+                let mut gif_frame = gif::Frame::from_rgba_speed(*width, *height, rgba, 10);
+                gif_frame.delay = *delay;
+                encoder.write_frame(&gif_frame)?;
+                Ok(())
+            }
+            Self::Apng { writer } => {
+                writer.write_image_data(rgba)?;
+                Ok(())
+            }
+        }
+    }
+
+    fn finish(self) -> anyhow::Result<()> {
+        match self {
+            Self::PngSequence { .. } => {}
+            // The GIF trailer is written when the encoder is dropped
+            Self::Gif { encoder, .. } => drop(encoder),
+            Self::Apng { writer } => writer.finish()?,
+        }
+        Ok(())
+    }
+}
+
+fn create_file(path: &Path) -> anyhow::Result<std::io::BufWriter<std::fs::File>> {
+    let file = std::fs::File::create(path)
+        .with_context(|| format!("failed to create {}", path.display()))?;
+    Ok(std::io::BufWriter::new(file))
+}
@@ -0,0 +1,471 @@
+use cgmath::{InnerSpace, Vector3};
+use wgpu::util::DeviceExt;
+
+use crate::clipping;
+use crate::model::{self, Vertex};
+use crate::texture;
+
+/// Which lines the [VertexVectors] draw.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VectorMode {
+    Off,
+    /// A blue line along the normal of every vertex.
+    Normals,
+    /// The normals plus a red line along the tangent and a green one along the bitangent of
+    /// every vertex.
+    TangentFrames,
+}
+
+impl VectorMode {
+    /// The next mode, wrapping around to [VectorMode::Off].
+    pub fn next(self) -> Self {
+        match self {
+            Self::Off => Self::Normals,
+            Self::Normals => Self::TangentFrames,
+            Self::TangentFrames => Self::Off,
+        }
+    }
+}
+
+impl std::str::FromStr for VectorMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "off" => Ok(Self::Off),
+            "normals" => Ok(Self::Normals),
+            "tangents" => Ok(Self::TangentFrames),
+            _ => anyhow::bail!(
+                "unknown vector mode {:?}, expected off, normals or tangents",
+                s
+            ),
+        }
+    }
+}
+
+/// Controls the [VertexVectors].
+#[derive(Debug, Clone, Copy)]
+pub struct VectorSettings {
+    pub mode: VectorMode,
+    /// The length of the lines as a fraction of the diagonal of the model's bounds.
+    pub length: f32,
+}
+
+impl Default for VectorSettings {
+    fn default() -> Self {
+        Self {
+            mode: VectorMode::Off,
+            length: 0.02,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct VectorUniform {
+    length: f32,
+    _padding: [f32; 3],
+}
+
+/// The tangent frame of one vertex, see `generate_frames` in `vectors.wgsl`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct Frame {
+    position: [f32; 4],
+    normal: [f32; 4],
+    // w is the handedness, the sign of the bitangent
+    tangent: [f32; 4],
+}
+
+impl Frame {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<Frame>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
+
+// The fixed point sums of the tangent and bitangent of a vertex, see `sums` in vectors.wgsl
+const SUM_SIZE: u64 = 6 * std::mem::size_of::<i32>() as u64;
+const WORKGROUP_SIZE: u32 = 64;
+// The most workgroups a dispatch may have along one dimension
+const MAX_WORKGROUPS: u32 = 65535;
+
+/// Draws lines along the normals, tangents and bitangents of the model's vertices.
+///
+/// Nothing is prepared on the CPU. The normals are drawn straight from the vertex buffers of the
+/// meshes, with every vertex as the instance of one line. The model has no tangents of its own,
+/// so compute passes derive them from the texture coordinates of the triangles around every
+/// vertex into a buffer that is then drawn the same way. They only run when the frames are
+/// first shown after the model changed.
+pub struct VertexVectors {
+    pub settings: VectorSettings,
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pipeline_layout: wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    normal_pipeline: wgpu::RenderPipeline,
+    frame_pipeline: wgpu::RenderPipeline,
+    accumulate_pipeline: wgpu::ComputePipeline,
+    generate_pipeline: wgpu::ComputePipeline,
+    // One buffer of Frames per mesh, empty until they are needed
+    frames: Vec<wgpu::Buffer>,
+}
+
+impl VertexVectors {
+    /// Arguments:
+    ///
+    /// * `camera_bind_group_layout`: The layout of the bind group passed to [VertexVectors::draw].
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Vector Buffer"),
+            contents: bytemuck::cast_slice(&[<VectorUniform as bytemuck::Zeroable>::zeroed()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("vector_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("vector_bind_group"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Vector Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = create_shader(device);
+        let (normal_pipeline, frame_pipeline) =
+            create_pipelines(device, &shader, &pipeline_layout, format, sample_count);
+        // The layouts are derived from the bindings each entry point uses
+        let accumulate_pipeline =
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("Tangent Sum Pipeline"),
+                layout: None,
+                module: &shader,
+                entry_point: "accumulate_tangents",
+            });
+        let generate_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Tangent Frame Pipeline"),
+            layout: None,
+            module: &shader,
+            entry_point: "generate_frames",
+        });
+
+        Self {
+            settings: VectorSettings::default(),
+            buffer,
+            bind_group,
+            pipeline_layout,
+            format,
+            normal_pipeline,
+            frame_pipeline,
+            accumulate_pipeline,
+            generate_pipeline,
+            frames: Vec::new(),
+        }
+    }
+
+    /// Recreates the pipelines for the MSAA sample count of the render pass.
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        (self.normal_pipeline, self.frame_pipeline) = create_pipelines(
+            device,
+            &create_shader(device),
+            &self.pipeline_layout,
+            self.format,
+            sample_count,
+        );
+    }
+
+    /// Drops the tangent frames. Must be called whenever the buffers of the model are rebuilt.
+    pub fn invalidate(&mut self) {
+        self.frames.clear();
+    }
+
+    /// Uploads the length of the lines.
+    ///
+    /// Arguments:
+    ///
+    /// * `bounds`: The bounds of the model as (min, max), see [model::Model::bounds].
+    pub fn update(&self, queue: &wgpu::Queue, bounds: (Vector3<f32>, Vector3<f32>)) {
+        let (min, max) = bounds;
+        let uniform = VectorUniform {
+            length: self.settings.length * (max - min).magnitude(),
+            _padding: [0.0; 3],
+        };
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    /// Generates the tangent frames of `model` if they are shown and missing. Must be recorded
+    /// before the pass that calls [VertexVectors::draw].
+    ///
+    /// Falls back to [VectorMode::Normals] if a mesh is too large for the storage buffers of the
+    /// device.
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        model: &model::Model,
+    ) {
+        if self.settings.mode != VectorMode::TangentFrames || !self.frames.is_empty() {
+            return;
+        }
+
+        let limits = device.limits();
+        let fits = model.meshes.iter().all(|mesh| {
+            let vertices = vertex_count(mesh) as u64;
+            let frames_size = vertices * std::mem::size_of::<Frame>() as u64;
+            let largest_binding = [
+                frames_size,
+                vertices * SUM_SIZE,
+                mesh.vertex_buffer.size(),
+                mesh.index_buffer.size(),
+            ]
+            .into_iter()
+            .max()
+            .unwrap_or(0);
+            frames_size <= limits.max_buffer_size
+                && largest_binding <= limits.max_storage_buffer_binding_size as u64
+        });
+        if !fits {
+            eprintln!(
+                "The model is too large for tangent frames on this device, showing the normals only"
+            );
+            self.settings.mode = VectorMode::Normals;
+            return;
+        }
+
+        self.frames = model
+            .meshes
+            .iter()
+            .map(|mesh| {
+                device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some(&format!("{:?} Tangent Frames", mesh.name)),
+                    size: vertex_count(mesh) as u64 * std::mem::size_of::<Frame>() as u64,
+                    usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX,
+                    mapped_at_creation: false,
+                })
+            })
+            .collect();
+        // New buffers are zeroed, which is where the sums start
+        let sums = model
+            .meshes
+            .iter()
+            .map(|mesh| {
+                device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some(&format!("{:?} Tangent Sums", mesh.name)),
+                    size: vertex_count(mesh) as u64 * SUM_SIZE,
+                    usage: wgpu::BufferUsages::STORAGE,
+                    mapped_at_creation: false,
+                })
+            })
+            .collect::<Vec<_>>();
+        let bind_groups = model
+            .meshes
+            .iter()
+            .zip(&self.frames)
+            .zip(&sums)
+            .map(|((mesh, frames), sums)| {
+                let accumulate = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &self.accumulate_pipeline.get_bind_group_layout(0),
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: mesh.vertex_buffer.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: mesh.index_buffer.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 3,
+                            resource: sums.as_entire_binding(),
+                        },
+                    ],
+                    label: Some("tangent_sum_bind_group"),
+                });
+                let generate = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &self.generate_pipeline.get_bind_group_layout(0),
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: mesh.vertex_buffer.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: frames.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 3,
+                            resource: sums.as_entire_binding(),
+                        },
+                    ],
+                    label: Some("tangent_frame_bind_group"),
+                });
+                (accumulate, generate)
+            })
+            .collect::<Vec<_>>();
+
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Tangent Frame Pass"),
+            timestamp_writes: None,
+        });
+        for (mesh, (accumulate, generate)) in model.meshes.iter().zip(&bind_groups) {
+            if mesh.num_elements == 0 {
+                continue;
+            }
+            pass.set_pipeline(&self.accumulate_pipeline);
+            pass.set_bind_group(0, accumulate, &[]);
+            dispatch(&mut pass, mesh.num_elements / 3);
+            pass.set_pipeline(&self.generate_pipeline);
+            pass.set_bind_group(0, generate, &[]);
+            dispatch(&mut pass, vertex_count(mesh));
+        }
+    }
+
+    /// Draws the lines of the current mode, depth tested against the model.
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        model: &'a model::Model,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        if self.settings.mode == VectorMode::Off {
+            return;
+        }
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+
+        render_pass.set_pipeline(&self.normal_pipeline);
+        for mesh in &model.meshes {
+            render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            render_pass.draw(0..2, 0..vertex_count(mesh));
+        }
+
+        if self.settings.mode == VectorMode::TangentFrames {
+            render_pass.set_pipeline(&self.frame_pipeline);
+            for (mesh, frames) in model.meshes.iter().zip(&self.frames) {
+                render_pass.set_vertex_buffer(0, frames.slice(..));
+                render_pass.draw(0..4, 0..vertex_count(mesh));
+            }
+        }
+    }
+}
+
+fn vertex_count(mesh: &model::Mesh) -> u32 {
+    (mesh.vertex_buffer.size() / std::mem::size_of::<model::ModelVertex>() as u64) as u32
+}
+
+/// Dispatches one invocation per item, spread over a second dimension for large meshes.
+fn dispatch(pass: &mut wgpu::ComputePass, items: u32) {
+    let workgroups = items.div_ceil(WORKGROUP_SIZE);
+    if workgroups > 0 {
+        pass.dispatch_workgroups(
+            workgroups.min(MAX_WORKGROUPS),
+            workgroups.div_ceil(MAX_WORKGROUPS),
+            1,
+        );
+    }
+}
+
+fn create_shader(device: &wgpu::Device) -> wgpu::ShaderModule {
+    device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Vector Shader"),
+        source: clipping::shader_source(include_str!("vectors.wgsl")),
+    })
+}
+
+/// Creates the normal and tangent frame pipelines.
+fn create_pipelines(
+    device: &wgpu::Device,
+    shader: &wgpu::ShaderModule,
+    layout: &wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+) -> (wgpu::RenderPipeline, wgpu::RenderPipeline) {
+    // The vertices of the model, read one per instance
+    let mut model_vertex = model::ModelVertex::desc();
+    model_vertex.step_mode = wgpu::VertexStepMode::Instance;
+
+    let pipeline = |entry_point, buffer: wgpu::VertexBufferLayout| {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Vector Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point,
+                buffers: &[buffer],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_STENCIL_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        })
+    };
+
+    (
+        pipeline("vs_normal", model_vertex),
+        pipeline("vs_frame", Frame::desc()),
+    )
+}
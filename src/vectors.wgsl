@@ -0,0 +1,208 @@
+// Lines along the normals and tangent frames of the vertices, see vectors.rs
+
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+// See clip.wgsl
+@group(0) @binding(5)
+var<uniform> clip_planes: ClipPlanes;
+
+// See vectors::VectorUniform
+struct Vectors {
+    // The length of every line in world units
+    length: f32,
+}
+@group(1) @binding(0)
+var<uniform> vectors: Vectors;
+
+const NORMAL_COLOR: vec3<f32> = vec3<f32>(0.1, 0.3, 1.0);
+const TANGENT_COLOR: vec3<f32> = vec3<f32>(1.0, 0.1, 0.1);
+const BITANGENT_COLOR: vec3<f32> = vec3<f32>(0.1, 1.0, 0.1);
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec3<f32>,
+    // Where the line starts, the whole line is cut away with it
+    @location(1) start: vec3<f32>,
+}
+
+fn line_vertex(start: vec3<f32>, direction: vec3<f32>, end: u32, color: vec3<f32>) -> VertexOutput {
+    let position = start + direction * vectors.length * f32(end);
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(position, 1.0);
+    out.color = color;
+    out.start = start;
+    return out;
+}
+
+// One line per instance, the instances are the vertices of the model as they are in its vertex
+// buffer
+@vertex
+fn vs_normal(
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @builtin(vertex_index) index: u32,
+) -> VertexOutput {
+    return line_vertex(position, normalize(normal), index, NORMAL_COLOR);
+}
+
+// See vectors::Frame, written by generate_frames
+struct FrameInput {
+    @location(0) position: vec4<f32>,
+    @location(1) normal: vec4<f32>,
+    // w is the handedness of the frame, the sign of the bitangent
+    @location(2) tangent: vec4<f32>,
+}
+
+// Two lines per instance, the tangent and the bitangent of a vertex
+@vertex
+fn vs_frame(frame: FrameInput, @builtin(vertex_index) index: u32) -> VertexOutput {
+    if index < 2u {
+        return line_vertex(frame.position.xyz, frame.tangent.xyz, index, TANGENT_COLOR);
+    }
+    let bitangent = cross(frame.normal.xyz, frame.tangent.xyz) * frame.tangent.w;
+    return line_vertex(frame.position.xyz, bitangent, index - 2u, BITANGENT_COLOR);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    if clipped(clip_planes, in.start) {
+        discard;
+    }
+    return vec4<f32>(in.color, 1.0);
+}
+
+// The model's buffers, read as they are. A model::ModelVertex is 8 floats: position, normal and
+// texture coordinates
+@group(0) @binding(0)
+var<storage, read> vertices: array<f32>;
+@group(0) @binding(1)
+var<storage, read> indices: array<u32>;
+
+struct Frame {
+    position: vec4<f32>,
+    normal: vec4<f32>,
+    tangent: vec4<f32>,
+}
+// One per vertex
+@group(0) @binding(2)
+var<storage, read_write> frames: array<Frame>;
+// The tangent and bitangent directions of the triangles around every vertex, added up in fixed
+// point because there are no atomic floats. Six per vertex, starting out as zero.
+@group(0) @binding(3)
+var<storage, read_write> sums: array<atomic<i32>>;
+
+const VERTEX_FLOATS: u32 = 8u;
+const WORKGROUP_SIZE: u32 = 64u;
+// Every triangle adds at most one to a component of a sum, so a vertex can be shared by up to
+// 2048 triangles before the sums overflow
+const FIXED_POINT_SCALE: f32 = 1048576.0;
+
+fn vertex_position(index: u32) -> vec3<f32> {
+    let base = index * VERTEX_FLOATS;
+    return vec3<f32>(vertices[base], vertices[base + 1u], vertices[base + 2u]);
+}
+
+fn vertex_normal(index: u32) -> vec3<f32> {
+    let base = index * VERTEX_FLOATS + 3u;
+    return vec3<f32>(vertices[base], vertices[base + 1u], vertices[base + 2u]);
+}
+
+fn vertex_tex_coords(index: u32) -> vec2<f32> {
+    let base = index * VERTEX_FLOATS + 6u;
+    return vec2<f32>(vertices[base], vertices[base + 1u]);
+}
+
+// Any direction perpendicular to the normal, for vertices without usable texture coordinates
+fn any_tangent(normal: vec3<f32>) -> vec3<f32> {
+    let axis = select(vec3<f32>(1.0, 0.0, 0.0), vec3<f32>(0.0, 1.0, 0.0), abs(normal.x) > 0.9);
+    return normalize(cross(axis, normal));
+}
+
+// Large meshes take more workgroups than fit into one dimension
+fn invocation_index(id: vec3<u32>, workgroups: vec3<u32>) -> u32 {
+    return id.x + id.y * workgroups.x * WORKGROUP_SIZE;
+}
+
+fn add_to_sum(vertex: u32, offset: u32, direction: vec3<f32>) {
+    let fixed = vec3<i32>(round(direction * FIXED_POINT_SCALE));
+    let base = vertex * 6u + offset;
+    atomicAdd(&sums[base], fixed.x);
+    atomicAdd(&sums[base + 1u], fixed.y);
+    atomicAdd(&sums[base + 2u], fixed.z);
+}
+
+fn load_sum(vertex: u32, offset: u32) -> vec3<f32> {
+    let base = vertex * 6u + offset;
+    let fixed = vec3<i32>(atomicLoad(&sums[base]), atomicLoad(&sums[base + 1u]), atomicLoad(&sums[base + 2u]));
+    return vec3<f32>(fixed) / FIXED_POINT_SCALE;
+}
+
+// One thread per triangle. Adds the directions of U and V of the texture coordinates across the
+// triangle to the sums of its corners, triangles without usable texture coordinates add nothing.
+@compute @workgroup_size(64)
+fn accumulate_tangents(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(num_workgroups) workgroups: vec3<u32>,
+) {
+    let triangle = invocation_index(id, workgroups);
+    if triangle >= arrayLength(&indices) / 3u {
+        return;
+    }
+
+    let i0 = indices[triangle * 3u];
+    let i1 = indices[triangle * 3u + 1u];
+    let i2 = indices[triangle * 3u + 2u];
+    let p0 = vertex_position(i0);
+    let uv0 = vertex_tex_coords(i0);
+    let edge1 = vertex_position(i1) - p0;
+    let edge2 = vertex_position(i2) - p0;
+    let delta_uv1 = vertex_tex_coords(i1) - uv0;
+    let delta_uv2 = vertex_tex_coords(i2) - uv0;
+    let determinant = delta_uv1.x * delta_uv2.y - delta_uv2.x * delta_uv1.y;
+    // Both are divided by the determinant, only its sign is left once they are normalized
+    let tangent = edge1 * delta_uv2.y - edge2 * delta_uv1.y;
+    let bitangent = edge2 * delta_uv1.x - edge1 * delta_uv2.x;
+    if abs(determinant) <= 1e-12 || dot(tangent, tangent) <= 1e-24 || dot(bitangent, bitangent) <= 1e-24 {
+        return;
+    }
+    let flip = sign(determinant);
+    for (var corner = 0u; corner < 3u; corner++) {
+        let vertex = indices[triangle * 3u + corner];
+        add_to_sum(vertex, 0u, normalize(tangent) * flip);
+        add_to_sum(vertex, 3u, normalize(bitangent) * flip);
+    }
+}
+
+// One thread per vertex, after accumulate_tangents. The summed tangent is made perpendicular to
+// the normal of the vertex.
+@compute @workgroup_size(64)
+fn generate_frames(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(num_workgroups) workgroups: vec3<u32>,
+) {
+    let vertex = invocation_index(id, workgroups);
+    if vertex >= arrayLength(&frames) {
+        return;
+    }
+
+    let normal = normalize(vertex_normal(vertex));
+    let tangent = load_sum(vertex, 0u);
+    let bitangent = load_sum(vertex, 3u);
+    var frame: Frame;
+    frame.position = vec4<f32>(vertex_position(vertex), 1.0);
+    frame.normal = vec4<f32>(normal, 0.0);
+    // Gram-Schmidt
+    let projected = tangent - normal * dot(normal, tangent);
+    if dot(projected, projected) > 1e-12 {
+        let handedness = select(1.0, -1.0, dot(cross(normal, projected), bitangent) < 0.0);
+        frame.tangent = vec4<f32>(normalize(projected), handedness);
+    } else {
+        frame.tangent = vec4<f32>(any_tangent(normal), 1.0);
+    }
+    frames[vertex] = frame;
+}
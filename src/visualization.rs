@@ -0,0 +1,599 @@
+use cgmath::{InnerSpace, Vector3};
+use wgpu::util::DeviceExt;
+
+use crate::clipping;
+use crate::model::{self, Vertex};
+use crate::orbit_camera::OrbitCamera;
+use crate::post::HDR_FORMAT;
+use crate::texture;
+
+/// What the model is drawn as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VisualizationMode {
+    /// The regular lighting and materials.
+    Shaded = 0,
+    /// The world space normals, with every axis mapped from -1..1 to 0..1.
+    Normals = 1,
+    /// The distance from the camera along the view direction, white at the front of the
+    /// model and black at its back.
+    Depth = 2,
+    /// The world space position within the model's bounds, mapped to 0..1.
+    Position = 3,
+    /// A checkerboard over the texture coordinates, tinted by the coordinates themselves.
+    UvChecker = 4,
+    /// Blue for front faces and red for back faces, which are normally culled.
+    FaceOrientation = 5,
+    /// How many fragments are rasterized per pixel, from blue for one to red for eight or more.
+    Overdraw = 6,
+    /// A different random color for every mesh.
+    MeshColor = 7,
+}
+
+impl VisualizationMode {
+    /// The next mode, wrapping around to [VisualizationMode::Shaded].
+    pub fn next(self) -> Self {
+        match self {
+            Self::Shaded => Self::Normals,
+            Self::Normals => Self::Depth,
+            Self::Depth => Self::Position,
+            Self::Position => Self::UvChecker,
+            Self::UvChecker => Self::FaceOrientation,
+            Self::FaceOrientation => Self::Overdraw,
+            Self::Overdraw => Self::MeshColor,
+            Self::MeshColor => Self::Shaded,
+        }
+    }
+
+    /// The name shown in the HUD.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Shaded => "Shaded",
+            Self::Normals => "World normals",
+            Self::Depth => "Linear depth",
+            Self::Position => "World position",
+            Self::UvChecker => "UV checker",
+            Self::FaceOrientation => "Face orientation",
+            Self::Overdraw => "Overdraw",
+            Self::MeshColor => "Mesh colors",
+        }
+    }
+}
+
+impl std::str::FromStr for VisualizationMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "shaded" => Ok(Self::Shaded),
+            "normals" => Ok(Self::Normals),
+            "depth" => Ok(Self::Depth),
+            "position" => Ok(Self::Position),
+            "uv" => Ok(Self::UvChecker),
+            "faces" => Ok(Self::FaceOrientation),
+            "overdraw" => Ok(Self::Overdraw),
+            "meshes" => Ok(Self::MeshColor),
+            _ => anyhow::bail!(
+                "unknown view {:?}, expected shaded, normals, depth, position, uv, faces, overdraw or meshes",
+                s
+            ),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct VisualizationUniform {
+    bounds_min: [f32; 4],
+    bounds_max: [f32; 4],
+    view_direction: [f32; 4],
+    mode: u32,
+    // The depth range of the model's bounds along view_direction
+    near: f32,
+    far: f32,
+    _padding: u32,
+}
+
+// The number of meshes with a color of their own, the colors repeat after that
+const MESH_COLORS: u32 = 256;
+
+// One per mesh, each at its own dynamic offset. Instance indices would be simpler, but not every
+// backend supports a first instance other than zero.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct MeshUniform {
+    index: u32,
+    _padding: [u32; 3],
+}
+
+/// Draws the model in one of the debug [VisualizationMode]s instead of shading it.
+///
+/// Most modes are branches of one shader. Face orientation needs a pipeline without back face
+/// culling, and overdraw counts the fragments in a pass of its own before the main pass shows
+/// the counts as a heatmap.
+pub struct Visualization {
+    pub mode: VisualizationMode,
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    mesh_bind_group: wgpu::BindGroup,
+    // The distance between the MeshUniforms in their buffer
+    mesh_stride: u32,
+    // Also holds the overdraw counts, which can't be bound while they are being written
+    heatmap_bind_group_layout: wgpu::BindGroupLayout,
+    heatmap_bind_group: wgpu::BindGroup,
+    pipeline_layout: wgpu::PipelineLayout,
+    heatmap_pipeline_layout: wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    pipeline: wgpu::RenderPipeline,
+    face_orientation_pipeline: wgpu::RenderPipeline,
+    overdraw_pipeline: wgpu::RenderPipeline,
+    heatmap_pipeline: wgpu::RenderPipeline,
+    overdraw_texture: wgpu::TextureView,
+}
+
+impl Visualization {
+    /// Arguments:
+    ///
+    /// * `camera_bind_group_layout`: The layout of the bind group passed to [Visualization::draw].
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Visualization Buffer"),
+            contents: bytemuck::cast_slice(&[
+                <VisualizationUniform as bytemuck::Zeroable>::zeroed(),
+            ]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let uniform_entry = wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[uniform_entry],
+            label: Some("visualization_bind_group_layout"),
+        });
+        let heatmap_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    uniform_entry,
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        },
+                        count: None,
+                    },
+                ],
+                label: Some("heatmap_bind_group_layout"),
+            });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("visualization_bind_group"),
+        });
+        let mesh_stride = std::mem::size_of::<MeshUniform>()
+            .max(device.limits().min_uniform_buffer_offset_alignment as usize);
+        let mut meshes = vec![0; mesh_stride * MESH_COLORS as usize];
+        for index in 0..MESH_COLORS {
+            let uniform = MeshUniform {
+                index,
+                _padding: [0; 3],
+            };
+            let offset = index as usize * mesh_stride;
+            meshes[offset..offset + std::mem::size_of::<MeshUniform>()]
+                .copy_from_slice(bytemuck::bytes_of(&uniform));
+        }
+        let mesh_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Visualization Mesh Buffer"),
+            contents: &meshes,
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let mesh_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: wgpu::BufferSize::new(
+                            std::mem::size_of::<MeshUniform>() as u64
+                        ),
+                    },
+                    count: None,
+                }],
+                label: Some("visualization_mesh_bind_group_layout"),
+            });
+        let mesh_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &mesh_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &mesh_buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(std::mem::size_of::<MeshUniform>() as u64),
+                }),
+            }],
+            label: Some("visualization_mesh_bind_group"),
+        });
+        let overdraw_texture = create_overdraw_texture(device, width, height);
+        let heatmap_bind_group = create_heatmap_bind_group(
+            device,
+            &heatmap_bind_group_layout,
+            &buffer,
+            &overdraw_texture,
+        );
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Visualization Pipeline Layout"),
+            bind_group_layouts: &[
+                camera_bind_group_layout,
+                &bind_group_layout,
+                &mesh_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let heatmap_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Heatmap Pipeline Layout"),
+                bind_group_layouts: &[camera_bind_group_layout, &heatmap_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let shader = create_shader(device);
+        let (pipeline, face_orientation_pipeline, heatmap_pipeline) = create_pipelines(
+            device,
+            &shader,
+            &pipeline_layout,
+            &heatmap_pipeline_layout,
+            format,
+            sample_count,
+        );
+        let overdraw_pipeline = create_overdraw_pipeline(device, &shader, &pipeline_layout);
+
+        Self {
+            mode: VisualizationMode::Shaded,
+            buffer,
+            bind_group,
+            mesh_bind_group,
+            mesh_stride: mesh_stride as u32,
+            heatmap_bind_group_layout,
+            heatmap_bind_group,
+            pipeline_layout,
+            heatmap_pipeline_layout,
+            format,
+            pipeline,
+            face_orientation_pipeline,
+            overdraw_pipeline,
+            heatmap_pipeline,
+            overdraw_texture,
+        }
+    }
+
+    /// Recreates the pipelines for the MSAA sample count of the render pass.
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        (
+            self.pipeline,
+            self.face_orientation_pipeline,
+            self.heatmap_pipeline,
+        ) = create_pipelines(
+            device,
+            &create_shader(device),
+            &self.pipeline_layout,
+            &self.heatmap_pipeline_layout,
+            self.format,
+            sample_count,
+        );
+    }
+
+    /// Recreates the overdraw counts for a frame of `width` x `height` pixels.
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.overdraw_texture = create_overdraw_texture(device, width, height);
+        self.heatmap_bind_group = create_heatmap_bind_group(
+            device,
+            &self.heatmap_bind_group_layout,
+            &self.buffer,
+            &self.overdraw_texture,
+        );
+    }
+
+    /// Uploads the mode and the ranges the depth and position views are mapped from.
+    ///
+    /// Arguments:
+    ///
+    /// * `bounds`: The bounds of the model as (min, max), see [model::Model::bounds].
+    pub fn update(
+        &self,
+        queue: &wgpu::Queue,
+        camera: &OrbitCamera,
+        bounds: (Vector3<f32>, Vector3<f32>),
+    ) {
+        let (min, max) = bounds;
+        let view_direction = (camera.target - camera.eye).normalize();
+        let (mut near, mut far) = (f32::MAX, f32::MIN);
+        for corner in 0..8 {
+            let point = Vector3::new(
+                if corner & 1 == 0 { min.x } else { max.x },
+                if corner & 2 == 0 { min.y } else { max.y },
+                if corner & 4 == 0 { min.z } else { max.z },
+            );
+            let depth = (point - camera.eye).dot(view_direction);
+            near = near.min(depth);
+            far = far.max(depth);
+        }
+
+        let uniform = VisualizationUniform {
+            bounds_min: min.extend(1.0).into(),
+            bounds_max: max.extend(1.0).into(),
+            view_direction: view_direction.extend(0.0).into(),
+            mode: self.mode as u32,
+            near: near.max(0.0),
+            far,
+            _padding: 0,
+        };
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    /// Counts the fragments per pixel for [VisualizationMode::Overdraw]. Does nothing in the
+    /// other modes. Must be recorded before the pass that calls [Visualization::draw].
+    pub fn render_overdraw(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        model: &model::Model,
+        camera_bind_group: &wgpu::BindGroup,
+    ) {
+        if self.mode != VisualizationMode::Overdraw {
+            return;
+        }
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Overdraw Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.overdraw_texture,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        render_pass.set_pipeline(&self.overdraw_pipeline);
+        draw_meshes(&mut render_pass, model, camera_bind_group, self);
+    }
+
+    /// Draws the model in the current mode in place of the shaded model.
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        model: &'a model::Model,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        match self.mode {
+            VisualizationMode::Shaded => {}
+            VisualizationMode::Overdraw => {
+                render_pass.set_pipeline(&self.heatmap_pipeline);
+                render_pass.set_bind_group(0, camera_bind_group, &[]);
+                render_pass.set_bind_group(1, &self.heatmap_bind_group, &[]);
+                render_pass.draw(0..3, 0..1);
+            }
+            VisualizationMode::FaceOrientation => {
+                render_pass.set_pipeline(&self.face_orientation_pipeline);
+                draw_meshes(render_pass, model, camera_bind_group, self);
+            }
+            _ => {
+                render_pass.set_pipeline(&self.pipeline);
+                draw_meshes(render_pass, model, camera_bind_group, self);
+            }
+        }
+    }
+}
+
+/// Draws every mesh with its own [MeshUniform], so the shader can tell them apart.
+fn draw_meshes<'a>(
+    render_pass: &mut wgpu::RenderPass<'a>,
+    model: &'a model::Model,
+    camera_bind_group: &'a wgpu::BindGroup,
+    visualization: &'a Visualization,
+) {
+    render_pass.set_bind_group(0, camera_bind_group, &[]);
+    render_pass.set_bind_group(1, &visualization.bind_group, &[]);
+    for (index, mesh) in model.meshes.iter().enumerate() {
+        if mesh.culled {
+            continue;
+        }
+        let offset = (index as u32 % MESH_COLORS) * visualization.mesh_stride;
+        render_pass.set_bind_group(2, &visualization.mesh_bind_group, &[offset]);
+        render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.draw_indexed(0..mesh.num_elements, 0, 0..1);
+    }
+}
+
+fn create_shader(device: &wgpu::Device) -> wgpu::ShaderModule {
+    device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Visualization Shader"),
+        source: clipping::shader_source(include_str!("visualization.wgsl")),
+    })
+}
+
+// Float so the counts can be added up by blending, in the format the frame can already blend
+fn create_overdraw_texture(device: &wgpu::Device, width: u32, height: u32) -> wgpu::TextureView {
+    device
+        .create_texture(&wgpu::TextureDescriptor {
+            label: Some("overdraw_texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: HDR_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        })
+        .create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+fn create_heatmap_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    buffer: &wgpu::Buffer,
+    overdraw_texture: &wgpu::TextureView,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(overdraw_texture),
+            },
+        ],
+        label: Some("heatmap_bind_group"),
+    })
+}
+
+/// Creates the pipelines that draw into the main pass: the one for most modes, the one for face
+/// orientation and the heatmap.
+fn create_pipelines(
+    device: &wgpu::Device,
+    shader: &wgpu::ShaderModule,
+    layout: &wgpu::PipelineLayout,
+    heatmap_layout: &wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+) -> (
+    wgpu::RenderPipeline,
+    wgpu::RenderPipeline,
+    wgpu::RenderPipeline,
+) {
+    let multisample = wgpu::MultisampleState {
+        count: sample_count,
+        mask: !0,
+        alpha_to_coverage_enabled: false,
+    };
+    let depth_stencil = |depth_compare| wgpu::DepthStencilState {
+        format: texture::Texture::DEPTH_STENCIL_FORMAT,
+        depth_write_enabled: true,
+        depth_compare,
+        stencil: wgpu::StencilState::default(),
+        bias: wgpu::DepthBiasState::default(),
+    };
+    let targets = [Some(wgpu::ColorTargetState {
+        format,
+        blend: Some(wgpu::BlendState::REPLACE),
+        write_mask: wgpu::ColorWrites::ALL,
+    })];
+    let mesh_pipeline = |cull_mode| {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Visualization Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: "vs_main",
+                buffers: &[model::ModelVertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: "fs_main",
+                targets: &targets,
+            }),
+            primitive: wgpu::PrimitiveState {
+                cull_mode,
+                ..Default::default()
+            },
+            depth_stencil: Some(depth_stencil(wgpu::CompareFunction::Less)),
+            multisample,
+            multiview: None,
+        })
+    };
+
+    let pipeline = mesh_pipeline(Some(wgpu::Face::Back));
+    let face_orientation_pipeline = mesh_pipeline(None);
+    let heatmap_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Heatmap Pipeline"),
+        layout: Some(heatmap_layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_fullscreen",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "fs_heatmap",
+            targets: &targets,
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        // Covers the depth of every pixel with a count, so the background leaves them alone
+        depth_stencil: Some(depth_stencil(wgpu::CompareFunction::Always)),
+        multisample,
+        multiview: None,
+    });
+    (pipeline, face_orientation_pipeline, heatmap_pipeline)
+}
+
+/// Adds one per fragment, without a depth test and with the back faces culled like the shaded
+/// model.
+fn create_overdraw_pipeline(
+    device: &wgpu::Device,
+    shader: &wgpu::ShaderModule,
+    layout: &wgpu::PipelineLayout,
+) -> wgpu::RenderPipeline {
+    let additive = wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::One,
+        dst_factor: wgpu::BlendFactor::One,
+        operation: wgpu::BlendOperation::Add,
+    };
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Overdraw Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[model::ModelVertex::desc()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "fs_overdraw",
+            targets: &[Some(wgpu::ColorTargetState {
+                format: HDR_FORMAT,
+                blend: Some(wgpu::BlendState {
+                    color: additive,
+                    alpha: additive,
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            cull_mode: Some(wgpu::Face::Back),
+            ..Default::default()
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
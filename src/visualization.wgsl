@@ -0,0 +1,167 @@
+// The debug views of the model, see visualization.rs
+
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+// See clip.wgsl
+@group(0) @binding(5)
+var<uniform> clip_planes: ClipPlanes;
+
+// See visualization::VisualizationUniform
+struct Visualization {
+    bounds_min: vec4<f32>,
+    bounds_max: vec4<f32>,
+    // The direction the camera looks in, linear depth is measured along it
+    view_direction: vec4<f32>,
+    mode: u32,
+    // The depth range of the model's bounds
+    near: f32,
+    far: f32,
+}
+@group(1) @binding(0)
+var<uniform> visualization: Visualization;
+// The number of fragments per pixel, written by fs_overdraw
+@group(1) @binding(1)
+var overdraw_texture: texture_2d<f32>;
+
+// See visualization::MeshUniform, bound with a dynamic offset for every mesh
+struct Mesh {
+    index: u32,
+}
+@group(2) @binding(0)
+var<uniform> mesh: Mesh;
+
+// Values of Visualization.mode, see visualization::VisualizationMode
+const MODE_NORMALS: u32 = 1u;
+const MODE_DEPTH: u32 = 2u;
+const MODE_POSITION: u32 = 3u;
+const MODE_UV_CHECKER: u32 = 4u;
+const MODE_FACE_ORIENTATION: u32 = 5u;
+const MODE_MESH_COLOR: u32 = 7u;
+
+// The number of checker squares along each UV axis
+const CHECKER_SQUARES: f32 = 8.0;
+// Overdraw at or above this shows up as the hottest color
+const MAX_OVERDRAW: f32 = 8.0;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) tex_coords: vec2<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_normal: vec3<f32>,
+    @location(1) world_position: vec3<f32>,
+    @location(2) tex_coords: vec2<f32>,
+}
+
+@vertex
+fn vs_main(model: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.world_normal = model.normal;
+    out.world_position = model.position;
+    out.tex_coords = model.tex_coords;
+    out.clip_position = camera.view_proj * vec4<f32>(model.position, 1.0);
+    return out;
+}
+
+// A well spread color for every integer
+fn random_color(index: u32) -> vec3<f32> {
+    var h = index * 747796405u + 2891336453u;
+    h = ((h >> ((h >> 28u) + 4u)) ^ h) * 277803737u;
+    h = (h >> 22u) ^ h;
+    let color = vec3<f32>(f32(h & 255u), f32((h >> 8u) & 255u), f32((h >> 16u) & 255u)) / 255.0;
+    // Keep the colors away from black
+    return 0.2 + 0.8 * color;
+}
+
+fn checker(uv: vec2<f32>) -> vec3<f32> {
+    let square = vec2<i32>(floor(uv * CHECKER_SQUARES));
+    let dark = ((square.x + square.y) & 1) == 0;
+    // Tinted by the coordinates, so flipped or rotated UVs stand out
+    let tint = vec3<f32>(fract(uv), 1.0);
+    return select(tint, tint * 0.25, dark);
+}
+
+@fragment
+fn fs_main(in: VertexOutput, @builtin(front_facing) front_facing: bool) -> @location(0) vec4<f32> {
+    if clipped(clip_planes, in.world_position) {
+        discard;
+    }
+    var color: vec3<f32>;
+    switch visualization.mode {
+        case MODE_NORMALS: {
+            color = normalize(in.world_normal) * 0.5 + 0.5;
+        }
+        case MODE_DEPTH: {
+            let depth = dot(in.world_position - camera.view_pos.xyz, visualization.view_direction.xyz);
+            let t = clamp((depth - visualization.near) / max(visualization.far - visualization.near, 1e-6), 0.0, 1.0);
+            // Near is white, far is black
+            color = vec3<f32>(1.0 - t);
+        }
+        case MODE_POSITION: {
+            let size = max(visualization.bounds_max.xyz - visualization.bounds_min.xyz, vec3<f32>(1e-6));
+            color = clamp((in.world_position - visualization.bounds_min.xyz) / size, vec3<f32>(0.0), vec3<f32>(1.0));
+        }
+        case MODE_UV_CHECKER: {
+            color = checker(in.tex_coords);
+        }
+        case MODE_FACE_ORIENTATION: {
+            color = select(vec3<f32>(1.0, 0.1, 0.1), vec3<f32>(0.1, 0.3, 1.0), front_facing);
+        }
+        case MODE_MESH_COLOR: {
+            color = random_color(mesh.index);
+        }
+        default: {
+            color = vec3<f32>(1.0, 0.0, 1.0);
+        }
+    }
+    return vec4<f32>(color, 1.0);
+}
+
+// Adds one for every fragment, the depth test is off
+@fragment
+fn fs_overdraw(in: VertexOutput) -> @location(0) vec4<f32> {
+    if clipped(clip_planes, in.world_position) {
+        discard;
+    }
+    return vec4<f32>(1.0, 0.0, 0.0, 0.0);
+}
+
+struct FullscreenOutput {
+    @builtin(position) clip_position: vec4<f32>,
+}
+
+// One triangle in front of everything, so the background skips the pixels it covers
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) index: u32) -> FullscreenOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: FullscreenOutput;
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    return out;
+}
+
+// From blue for a single fragment over green and yellow to red for MAX_OVERDRAW
+@fragment
+fn fs_heatmap(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let count = textureLoad(overdraw_texture, vec2<i32>(in.clip_position.xy), 0).r;
+    if count < 0.5 {
+        discard;
+    }
+    let t = clamp((count - 1.0) / (MAX_OVERDRAW - 1.0), 0.0, 1.0) * 3.0;
+    var color: vec3<f32>;
+    if t < 1.0 {
+        color = mix(vec3<f32>(0.0, 0.0, 1.0), vec3<f32>(0.0, 1.0, 0.0), t);
+    } else if t < 2.0 {
+        color = mix(vec3<f32>(0.0, 1.0, 0.0), vec3<f32>(1.0, 1.0, 0.0), t - 1.0);
+    } else {
+        color = mix(vec3<f32>(1.0, 1.0, 0.0), vec3<f32>(1.0, 0.0, 0.0), t - 2.0);
+    }
+    return vec4<f32>(color, 1.0);
+}
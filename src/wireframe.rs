@@ -0,0 +1,400 @@
+use wgpu::util::DeviceExt;
+
+use crate::clipping;
+use crate::model::{self, Vertex, WireframeVertex};
+use crate::texture;
+
+/// How the [Wireframe] is combined with the model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WireframeMode {
+    Off,
+    /// The edges are drawn on top of the shaded model.
+    Overlay,
+    /// The model is filled with [WireframeSettings::fill_color], so only the edges facing the
+    /// camera and not covered by other faces are visible.
+    HiddenLine,
+    /// Only the edges are drawn, including the ones at the back.
+    SeeThrough,
+}
+
+impl WireframeMode {
+    /// The next mode, wrapping around to [WireframeMode::Off].
+    pub fn next(self) -> Self {
+        match self {
+            Self::Off => Self::Overlay,
+            Self::Overlay => Self::HiddenLine,
+            Self::HiddenLine => Self::SeeThrough,
+            Self::SeeThrough => Self::Off,
+        }
+    }
+
+    /// Whether the shaded model is drawn in this mode.
+    pub fn shades_model(self) -> bool {
+        matches!(self, Self::Off | Self::Overlay)
+    }
+}
+
+impl std::str::FromStr for WireframeMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "off" => Ok(Self::Off),
+            "overlay" => Ok(Self::Overlay),
+            "hidden-line" => Ok(Self::HiddenLine),
+            "see-through" => Ok(Self::SeeThrough),
+            _ => anyhow::bail!(
+                "unknown wireframe mode {:?}, expected off, overlay, hidden-line or see-through",
+                s
+            ),
+        }
+    }
+}
+
+/// Controls what the [Wireframe] looks like. Colors are linear RGB.
+#[derive(Debug, Clone, Copy)]
+pub struct WireframeSettings {
+    pub mode: WireframeMode,
+    /// The width of the lines in pixels, independent of the distance to the camera.
+    pub width: f32,
+    pub color: [f32; 3],
+    /// The color of the faces in [WireframeMode::HiddenLine].
+    pub fill_color: [f32; 3],
+}
+
+impl Default for WireframeSettings {
+    fn default() -> Self {
+        Self {
+            mode: WireframeMode::Off,
+            width: 1.0,
+            color: [0.01, 0.01, 0.01],
+            fill_color: [0.8, 0.8, 0.8],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct WireframeUniform {
+    color: [f32; 4],
+    fill_color: [f32; 4],
+    width: f32,
+    _padding: [f32; 3],
+}
+
+/// Draws the edges of the model's polygons.
+///
+/// The lines are not rasterized as lines, which would need `Features::POLYGON_MODE_LINE`.
+/// Every triangle is drawn filled with the barycentric coordinates of its corners
+/// (see [model::WireframeVertex]) and the fragment shader only colors the pixels close to an
+/// edge. Edges that the triangulation added inside a polygon are left out.
+pub struct Wireframe {
+    pub settings: WireframeSettings,
+    // Per mesh of the model, only built while the wireframe is shown
+    meshes: Vec<MeshTriangles>,
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pipeline_layout: wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    // Draws the edges over the shaded model
+    overlay_pipeline: wgpu::RenderPipeline,
+    // Draws the filled faces with their edges instead of the shaded model
+    hidden_line_pipeline: wgpu::RenderPipeline,
+    // Draws all edges without a depth test
+    see_through_pipeline: wgpu::RenderPipeline,
+}
+
+impl Wireframe {
+    /// Arguments:
+    ///
+    /// * `camera_bind_group_layout`: The layout of the bind group passed to [Wireframe::draw].
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let settings = WireframeSettings::default();
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Wireframe Buffer"),
+            contents: bytemuck::cast_slice(&[WireframeUniform::new(&settings)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("wireframe_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("wireframe_bind_group"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Wireframe Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let (overlay_pipeline, hidden_line_pipeline, see_through_pipeline) =
+            create_pipelines(device, &pipeline_layout, format, sample_count);
+
+        Self {
+            settings,
+            meshes: Vec::new(),
+            buffer,
+            bind_group,
+            pipeline_layout,
+            format,
+            overlay_pipeline,
+            hidden_line_pipeline,
+            see_through_pipeline,
+        }
+    }
+
+    /// Recreates the pipelines for the MSAA sample count of the render pass.
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        (
+            self.overlay_pipeline,
+            self.hidden_line_pipeline,
+            self.see_through_pipeline,
+        ) = create_pipelines(device, &self.pipeline_layout, self.format, sample_count);
+    }
+
+    /// Builds the unindexed triangles of `model` at the subdivision `level` if the wireframe is
+    /// shown and they are missing, and drops them while it is off.
+    pub fn prepare(&mut self, device: &wgpu::Device, model: &model::Model, level: u32) {
+        if self.settings.mode == WireframeMode::Off {
+            self.meshes.clear();
+        } else if self.meshes.is_empty() {
+            self.meshes = model
+                .meshes
+                .iter()
+                .map(|mesh| MeshTriangles::new(device, mesh, level))
+                .collect();
+        }
+    }
+
+    /// Drops the triangles. Must be called whenever the buffers of the model are rebuilt.
+    pub fn invalidate(&mut self) {
+        self.meshes.clear();
+    }
+
+    /// Uploads the settings.
+    pub fn update(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.buffer,
+            0,
+            bytemuck::cast_slice(&[WireframeUniform::new(&self.settings)]),
+        );
+    }
+
+    /// Draws the faces of [WireframeMode::HiddenLine] in place of the shaded model. Does
+    /// nothing in the other modes.
+    pub fn draw_surfaces<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        model: &'a model::Model,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        if self.settings.mode == WireframeMode::HiddenLine {
+            self.draw_with(
+                render_pass,
+                &self.hidden_line_pipeline,
+                model,
+                camera_bind_group,
+            );
+        }
+    }
+
+    /// Draws the edges of [WireframeMode::Overlay] and [WireframeMode::SeeThrough]. Must come
+    /// after everything else in the pass, the lines are blended over it.
+    pub fn draw_lines<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        model: &'a model::Model,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        let pipeline = match self.settings.mode {
+            WireframeMode::Overlay => &self.overlay_pipeline,
+            WireframeMode::SeeThrough => &self.see_through_pipeline,
+            WireframeMode::Off | WireframeMode::HiddenLine => return,
+        };
+        self.draw_with(render_pass, pipeline, model, camera_bind_group);
+    }
+
+    fn draw_with<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        pipeline: &'a wgpu::RenderPipeline,
+        model: &'a model::Model,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        for (mesh, triangles) in model.meshes.iter().zip(&self.meshes) {
+            if !mesh.culled {
+                render_pass.set_vertex_buffer(0, triangles.buffer.slice(..));
+                render_pass.draw(0..triangles.vertex_count, 0..1);
+            }
+        }
+    }
+}
+
+/// The [WireframeVertex] triangles of a mesh.
+struct MeshTriangles {
+    buffer: wgpu::Buffer,
+    vertex_count: u32,
+}
+
+impl MeshTriangles {
+    fn new(device: &wgpu::Device, mesh: &model::Mesh, level: u32) -> Self {
+        let (vertices, indices, edges) = mesh.triangles(level);
+        let wireframe_vertices = indices
+            .chunks_exact(3)
+            .zip(edges)
+            .flat_map(|(triangle, drawn)| {
+                let vertices = &vertices;
+                (0..3).map(move |corner| {
+                    let mut barycentric = [0.0; 3];
+                    barycentric[corner] = 1.0;
+                    // An offset coordinate never gets close to zero, so its edge isn't drawn
+                    for (coordinate, &drawn) in barycentric.iter_mut().zip(&drawn) {
+                        if !drawn {
+                            *coordinate += 1.0;
+                        }
+                    }
+                    WireframeVertex {
+                        position: vertices[triangle[corner] as usize].position,
+                        barycentric,
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Wireframe Buffer", mesh.name)),
+            contents: bytemuck::cast_slice(&wireframe_vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        Self {
+            buffer,
+            vertex_count: wireframe_vertices.len() as u32,
+        }
+    }
+}
+
+impl WireframeUniform {
+    fn new(settings: &WireframeSettings) -> Self {
+        let [r, g, b] = settings.color;
+        let [fill_r, fill_g, fill_b] = settings.fill_color;
+        Self {
+            color: [r, g, b, 1.0],
+            fill_color: [fill_r, fill_g, fill_b, 1.0],
+            width: settings.width,
+            _padding: [0.0; 3],
+        }
+    }
+}
+
+/// Creates the overlay, hidden line and see-through pipelines.
+fn create_pipelines(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+) -> (
+    wgpu::RenderPipeline,
+    wgpu::RenderPipeline,
+    wgpu::RenderPipeline,
+) {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Wireframe Shader"),
+        source: clipping::shader_source(include_str!("wireframe.wgsl")),
+    });
+    let pipeline = |entry_point, blend, cull_mode, depth_stencil| {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Wireframe Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[model::WireframeVertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point,
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(blend),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                cull_mode,
+                ..Default::default()
+            },
+            depth_stencil: Some(depth_stencil),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        })
+    };
+
+    let overlay = pipeline(
+        "fs_lines",
+        wgpu::BlendState::ALPHA_BLENDING,
+        Some(wgpu::Face::Back),
+        wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_STENCIL_FORMAT,
+            depth_write_enabled: false,
+            // The surface was drawn at the same depth, pulled forward a little against z-fighting
+            depth_compare: wgpu::CompareFunction::LessEqual,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState {
+                constant: -2,
+                slope_scale: -1.0,
+                clamp: 0.0,
+            },
+        },
+    );
+    let hidden_line = pipeline(
+        "fs_hidden_line",
+        wgpu::BlendState::REPLACE,
+        Some(wgpu::Face::Back),
+        wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_STENCIL_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        },
+    );
+    let see_through = pipeline(
+        "fs_lines",
+        wgpu::BlendState::ALPHA_BLENDING,
+        None,
+        wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_STENCIL_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::Always,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        },
+    );
+    (overlay, hidden_line, see_through)
+}
@@ -0,0 +1,73 @@
+// Wireframe from barycentric coordinates, see wireframe.rs
+
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+// See clip.wgsl
+@group(0) @binding(5)
+var<uniform> clip_planes: ClipPlanes;
+
+// See wireframe::WireframeUniform
+struct Wireframe {
+    color: vec4<f32>,
+    fill_color: vec4<f32>,
+    // In pixels
+    width: f32,
+}
+@group(1) @binding(0)
+var<uniform> wireframe: Wireframe;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    // One at its own corner and zero at the others. Components of edges that are not drawn are
+    // offset by one, so they never get close to zero.
+    @location(1) barycentric: vec3<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) barycentric: vec3<f32>,
+    @location(1) world_position: vec3<f32>,
+}
+
+@vertex
+fn vs_main(model: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    // The same transform as shader.wgsl, so the overlay lands on the depth of the shaded surface
+    out.clip_position = camera.view_proj * vec4<f32>(model.position, 1.0);
+    out.barycentric = model.barycentric;
+    out.world_position = model.position;
+    return out;
+}
+
+// How much of the pixel is covered by a line of wireframe.width pixels along the nearest edge
+fn edge_coverage(barycentric: vec3<f32>) -> f32 {
+    // The barycentric coordinates change by fwidth per pixel, so this is the distance in pixels
+    let distances = barycentric / max(fwidth(barycentric), vec3<f32>(1e-6));
+    let distance = min(distances.x, min(distances.y, distances.z));
+    return clamp(wireframe.width * 0.5 + 0.5 - distance, 0.0, 1.0);
+}
+
+// Lines only, blended over whatever was drawn before
+@fragment
+fn fs_lines(in: VertexOutput) -> @location(0) vec4<f32> {
+    let coverage = edge_coverage(in.barycentric);
+    if coverage <= 0.0 || clipped(clip_planes, in.world_position) {
+        discard;
+    }
+    return vec4<f32>(wireframe.color.rgb, coverage);
+}
+
+// Opaque surfaces in the fill color, so they hide the lines behind them
+@fragment
+fn fs_hidden_line(in: VertexOutput) -> @location(0) vec4<f32> {
+    let coverage = edge_coverage(in.barycentric);
+    if clipped(clip_planes, in.world_position) {
+        discard;
+    }
+    return vec4<f32>(mix(wireframe.fill_color.rgb, wireframe.color.rgb, coverage), 1.0);
+}